@@ -1,92 +1,288 @@
-use unionize_testbench::{
-    experiments,
-    scenarios::dynamic::{SimObject, Simulator, Trace, TraceEntryRecord},
+use std::path::Path;
+
+use clap::Parser;
+
+use unionize_testbench::experiments::{
+    scenario::Scenario,
+    sweep::{presets, run_jobs, Job, Output, OutputFormat, Split, Suite},
+    TriggerConf,
+};
+use unionize_testbench::scenarios::dynamic::{
+    ConvergenceCheck, Link, Network, Retention, SimDuration, SyncFailurePolicy,
 };
+use unionize_testbench::scenarios::protocol::Faults;
 
-fn main() -> std::io::Result<()> {
-    std::fs::create_dir_all("out")?;
-
-    let (tx, rx) = std::sync::mpsc::channel();
-    let handles: Vec<_> =
-        (0..4)
-            .into_iter()
-            .map(|i| {
-                let tx = tx.clone();
-                std::thread::spawn(move || -> std::io::Result<()> {
-                    let seed = [0u8; 32];
-                    match i {
-                        0 => {
-                            let trace =
-                                experiments::timestamped::timestamped_experiment::<3, 4>(seed);
-                            write_trace_to_file::<experiments::timestamped::TimestampSim>(
-                                "out/timestamped_3_4.csv",
-                                trace,
-                            )?;
-                        }
-                        1 => {
-                            let trace =
-                                experiments::timestamped::timestamped_experiment_dynamic_split::<4>(
-                                    seed,
-                                );
-                            write_trace_to_file::<experiments::timestamped::TimestampSim>(
-                                "out/timestamped_dyn_4.csv",
-                                trace,
-                            )?;
-                        }
-                        2 => {
-                            let trace = experiments::uniform::uniform_experiment::<3, 4>(seed);
-                            write_trace_to_file::<experiments::uniform::UniformSim>(
-                                "out/uniform_3_4.csv",
-                                trace,
-                            )?;
-                        }
-                        3 => {
-                            let trace = experiments::uniform::uniform_experiment::<2, 2>(seed);
-                            write_trace_to_file::<experiments::uniform::UniformSim>(
-                                "out/uniform_2_2.csv",
-                                trace,
-                            )?;
-                        }
-                        _ => unreachable!(),
-                    }
-                    tx.send(i).unwrap();
-                    Ok(())
-                })
-            })
-            .collect();
-
-    let mut running = 4;
-    while running > 0 {
-        match rx.recv() {
-            Ok(0) => println!("timestamped_3_4 done"),
-            Ok(1) => println!("timestamped_dyn_4 done"),
-            Ok(2) => println!("uniform_3_4 done"),
-            Ok(3) => println!("uniform_2_2 done"),
-            x => unreachable!("{x:?}"),
-        }
-        running -= 1;
+/// Runs the sync experiments and writes one trace per run, plus an index, to the output
+/// directory. Without options, runs all presets with their default parameters.
+#[derive(Parser, Debug)]
+#[command(name = "run-experiments")]
+struct Args {
+    /// Print the available suites, experiments and scenarios and exit.
+    #[arg(long)]
+    list: bool,
+
+    /// Only run experiments of these suites.
+    #[arg(long, value_delimiter = ',')]
+    suite: Vec<Suite>,
+
+    /// Only run these experiments (see --list).
+    #[arg(long, value_delimiter = ',')]
+    experiment: Vec<String>,
+
+    /// Seeds to run every experiment with.
+    #[arg(long, value_delimiter = ',', default_value = "0")]
+    seed: Vec<u64>,
+
+    /// Override the split factor; a number or "dyn".
+    #[arg(long, value_delimiter = ',')]
+    split: Vec<Split>,
+
+    /// Override the item set threshold.
+    #[arg(long, value_delimiter = ',')]
+    threshold: Vec<usize>,
+
+    /// Override the number of parties.
+    #[arg(long)]
+    parties: Option<usize>,
+
+    /// Override the simulated duration, e.g. 90min, 12h, 3d, 2w, 18mo or 1y.
+    #[arg(long)]
+    duration: Option<SimDuration>,
+
+    /// Override the scenario; a name (see --list) or the path of a scenario file.
+    #[arg(long)]
+    scenario: Option<String>,
+
+    /// Directory the traces are written to.
+    #[arg(long, default_value = "out")]
+    out: String,
+
+    #[arg(long, default_value = "csv")]
+    format: OutputFormat,
+
+    /// Check that both parties hold the same objects after every sync. With "record",
+    /// divergences are added to the trace, with "panic" they fail the experiment.
+    #[arg(long)]
+    check_convergence: Option<ConvergenceCheck>,
+
+    /// What to do when a sync fails: continue, abort, or retry:<duration>[:<attempts>].
+    #[arg(long)]
+    on_sync_failure: Option<SyncFailurePolicy>,
+
+    /// Fail syncs that take more than this many messages.
+    #[arg(long)]
+    max_rounds: Option<usize>,
+
+    /// Don't fail syncs in which a party repeats fingerprints it sent before.
+    #[arg(long)]
+    no_cycle_detection: bool,
+
+    /// Write the rounds of every sync to <label>_transcripts next to the trace.
+    #[arg(long)]
+    transcripts: bool,
+
+    /// Add the whole event in Debug form to every trace row.
+    #[arg(long)]
+    event_debug: bool,
+
+    /// Make parties drop old objects: age:<duration> or items:<n>, e.g. age:30d.
+    #[arg(long)]
+    retention: Option<Retention>,
+
+    /// How often parties drop old objects, if --retention is given.
+    #[arg(long)]
+    gc_every: Option<SimDuration>,
+
+    /// Make syncs take time: every message takes half this many milliseconds.
+    #[arg(long)]
+    rtt_ms: Option<u64>,
+
+    /// Make syncs take time: the bytes of a sync are sent at this many bytes per second.
+    #[arg(long)]
+    bandwidth: Option<u64>,
+
+    /// Make messages get lost, e.g. drop=0.01,duplicate=0.01,truncate=0.01,reorder=0.1 or
+    /// kill-after=<messages>. Add seed=<n> to pick other faults for the same runs.
+    #[arg(long)]
+    faults: Option<Faults>,
+
+    /// Keep the objects that arrived before a sync failed.
+    #[arg(long)]
+    keep_partial_syncs: bool,
+
+    /// Number of experiments run in parallel. Defaults to the number of CPUs.
+    #[arg(long)]
+    workers: Option<usize>,
+}
+
+fn list() {
+    println!("suites:");
+    for suite in Suite::ALL {
+        println!("  {}", suite.name());
+    }
+    println!("experiments:");
+    for (name, _, _) in presets() {
+        println!("  {name}");
+    }
+    println!("scenarios:");
+    for (name, description) in TriggerConf::NAMED {
+        println!("  {name:<10} {description}");
     }
+}
 
-    for (i, handle) in handles.into_iter().enumerate() {
-        if let Err(e) = handle.join() {
-            println!("error in thread {i}: {e:?}")
+fn jobs(args: &Args) -> Result<Vec<Job>, String> {
+    let presets = presets();
+    for name in &args.experiment {
+        if !presets.iter().any(|(preset, _, _)| preset == name) {
+            return Err(format!("unknown experiment {name:?}, see --list"));
         }
     }
 
-    Ok(())
+    let triggers = match &args.scenario {
+        Some(name) => match TriggerConf::by_name(name) {
+            Some(triggers) => Some(triggers),
+            None if Path::new(name).is_file() => {
+                let scenario = Scenario::load(name).map_err(|e| format!("{name}: {e}"))?;
+                Some(TriggerConf::Custom(scenario.triggers()))
+            }
+            None => return Err(format!("unknown scenario {name:?}, see --list")),
+        },
+        None => None,
+    };
+
+    let mut jobs: Vec<Job> = vec![];
+    for (name, suite, mut config) in presets {
+        if !args.experiment.is_empty() && !args.experiment.iter().any(|e| e == name) {
+            continue;
+        }
+        if !args.suite.is_empty() && !args.suite.contains(&suite) {
+            continue;
+        }
+
+        if let Some(n_parties) = args.parties {
+            config.n_parties = n_parties;
+        }
+        if let Some(length) = args.duration {
+            config.length = length;
+        }
+        if let Some(triggers) = &triggers {
+            config.triggers = triggers.clone();
+        }
+        if let Some(check) = args.check_convergence {
+            config.options.check_convergence = check;
+        }
+        if let Some(policy) = args.on_sync_failure {
+            config.options.on_sync_failure = policy;
+        }
+        if args.max_rounds.is_some() {
+            config.max_rounds = args.max_rounds;
+        }
+        if args.no_cycle_detection {
+            config.detect_cycles = false;
+        }
+        if args.transcripts {
+            config.options.record_transcripts = true;
+        }
+        if args.event_debug {
+            config.options.record_event_debug = true;
+        }
+        if args.retention.is_some() {
+            config.retention = args.retention;
+        }
+        if let Some(every) = args.gc_every {
+            config.gc_every = every;
+        }
+        if args.faults.is_some() {
+            config.faults = args.faults;
+        }
+        if args.keep_partial_syncs {
+            config.options.keep_partial_syncs = true;
+        }
+        if args.rtt_ms.is_some() || args.bandwidth.is_some() {
+            let rtt = std::time::Duration::from_millis(args.rtt_ms.unwrap_or(0));
+            config.options.network = Some(Network::new(Link::new(rtt, args.bandwidth)));
+        }
+
+        let splits = if args.split.is_empty() {
+            vec![None]
+        } else {
+            args.split.iter().copied().map(Some).collect()
+        };
+        let thresholds = if args.threshold.is_empty() {
+            vec![config.threshold]
+        } else {
+            args.threshold.clone()
+        };
+
+        for split in &splits {
+            for threshold in &thresholds {
+                for seed in &args.seed {
+                    let mut job = Job::new(suite, *seed, config.clone());
+                    if let Some(split) = split {
+                        job.set_split(*split);
+                    }
+                    job.config.threshold = *threshold;
+
+                    // overriding the parameters can turn different presets into the same job
+                    if !jobs.iter().any(|other| other.label() == job.label()) {
+                        jobs.push(job);
+                    }
+                }
+            }
+        }
+    }
+
+    if jobs.is_empty() {
+        return Err("no experiments selected".to_string());
+    }
+
+    for job in &jobs {
+        job.validate()
+            .map_err(|e| format!("invalid experiment {}: {e}", job.label()))?;
+    }
+
+    Ok(jobs)
 }
 
-fn write_trace_to_file<S>(path: &str, trace: Trace<S::Item, SimObject>) -> std::io::Result<()>
-where
-    S: Simulator,
-    SimObject: unionize::Object<S::Item>,
-{
-    let f = std::fs::File::create(path)?;
-    let mut wtr = csv::WriterBuilder::new().flexible(true).from_writer(f);
-
-    for (meta, entry) in trace.entries() {
-        let rec: TraceEntryRecord<S> = entry.clone().into();
-        wtr.serialize((meta, rec)).unwrap();
+fn main() -> std::io::Result<()> {
+    let args = Args::parse();
+    if args.list {
+        list();
+        return Ok(());
+    }
+
+    let jobs = match jobs(&args) {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            eprintln!("error: {e}");
+            std::process::exit(2);
+        }
+    };
+
+    let workers = args
+        .workers
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+    let output = Output::new(&args.out, args.format);
+    let reports = run_jobs(jobs, &output, workers, |progress| {
+        let report = progress.report;
+        match (&report.error, report.aborted_at) {
+            (None, Some(aborted_at)) => println!(
+                "[{}/{}] {} aborted at minute {aborted_at} after {:.1}s",
+                progress.done, progress.total, report.label, report.seconds
+            ),
+            (None, None) => println!(
+                "[{}/{}] {} done after {:.1}s",
+                progress.done, progress.total, report.label, report.seconds
+            ),
+            (Some(e), _) => println!(
+                "[{}/{}] {} failed: {e}",
+                progress.done, progress.total, report.label
+            ),
+        }
+    })?;
+
+    if reports.iter().any(|report| !report.is_ok()) {
+        std::process::exit(1);
     }
 
     Ok(())
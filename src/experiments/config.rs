@@ -0,0 +1,310 @@
+use std::collections::BTreeMap;
+
+use crate::scenarios::dynamic::{Event, Retention, SimDuration, SimInstant, SimOptions, Triggers};
+use crate::scenarios::protocol::Faults;
+
+/// Everything that distinguishes one experiment run from another, so parameters can be chosen
+/// at runtime instead of being baked into const generics.
+#[derive(Debug, Clone)]
+pub struct ExperimentConfig {
+    /// Into how many parts a range is split if its fingerprints don't match.
+    pub split: usize,
+    /// Ranges with fewer items than this are sent as item sets.
+    pub threshold: usize,
+    /// Keep splitting in halves until the rest is below the threshold, instead of splitting
+    /// into a fixed number of parts. Only supported by the timestamped suite; `split` is ignored.
+    pub dynamic_split: bool,
+    /// Fail a sync after this many messages.
+    pub max_rounds: Option<usize>,
+    /// Fail a sync when a party repeats fingerprints it sent before.
+    pub detect_cycles: bool,
+    /// Send the messages of every sync over a flaky channel.
+    pub faults: Option<Faults>,
+    pub n_parties: usize,
+    pub length: SimDuration,
+    pub seed: [u8; 32],
+    pub triggers: TriggerConf,
+    /// Makes every party collect its garbage every `gc_every`, keeping what the retention says.
+    pub retention: Option<Retention>,
+    pub gc_every: SimDuration,
+    pub options: SimOptions,
+}
+
+impl ExperimentConfig {
+    /// The setup we used so far: ten parties talking for 18 months, seeded with zeroes.
+    pub fn new(split: usize, threshold: usize) -> Self {
+        ExperimentConfig {
+            split,
+            threshold,
+            dynamic_split: false,
+            max_rounds: None,
+            detect_cycles: true,
+            faults: None,
+            n_parties: 10,
+            length: 18 * SimDuration::MONTH,
+            seed: [0u8; 32],
+            triggers: TriggerConf::Conf10,
+            retention: None,
+            gc_every: SimDuration::DAY,
+            options: SimOptions::default(),
+        }
+    }
+
+    pub fn dynamic_split(threshold: usize) -> Self {
+        ExperimentConfig {
+            dynamic_split: true,
+            ..Self::new(2, threshold)
+        }
+    }
+
+    /// Checks the parts of the config that don't depend on the suite.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.threshold == 0 {
+            return Err(ConfigError::UnsupportedThreshold(self.threshold));
+        }
+        if self.max_rounds == Some(0) {
+            return Err(ConfigError::ZeroMaxRounds);
+        }
+        if let Some(faults) = &self.faults {
+            faults.check().map_err(ConfigError::InvalidFaults)?;
+        }
+        if self.length == SimDuration::zero() {
+            return Err(ConfigError::ZeroLength);
+        }
+        if self.retention.is_some() && self.gc_every == SimDuration::zero() {
+            return Err(ConfigError::ZeroGcInterval);
+        }
+
+        let required = self.triggers.min_parties();
+        if self.n_parties < required {
+            return Err(ConfigError::TooFewParties {
+                required,
+                configured: self.n_parties,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// The triggers of the scenario, plus the garbage collection of every party.
+    pub fn initial_triggers(&self) -> Triggers {
+        let mut triggers = self.triggers.triggers();
+        if let Some(retention) = self.retention {
+            let gc = (0..self.n_parties)
+                .map(|party_id| (party_id, Event::repeat(self.gc_every, Event::Gc(retention))))
+                .collect();
+            let scheduled = BTreeMap::from_iter([(SimInstant::zero() + self.gc_every, gc)]);
+            triggers.append(&mut Triggers::new(scheduled, vec![]));
+        }
+        triggers
+    }
+}
+
+/// Which triggers drive the experiment.
+#[derive(Debug, Clone)]
+pub enum TriggerConf {
+    /// Two groups of four clients that are awake ten hours a day, each group syncing with its
+    /// own server. The two servers (parties 8 and 9) sync with each other about once a day.
+    Conf10,
+    Custom(Triggers),
+}
+
+impl TriggerConf {
+    /// The scenarios that can be selected by name, with a short description.
+    pub const NAMED: [(&'static str, &'static str); 1] = [(
+        "conf10",
+        "8 clients awake 10h a day, posting hourly and syncing every 3h with one of 2 servers",
+    )];
+
+    pub fn by_name(name: &str) -> Option<Self> {
+        match name {
+            "conf10" => Some(TriggerConf::Conf10),
+            _ => None,
+        }
+    }
+
+    pub fn triggers(&self) -> Triggers {
+        match self {
+            TriggerConf::Conf10 => super::trigger_conf_10(),
+            TriggerConf::Custom(triggers) => triggers.clone(),
+        }
+    }
+
+    /// The number of parties the triggers refer to.
+    pub fn min_parties(&self) -> usize {
+        match self {
+            TriggerConf::Conf10 => 10,
+            TriggerConf::Custom(triggers) => triggers.max_party_id().map_or(0, |id| id + 1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    UnsupportedSplit(usize),
+    UnsupportedThreshold(usize),
+    DynamicSplitUnsupported,
+    TooFewParties { required: usize, configured: usize },
+    ZeroLength,
+    ZeroMaxRounds,
+    ZeroGcInterval,
+    InvalidFaults(String),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::UnsupportedSplit(split) => write!(f, "unsupported split factor {split}"),
+            ConfigError::UnsupportedThreshold(thresh) => {
+                write!(f, "unsupported threshold {thresh}")
+            }
+            ConfigError::DynamicSplitUnsupported => {
+                write!(f, "this suite does not support dynamic splitting")
+            }
+            ConfigError::TooFewParties {
+                required,
+                configured,
+            } => write!(
+                f,
+                "triggers need {required} parties, but only {configured} are configured"
+            ),
+            ConfigError::ZeroLength => write!(f, "experiment length must not be zero"),
+            ConfigError::ZeroMaxRounds => write!(f, "round limit must not be zero"),
+            ConfigError::ZeroGcInterval => {
+                write!(f, "garbage collection interval must not be zero")
+            }
+            ConfigError::InvalidFaults(e) => write!(f, "invalid faults: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Why an experiment that streams its trace did not finish.
+#[derive(Debug)]
+pub enum ExperimentError {
+    Config(ConfigError),
+    /// Writing the trace failed.
+    Io(std::io::Error),
+}
+
+impl From<ConfigError> for ExperimentError {
+    fn from(value: ConfigError) -> Self {
+        ExperimentError::Config(value)
+    }
+}
+
+impl From<std::io::Error> for ExperimentError {
+    fn from(value: std::io::Error) -> Self {
+        ExperimentError::Io(value)
+    }
+}
+
+impl std::fmt::Display for ExperimentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExperimentError::Config(e) => write!(f, "invalid config: {e}"),
+            ExperimentError::Io(e) => write!(f, "i/o error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ExperimentError {}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConfigError, ExperimentConfig, TriggerConf};
+    use crate::experiments::{timestamped, uniform};
+    use crate::scenarios::dynamic::{Retention, SimDuration, TraceEntry};
+    use crate::scenarios::protocol::Faults;
+
+    #[test]
+    fn validation() {
+        assert_eq!(TriggerConf::Conf10.min_parties(), 10);
+        assert_eq!(
+            TriggerConf::Conf10.triggers().max_party_id(),
+            Some(9),
+            "Conf10 refers to parties it doesn't declare"
+        );
+
+        let config = ExperimentConfig {
+            n_parties: 9,
+            ..ExperimentConfig::new(2, 2)
+        };
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::TooFewParties {
+                required: 10,
+                configured: 9
+            })
+        );
+
+        let config = ExperimentConfig {
+            max_rounds: Some(0),
+            ..ExperimentConfig::new(2, 2)
+        };
+        assert_eq!(config.validate(), Err(ConfigError::ZeroMaxRounds));
+
+        let config = ExperimentConfig {
+            retention: Some(Retention::MaxItems(100)),
+            gc_every: SimDuration::zero(),
+            ..ExperimentConfig::new(2, 2)
+        };
+        assert_eq!(config.validate(), Err(ConfigError::ZeroGcInterval));
+
+        let config = ExperimentConfig {
+            faults: Some(Faults {
+                drop: -0.5,
+                ..Default::default()
+            }),
+            ..ExperimentConfig::new(2, 2)
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::InvalidFaults(_))
+        ));
+
+        let config = ExperimentConfig::new(42, 2);
+        assert_eq!(
+            uniform::protocol_params(&config).unwrap_err(),
+            ConfigError::UnsupportedSplit(42)
+        );
+        assert_eq!(
+            uniform::protocol_params(&ExperimentConfig::dynamic_split(4)).unwrap_err(),
+            ConfigError::DynamicSplitUnsupported
+        );
+        assert!(timestamped::protocol_params(&ExperimentConfig::dynamic_split(4)).is_ok());
+    }
+
+    #[test]
+    fn runtime_parameters() {
+        let short = |config: ExperimentConfig| ExperimentConfig {
+            length: 3 * SimDuration::DAY,
+            ..config
+        };
+
+        let a = uniform::uniform_experiment(&short(ExperimentConfig::new(2, 2))).unwrap();
+        let b = uniform::uniform_experiment(&short(ExperimentConfig::new(5, 8))).unwrap();
+        assert_eq!(a.entries().len(), b.entries().len());
+
+        let c = timestamped::timestamped_experiment(&short(ExperimentConfig::dynamic_split(4)))
+            .unwrap();
+        assert!(!c.entries().is_empty());
+
+        let d = timestamped::timestamped_experiment(&short(ExperimentConfig {
+            retention: Some(Retention::MaxAge(SimDuration::DAY)),
+            ..ExperimentConfig::dynamic_split(4)
+        }))
+        .unwrap();
+        let n_collections = d
+            .entries()
+            .iter()
+            .filter(|(_, entry)| matches!(entry, TraceEntry::Collected(_)))
+            .count();
+        assert_eq!(
+            n_collections,
+            2 * 10,
+            "every party collects after day one and two"
+        );
+    }
+}
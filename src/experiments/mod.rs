@@ -1,6 +1,14 @@
 use std::collections::BTreeMap;
 
-use crate::scenarios::dynamic::{Event, Frequency, Probability, SimDuration, SimInstant, Triggers};
+use crate::scenarios::dynamic::{
+    Event, EventFilter, Frequency, Probability, SimDuration, SimInstant, Triggers,
+};
+
+mod config;
+pub mod scenario;
+pub mod sweep;
+pub mod topology;
+pub use config::{ConfigError, ExperimentConfig, ExperimentError, TriggerConf};
 
 fn sleep_schedule(
     party_id: usize,
@@ -20,36 +28,34 @@ fn sleep_schedule(
 
     let sleep_event = Event::repeat(
         SimDuration::DAY,
-        Event::drop_probabilities(move |(evt_party_id, p, evt)| {
-            let mut found = false;
-            for i in awake_probabilities.clone() {
-                if (*evt_party_id, p.clone(), evt.clone()) == i {
-                    found = true;
-                }
-            }
-            found
-        }),
+        Event::DropProbabilities(EventFilter::Or(
+            awake_probabilities
+                .iter()
+                .map(EventFilter::trigger)
+                .collect(),
+        )),
     );
 
     Triggers::new(
-        BTreeMap::from_iter(
-            [
-                (SimInstant::zero() + offset, vec![(party_id, awake_event)]),
-                (
-                    SimInstant::zero() + offset + time_awake,
-                    vec![(party_id, sleep_event)],
-                ),
-            ]
-            .into_iter(),
-        ),
+        BTreeMap::from_iter([
+            (SimInstant::zero() + offset, vec![(party_id, awake_event)]),
+            (
+                SimInstant::zero() + offset + time_awake,
+                vec![(party_id, sleep_event)],
+            ),
+        ]),
         vec![],
     )
 }
 
-fn many_parties<F: Fn(usize) -> Triggers>(party_ids: std::ops::Range<usize>, f: F) -> Triggers {
+fn many_parties<I, F>(party_ids: I, f: F) -> Triggers
+where
+    I: IntoIterator<Item = usize>,
+    F: Fn(usize) -> Triggers,
+{
     let mut triggers = Triggers::default();
 
-    for mut party_triggers in party_ids.map(f) {
+    for mut party_triggers in party_ids.into_iter().map(f) {
         triggers.append(&mut party_triggers)
     }
 
@@ -115,7 +121,11 @@ pub mod timestamped {
     use unionize::protocol::Encodable;
     use unionize::Object;
 
-    use crate::scenarios::dynamic::{self, SimDuration, SimInstant, SimObject, Simulator, Trace};
+    use super::{ConfigError, ExperimentConfig, ExperimentError};
+    use crate::scenarios::dynamic::{
+        self, Protocol, SimInstant, SimObject, SimOutcome, Simulator, Trace, TraceSink,
+    };
+    use crate::scenarios::protocol::ProtocolParams;
     use crate::suites::{timestamped, uniform};
 
     impl unionize::Item for SimInstant {
@@ -139,20 +149,17 @@ pub mod timestamped {
         }
 
         fn to_unique(&self) -> Self::Unique {
-            <SimObject as Object<uniform::Item>>::to_item(&self)
+            <SimObject as Object<uniform::Item>>::to_item(self)
         }
 
         fn validate_self_consistency(&self) -> bool {
-            <SimObject as Object<uniform::Item>>::validate_self_consistency(&self)
+            <SimObject as Object<uniform::Item>>::validate_self_consistency(self)
         }
     }
 
     #[derive(Clone)]
     pub struct TimestampSim;
     impl Simulator for TimestampSim {
-        const ITEM_SIZE: usize = 33;
-        const MONOID_SIZE: usize = 30;
-
         type Item = timestamped::Item;
         type Monoid = timestamped::Monoid;
         type Node = timestamped::Node;
@@ -160,48 +167,68 @@ pub mod timestamped {
         type EncodedMonoid = <uniform::Monoid as Encodable>::Encoded;
     }
 
-    pub fn timestamped_experiment<const SPLITS: usize, const THRESH: usize>(
-        seed: [u8; 32],
-    ) -> Trace<timestamped::Item, SimObject> {
-        let mut rng = rand_chacha::ChaCha8Rng::from_seed(seed);
-        let n_parties = 10;
-        let initial_triggers = super::trigger_conf_10();
-        let length = 18 * SimDuration::MONTH;
+    pub fn protocol_params(config: &ExperimentConfig) -> Result<ProtocolParams, ConfigError> {
+        let split = if config.dynamic_split {
+            timestamped::split_dynamic_fn(config.threshold)
+                .ok_or(ConfigError::UnsupportedThreshold(config.threshold))?
+        } else {
+            timestamped::split_fn(config.split)
+                .ok_or(ConfigError::UnsupportedSplit(config.split))?
+        };
+
+        Ok(ProtocolParams {
+            max_rounds: config.max_rounds,
+            detect_cycles: config.detect_cycles,
+            faults: config.faults,
+            ..ProtocolParams::new(config.threshold, split)
+        })
+    }
+
+    pub fn timestamped_experiment(
+        config: &ExperimentConfig,
+    ) -> Result<Trace<timestamped::Item, SimObject>, ConfigError> {
+        config.validate()?;
+        let params = protocol_params(config)?;
+        let mut rng = rand_chacha::ChaCha8Rng::from_seed(config.seed);
 
-        TimestampSim::sim(
+        Ok(TimestampSim::sim_with_options(
             &mut rng,
-            n_parties,
-            initial_triggers,
-            length,
-            timestamped::run_protocol::<_, _, _, SPLITS, THRESH>,
-        )
+            config.n_parties,
+            config.initial_triggers(),
+            config.length,
+            Protocol::new(timestamped::run_protocol, params),
+            config.options.clone(),
+        ))
     }
 
-    pub fn timestamped_experiment_dynamic_split<const THRESH: usize>(
-        seed: [u8; 32],
-    ) -> Trace<timestamped::Item, SimObject> {
-        let mut rng = rand_chacha::ChaCha8Rng::from_seed(seed);
-        let n_parties = 10;
-        let initial_triggers = super::trigger_conf_10();
-        let length = 18 * SimDuration::MONTH;
+    /// Like [`timestamped_experiment`], but hands the trace to the sink as it happens.
+    pub fn timestamped_experiment_into<K: TraceSink<TimestampSim>>(
+        config: &ExperimentConfig,
+        sink: &mut K,
+    ) -> Result<SimOutcome, ExperimentError> {
+        config.validate()?;
+        let params = protocol_params(config)?;
+        let mut rng = rand_chacha::ChaCha8Rng::from_seed(config.seed);
 
-        TimestampSim::sim(
+        Ok(TimestampSim::sim_into(
             &mut rng,
-            n_parties,
-            initial_triggers,
-            length,
-            timestamped::run_protocol_dynamic_split::<_, _, _, THRESH>,
-        )
+            config.n_parties,
+            config.initial_triggers(),
+            config.length,
+            Protocol::new(timestamped::run_protocol, params),
+            config.options.clone(),
+            sink,
+        )?)
     }
 
     #[cfg(test)]
     mod tests {
+        use crate::experiments::ExperimentConfig;
         use crate::scenarios::dynamic::TraceEntryRecord;
 
         #[test]
         fn run_timestamped_experiment() {
-            let seed = [0u8; 32];
-            let trace = super::timestamped_experiment::<3, 4>(seed);
+            let trace = super::timestamped_experiment(&ExperimentConfig::new(3, 4)).unwrap();
             let mut wtr = csv::WriterBuilder::new().flexible(true).from_writer(vec![]);
 
             for (meta, entry) in trace.entries() {
@@ -221,7 +248,9 @@ pub mod uniform {
     use unionize::protocol::Encodable;
     use unionize::Object;
 
-    use crate::scenarios::dynamic::{SimDuration, SimObject, Simulator, Trace};
+    use super::{ConfigError, ExperimentConfig, ExperimentError};
+    use crate::scenarios::dynamic::{Protocol, SimObject, SimOutcome, Simulator, Trace, TraceSink};
+    use crate::scenarios::protocol::ProtocolParams;
     use crate::suites::uniform;
 
     impl Object<uniform::Item> for SimObject {
@@ -230,13 +259,8 @@ pub mod uniform {
             let post_id_bs = self.post_id.to_le_bytes();
             let author_bs = self.author.to_le_bytes();
 
-            for i in 0..author_bs.len() {
-                buf[i] = author_bs[i];
-            }
-
-            for i in 0..post_id_bs.len() {
-                buf[i + 8] = post_id_bs[i];
-            }
+            buf[..author_bs.len()].copy_from_slice(&author_bs);
+            buf[8..8 + post_id_bs.len()].copy_from_slice(&post_id_bs);
 
             LEByteArray(buf)
         }
@@ -252,9 +276,6 @@ pub mod uniform {
     pub struct UniformSim;
 
     impl Simulator for UniformSim {
-        const ITEM_SIZE: usize = 30;
-        const MONOID_SIZE: usize = 30;
-
         type Item = LEByteArray<30>;
         type Monoid = uniform::Monoid;
         type Node = uniform::Node;
@@ -262,31 +283,67 @@ pub mod uniform {
         type EncodedMonoid = <uniform::Monoid as Encodable>::Encoded;
     }
 
-    pub fn uniform_experiment<const SPLITS: usize, const THRESH: usize>(
-        seed: [u8; 32],
-    ) -> Trace<LEByteArray<30>, SimObject> {
-        let mut rng = rand_chacha::ChaCha8Rng::from_seed(seed);
-        let n_parties = 10;
-        let initial_triggers = super::trigger_conf_10();
-        let length = 18 * SimDuration::MONTH;
+    pub fn protocol_params(config: &ExperimentConfig) -> Result<ProtocolParams, ConfigError> {
+        if config.dynamic_split {
+            return Err(ConfigError::DynamicSplitUnsupported);
+        }
+
+        let split =
+            uniform::split_fn(config.split).ok_or(ConfigError::UnsupportedSplit(config.split))?;
+
+        Ok(ProtocolParams {
+            max_rounds: config.max_rounds,
+            detect_cycles: config.detect_cycles,
+            faults: config.faults,
+            ..ProtocolParams::new(config.threshold, split)
+        })
+    }
+
+    pub fn uniform_experiment(
+        config: &ExperimentConfig,
+    ) -> Result<Trace<LEByteArray<30>, SimObject>, ConfigError> {
+        config.validate()?;
+        let params = protocol_params(config)?;
+        let mut rng = rand_chacha::ChaCha8Rng::from_seed(config.seed);
 
-        UniformSim::sim(
+        Ok(UniformSim::sim_with_options(
             &mut rng,
-            n_parties,
-            initial_triggers,
-            length,
-            uniform::run_protocol::<_, _, _, SPLITS, THRESH>,
-        )
+            config.n_parties,
+            config.initial_triggers(),
+            config.length,
+            Protocol::new(uniform::run_protocol, params),
+            config.options.clone(),
+        ))
+    }
+
+    /// Like [`uniform_experiment`], but hands the trace to the sink as it happens.
+    pub fn uniform_experiment_into<K: TraceSink<UniformSim>>(
+        config: &ExperimentConfig,
+        sink: &mut K,
+    ) -> Result<SimOutcome, ExperimentError> {
+        config.validate()?;
+        let params = protocol_params(config)?;
+        let mut rng = rand_chacha::ChaCha8Rng::from_seed(config.seed);
+
+        Ok(UniformSim::sim_into(
+            &mut rng,
+            config.n_parties,
+            config.initial_triggers(),
+            config.length,
+            Protocol::new(uniform::run_protocol, params),
+            config.options.clone(),
+            sink,
+        )?)
     }
 
     #[cfg(test)]
     mod tests {
+        use crate::experiments::ExperimentConfig;
         use crate::scenarios::dynamic::TraceEntryRecord;
 
         #[test]
         fn run_uniform_experiment_2_3() {
-            let seed = [0u8; 32];
-            let trace = super::uniform_experiment::<2, 3>(seed);
+            let trace = super::uniform_experiment(&ExperimentConfig::new(2, 3)).unwrap();
             let mut wtr = csv::WriterBuilder::new().flexible(true).from_writer(vec![]);
 
             for (meta, entry) in trace.entries() {
@@ -299,8 +356,7 @@ pub mod uniform {
 
         #[test]
         fn run_uniform_experiment_2_2() {
-            let seed = [0u8; 32];
-            let trace = super::uniform_experiment::<2, 2>(seed);
+            let trace = super::uniform_experiment(&ExperimentConfig::new(2, 2)).unwrap();
             let mut wtr = csv::WriterBuilder::new().flexible(true).from_writer(vec![]);
 
             for (meta, entry) in trace.entries() {
@@ -313,8 +369,7 @@ pub mod uniform {
 
         #[test]
         fn run_uniform_experiment_3_4() {
-            let seed = [0u8; 32];
-            let trace = super::uniform_experiment::<3, 4>(seed);
+            let trace = super::uniform_experiment(&ExperimentConfig::new(3, 4)).unwrap();
             let mut wtr = csv::WriterBuilder::new().flexible(true).from_writer(vec![]);
 
             for (meta, entry) in trace.entries() {
@@ -0,0 +1,854 @@
+//! Scenarios written down as TOML files instead of Rust, so they can be shared and tweaked
+//! without recompiling. See `scenarios/conf10.toml` for the scenario we use by default, written
+//! in this format.
+//!
+//! A scenario has three kinds of entries, all optional:
+//!
+//! ```toml
+//! # parties that are only active part of the day
+//! [[sleep]]
+//! parties = "0..4"     # a single id, a list like [0, 2], or a range
+//! offset = "0"         # when they wake up the first time, default 0
+//! awake = "10h"        # how long they stay awake each day
+//! while_awake = [
+//!     { every = "1h", event = "post" },
+//!     { percent = 5, event = { sync = 8 } },
+//! ]
+//!
+//! # triggers that are active for the whole run
+//! [[probabilistic]]
+//! parties = 8
+//! every = "1d"
+//! event = { sync = 9 }
+//!
+//! # partners picked when the sync happens: `random`, `round_robin` or `least_recent` among the
+//! # listed parties (all others if the list is empty), or `weighted` by [party, weight] pairs
+//! [[probabilistic]]
+//! parties = 9
+//! every = "12h"
+//! event = { sync_with = { weighted = [[8, 3], [0, 1]] } }
+//!
+//! # events at fixed points in time
+//! [[scheduled]]
+//! at = "2w"
+//! parties = [0, 1]
+//! event = { repeat = { every = "1d", event = "post" } }
+//!
+//! # tagged triggers can be dropped again by tag
+//! [[scheduled]]
+//! at = "0"
+//! parties = 3
+//! event = { add_probabilities = [
+//!     { parties = 3, every = "1h", event = { tagged = { tag = "burst", event = "post" } } },
+//! ] }
+//!
+//! [[scheduled]]
+//! at = "1d"
+//! parties = 3
+//! event = { drop_probabilities = { tag = "burst" } }
+//!
+//! # party 9 joins after a month, with a week old copy of what party 0 holds
+//! [[scheduled]]
+//! at = "0"
+//! parties = 9
+//! event = "leave"
+//!
+//! [[scheduled]]
+//! at = "4w"
+//! parties = 9
+//! event = { join = { snapshot = { from = 0, age = "1w" } } }
+//!
+//! # 200 more parties, each syncing with 3 random others every 6 hours; the graph's party 0 is
+//! # party 10 here, and the seed picks the graph
+//! [[topology]]
+//! parties = "10..210"
+//! graph = { random_regular = { degree = 3 } }
+//! every = "6h"
+//! seed = 1
+//!
+//! # parties 0 to 4 can't sync with the others for a month; `until` is optional
+//! [[partition]]
+//! side = "0..5"
+//! from = "1w"
+//! until = "5w"
+//!
+//! # parties delete their newest post now and then
+//! [[probabilistic]]
+//! parties = "0..4"
+//! every = "1w"
+//! event = { delete = "newest" }
+//!
+//! # and only keep a month of history
+//! [[scheduled]]
+//! at = "1d"
+//! parties = "0..10"
+//! event = { repeat = { every = "1d", event = { gc = { max_age = "30d" } } } }
+//! ```
+//!
+//! Graphs are `"star"`, `"ring"`, `{ random_regular = { degree = <n> } }`,
+//! `{ erdos_renyi = { p = <probability> } }`, `{ barabasi_albert = { m = <n> } }` or
+//! `{ two_tier = { servers = <n> } }`, see [`Topology`].
+//!
+//! Durations are given like `90min`, `12h`, `3d`, `2w`, `18mo` or `1y`. Rates are either
+//! `every = <duration>`, `percent = <n>` or `permille = <n>` per minute. Filters are written
+//! like `{ and = [{ party = 3 }, { kind = "post" }] }`, see [`EventFilter`] for what they match.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Deserializer};
+
+use super::topology::{Graph, Topology, TopologyError};
+
+use crate::scenarios::dynamic::{
+    Deletion, Event, EventFilter, Frequency, PeerSelection, Probability, Retention, Seed,
+    SimDuration, SimInstant, Triggers,
+};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub sleep: Vec<SleepSpec>,
+    #[serde(default)]
+    pub probabilistic: Vec<ProbabilisticSpec>,
+    #[serde(default)]
+    pub scheduled: Vec<ScheduledSpec>,
+    #[serde(default)]
+    pub topology: Vec<TopologySpec>,
+    #[serde(default)]
+    pub partition: Vec<PartitionSpec>,
+}
+
+impl Scenario {
+    pub fn from_toml(s: &str) -> Result<Self, ScenarioError> {
+        let scenario: Scenario = toml::from_str(s).map_err(ScenarioError::Parse)?;
+        for topology in &scenario.topology {
+            topology.graph()?;
+        }
+        Ok(scenario)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ScenarioError> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    /// Panics if a topology can't be generated, which [`Scenario::from_toml`] checks.
+    pub fn triggers(&self) -> Triggers {
+        let mut triggers = Triggers::default();
+
+        for sleep in &self.sleep {
+            triggers.append(&mut super::many_parties(sleep.parties.iter(), |party_id| {
+                super::sleep_schedule(
+                    party_id,
+                    sleep.offset,
+                    sleep.awake,
+                    sleep
+                        .while_awake
+                        .iter()
+                        .map(|awake| (awake.rate.0, awake.event.to_event()))
+                        .collect(),
+                )
+            }));
+        }
+
+        for prob in &self.probabilistic {
+            triggers.append(&mut super::probabilistic_triggers(prob.to_triggers()));
+        }
+
+        for sched in &self.scheduled {
+            let events = sched
+                .parties
+                .iter()
+                .map(|party_id| (party_id, sched.event.to_event()))
+                .collect();
+            triggers.append(&mut Triggers::new(
+                BTreeMap::from_iter([(SimInstant::zero() + sched.at, events)]),
+                vec![],
+            ));
+        }
+
+        for topology in &self.topology {
+            triggers.append(&mut topology.triggers());
+        }
+
+        for partition in &self.partition {
+            let side: Vec<_> = partition.side.iter().collect();
+            // the party doesn't matter, as long as it refers to no party that isn't there
+            let party_id = side.first().copied().unwrap_or_default();
+            let mut scheduled = BTreeMap::from_iter([(
+                SimInstant::zero() + partition.from,
+                vec![(party_id, Event::Partition(side))],
+            )]);
+            if let Some(until) = partition.until {
+                scheduled
+                    .entry(SimInstant::zero() + until)
+                    .or_default()
+                    .push((party_id, Event::Heal));
+            }
+            triggers.append(&mut Triggers::new(scheduled, vec![]));
+        }
+
+        triggers
+    }
+}
+
+/// Parties that follow a daily sleep schedule. While they are awake, the `while_awake`
+/// triggers are active.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SleepSpec {
+    pub parties: Parties,
+    #[serde(default = "SimDuration::zero", deserialize_with = "duration")]
+    pub offset: SimDuration,
+    #[serde(deserialize_with = "duration")]
+    pub awake: SimDuration,
+    #[serde(default)]
+    pub while_awake: Vec<AwakeSpec>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AwakeSpec {
+    #[serde(flatten)]
+    pub rate: Rate,
+    pub event: EventSpec,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProbabilisticSpec {
+    pub parties: Parties,
+    #[serde(flatten)]
+    pub rate: Rate,
+    pub event: EventSpec,
+}
+
+impl ProbabilisticSpec {
+    fn to_triggers(&self) -> Vec<(usize, Probability, Event)> {
+        self.parties
+            .iter()
+            .map(|party_id| (party_id, self.rate.0, self.event.to_event()))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduledSpec {
+    #[serde(deserialize_with = "duration")]
+    pub at: SimDuration,
+    pub parties: Parties,
+    pub event: EventSpec,
+}
+
+/// Sync triggers along the edges of a generated graph, see [`Topology`]. Party `i` of the graph
+/// is the `i`th of `parties`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TopologySpec {
+    pub parties: Parties,
+    pub graph: Topology,
+    #[serde(flatten)]
+    pub rate: Rate,
+    /// Seeds the graph, not the simulation.
+    #[serde(default)]
+    pub seed: u64,
+}
+
+impl TopologySpec {
+    pub fn graph(&self) -> Result<Graph, TopologyError> {
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+        self.graph.generate(self.parties.0.len(), &mut rng)
+    }
+
+    fn triggers(&self) -> Triggers {
+        let graph = self
+            .graph()
+            .expect("topologies are checked when they are read");
+        let party_ids = &self.parties.0;
+        let probabilistic = graph
+            .edges()
+            .map(|(responder, initiator)| {
+                (
+                    party_ids[initiator],
+                    self.rate.0,
+                    Event::Sync(party_ids[responder]),
+                )
+            })
+            .collect();
+        Triggers::new(Default::default(), probabilistic)
+    }
+}
+
+/// Keeps the parties on `side` from syncing with all others from `from` until `until`, see
+/// [`Event::Partition`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PartitionSpec {
+    pub side: Parties,
+    #[serde(deserialize_with = "duration")]
+    pub from: SimDuration,
+    #[serde(default, deserialize_with = "optional_duration")]
+    pub until: Option<SimDuration>,
+}
+
+/// Same as [`ScheduledSpec`], but relative to the event that schedules it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RelativeSpec {
+    pub parties: Parties,
+    pub event: EventSpec,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum EventSpec {
+    Post,
+    Sync(usize),
+    SyncWith(PeerSelection),
+    AddProbabilities(Vec<ProbabilisticSpec>),
+    ScheduleRelative {
+        #[serde(deserialize_with = "duration")]
+        after: SimDuration,
+        events: Vec<RelativeSpec>,
+    },
+    Repeat {
+        #[serde(deserialize_with = "duration")]
+        every: SimDuration,
+        event: Box<EventSpec>,
+    },
+    DropProbabilities(EventFilter),
+    Tagged {
+        tag: String,
+        event: Box<EventSpec>,
+    },
+    Join(SeedSpec),
+    Leave,
+    Wipe,
+    Delete(Deletion),
+    Gc(RetentionSpec),
+    Partition(Parties),
+    Heal,
+}
+
+/// See [`Retention`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum RetentionSpec {
+    MaxAge(#[serde(deserialize_with = "duration")] SimDuration),
+    MaxItems(usize),
+}
+
+/// See [`Seed`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum SeedSpec {
+    Empty,
+    Snapshot {
+        from: usize,
+        #[serde(default = "SimDuration::zero", deserialize_with = "duration")]
+        age: SimDuration,
+    },
+}
+
+impl EventSpec {
+    pub fn to_event(&self) -> Event {
+        match self {
+            EventSpec::Post => Event::Post,
+            EventSpec::Sync(partner) => Event::Sync(*partner),
+            EventSpec::SyncWith(selection) => Event::SyncWith(selection.clone()),
+            EventSpec::AddProbabilities(probs) => Event::AddProbabilities(
+                probs
+                    .iter()
+                    .flat_map(ProbabilisticSpec::to_triggers)
+                    .collect(),
+            ),
+            EventSpec::ScheduleRelative { after, events } => Event::ScheduleRelative(
+                *after,
+                events
+                    .iter()
+                    .flat_map(|rel| {
+                        rel.parties
+                            .iter()
+                            .map(|party_id| (party_id, rel.event.to_event()))
+                    })
+                    .collect(),
+            ),
+            EventSpec::Repeat { every, event } => Event::repeat(*every, event.to_event()),
+            EventSpec::DropProbabilities(filter) => Event::DropProbabilities(filter.clone()),
+            EventSpec::Tagged { tag, event } => Event::tagged(tag.clone(), event.to_event()),
+            EventSpec::Join(SeedSpec::Empty) => Event::Join(Seed::Empty),
+            EventSpec::Join(SeedSpec::Snapshot { from, age }) => Event::Join(Seed::Snapshot {
+                from: *from,
+                age: *age,
+            }),
+            EventSpec::Leave => Event::Leave,
+            EventSpec::Wipe => Event::Wipe,
+            EventSpec::Delete(deletion) => Event::Delete(deletion.clone()),
+            EventSpec::Gc(RetentionSpec::MaxAge(age)) => Event::Gc(Retention::MaxAge(*age)),
+            EventSpec::Gc(RetentionSpec::MaxItems(n)) => Event::Gc(Retention::MaxItems(*n)),
+            EventSpec::Partition(side) => Event::Partition(side.iter().collect()),
+            EventSpec::Heal => Event::Heal,
+        }
+    }
+}
+
+/// A set of party ids: a single id, a list, or a range like `"0..4"`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "PartiesSpec")]
+pub struct Parties(Vec<usize>);
+
+impl Parties {
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.0.iter().copied()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PartiesSpec {
+    One(usize),
+    List(Vec<usize>),
+    Range(String),
+}
+
+impl TryFrom<PartiesSpec> for Parties {
+    type Error = String;
+
+    fn try_from(value: PartiesSpec) -> Result<Self, Self::Error> {
+        match value {
+            PartiesSpec::One(party_id) => Ok(Parties(vec![party_id])),
+            PartiesSpec::List(party_ids) => Ok(Parties(party_ids)),
+            PartiesSpec::Range(range) => {
+                let invalid = || format!("invalid party range {range:?}, expected e.g. \"0..4\"");
+                let (start, end) = range.split_once("..").ok_or_else(invalid)?;
+                let start: usize = start.trim().parse().map_err(|_| invalid())?;
+                let end: usize = end.trim().parse().map_err(|_| invalid())?;
+                Ok(Parties((start..end).collect()))
+            }
+        }
+    }
+}
+
+/// The probability of a trigger firing in a given minute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "RateSpec")]
+pub struct Rate(pub Probability);
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum RateSpec {
+    Every(String),
+    Percent(u64),
+    Permille(u64),
+}
+
+impl TryFrom<RateSpec> for Rate {
+    type Error = String;
+
+    fn try_from(value: RateSpec) -> Result<Self, Self::Error> {
+        let p = match value {
+            RateSpec::Every(period) => {
+                let period: SimDuration = period.parse()?;
+                if period == SimDuration::zero() {
+                    return Err("period must not be zero".to_string());
+                }
+                Probability::from_frequency(Frequency::from_period(period))
+            }
+            RateSpec::Percent(percent) if percent <= 100 => Probability::from_percent(percent),
+            RateSpec::Permille(permille) if permille <= 1000 => {
+                Probability::from_permille(permille)
+            }
+            RateSpec::Percent(_) | RateSpec::Permille(_) => {
+                return Err("probability must not exceed 100%".to_string())
+            }
+        };
+
+        Ok(Rate(p))
+    }
+}
+
+fn duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SimDuration, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    s.parse().map_err(serde::de::Error::custom)
+}
+
+fn optional_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<SimDuration>, D::Error> {
+    duration(deserializer).map(Some)
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    Topology(TopologyError),
+}
+
+impl From<TopologyError> for ScenarioError {
+    fn from(value: TopologyError) -> Self {
+        ScenarioError::Topology(value)
+    }
+}
+
+impl From<std::io::Error> for ScenarioError {
+    fn from(value: std::io::Error) -> Self {
+        ScenarioError::Io(value)
+    }
+}
+
+impl std::fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScenarioError::Io(e) => write!(f, "could not read scenario: {e}"),
+            ScenarioError::Parse(e) => write!(f, "invalid scenario: {e}"),
+            ScenarioError::Topology(e) => write!(f, "invalid topology: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+#[cfg(test)]
+mod tests {
+    use super::{Scenario, ScenarioError};
+    use crate::scenarios::dynamic::{
+        Deletion, Event, EventFilter, EventKind, Frequency, PeerSelection, Probability, Retention,
+        Seed, SimDuration, SimInstant,
+    };
+
+    #[test]
+    fn conf10_file_matches_builtin() {
+        let scenario = Scenario::from_toml(include_str!("../../scenarios/conf10.toml")).unwrap();
+
+        assert_eq!(
+            format!("{:?}", scenario.triggers()),
+            format!("{:?}", super::super::trigger_conf_10())
+        );
+    }
+
+    #[test]
+    fn parse_events() {
+        let scenario = Scenario::from_toml(
+            r#"
+            [[scheduled]]
+            at = "1d"
+            parties = "1..3"
+            event = { schedule_relative = { after = "2h", events = [
+                { parties = 0, event = { repeat = { every = "1w", event = { sync = 4 } } } },
+            ] } }
+
+            [[scheduled]]
+            at = "0"
+            parties = [5]
+            event = { add_probabilities = [{ parties = 5, permille = 1, event = "post" }] }
+            "#,
+        )
+        .unwrap();
+
+        let triggers = scenario.triggers();
+        assert_eq!(triggers.max_party_id(), Some(5));
+
+        let expected = format!(
+            "{:?}",
+            Event::ScheduleRelative(
+                2 * SimDuration::HOUR,
+                vec![(0, Event::repeat(SimDuration::WEEK, Event::Sync(4)))]
+            )
+        );
+        assert_eq!(
+            format!("{triggers:?}").matches(&expected).count(),
+            2,
+            "expected the relative event for party 1 and 2"
+        );
+        assert!(format!("{triggers:?}").contains(&format!(
+            "{:?}",
+            Event::AddProbabilities(vec![(5, Probability::from_permille(1), Event::Post)])
+        )));
+    }
+
+    #[test]
+    fn parse_peer_selection() {
+        let scenario = Scenario::from_toml(
+            r#"
+            [[probabilistic]]
+            parties = "0..4"
+            every = "1h"
+            event = { sync_with = { random = [] } }
+
+            [[probabilistic]]
+            parties = 4
+            every = "1h"
+            event = { sync_with = { round_robin = [0, 2] } }
+
+            [[probabilistic]]
+            parties = 5
+            every = "1h"
+            event = { sync_with = { least_recent = [1, 3] } }
+
+            [[probabilistic]]
+            parties = 6
+            every = "1h"
+            event = { sync_with = { weighted = [[8, 3], [0, 1]] } }
+            "#,
+        )
+        .unwrap();
+
+        let events: Vec<_> = scenario
+            .probabilistic
+            .iter()
+            .map(|spec| spec.event.to_event())
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                Event::SyncWith(PeerSelection::Random(vec![])),
+                Event::SyncWith(PeerSelection::RoundRobin(vec![0, 2])),
+                Event::SyncWith(PeerSelection::LeastRecent(vec![1, 3])),
+                Event::SyncWith(PeerSelection::Weighted(vec![(8, 3), (0, 1)])),
+            ]
+        );
+        assert_eq!(events[0].kind(), EventKind::Sync);
+        assert_eq!(scenario.triggers().max_party_id(), Some(8));
+    }
+
+    #[test]
+    fn parse_topology() {
+        let scenario = Scenario::from_toml(
+            r#"
+            [[topology]]
+            parties = [10, 20, 30]
+            graph = "star"
+            every = "1h"
+
+            [[topology]]
+            parties = "0..100"
+            graph = { barabasi_albert = { m = 2 } }
+            every = "6h"
+            seed = 3
+            "#,
+        )
+        .unwrap();
+
+        let star = Probability::from_frequency(Frequency::from_period(SimDuration::HOUR));
+        let triggers = format!("{:?}", scenario.triggers());
+        for leaf in [20, 30] {
+            let trigger = format!("{:?}", (leaf, star, Event::Sync(10)));
+            assert!(triggers.contains(&trigger), "missing {trigger}");
+        }
+        assert_eq!(scenario.triggers().max_party_id(), Some(99));
+        assert_eq!(scenario.topology[1].graph().unwrap().n_edges(), 3 + 97 * 2);
+
+        // the seed picks the graph
+        let graph = |seed| {
+            let toml = format!(
+                "[[topology]]\nparties = \"0..50\"\ngraph = {{ erdos_renyi = {{ p = 0.2 }} }}\n\
+                 every = \"1d\"\nseed = {seed}\n"
+            );
+            Scenario::from_toml(&toml).unwrap().topology[0]
+                .graph()
+                .unwrap()
+        };
+        assert_eq!(graph(1), graph(1));
+        assert_ne!(graph(1), graph(2));
+
+        assert!(matches!(
+            Scenario::from_toml(
+                "[[topology]]\nparties = \"0..5\"\ngraph = { random_regular = { degree = 3 } }\n\
+                 every = \"1d\"\n"
+            ),
+            Err(ScenarioError::Topology(_))
+        ));
+    }
+
+    #[test]
+    fn parse_partitions() {
+        let scenario = Scenario::from_toml(
+            r#"
+            [[partition]]
+            side = "2..4"
+            from = "1w"
+            until = "5w"
+
+            [[partition]]
+            side = 6
+            from = "6w"
+
+            [[scheduled]]
+            at = "7w"
+            parties = 0
+            event = "heal"
+            "#,
+        )
+        .unwrap();
+
+        let triggers = format!("{:?}", scenario.triggers());
+        let scheduled = |weeks: u64, party_id: usize, event: Event| {
+            format!(
+                "{:?}: [{:?}]",
+                SimInstant::zero() + weeks * SimDuration::WEEK,
+                (party_id, event)
+            )
+        };
+        for expected in [
+            scheduled(1, 2, Event::Partition(vec![2, 3])),
+            scheduled(5, 2, Event::Heal),
+            scheduled(6, 6, Event::Partition(vec![6])),
+            scheduled(7, 0, Event::Heal),
+        ] {
+            assert!(triggers.contains(&expected), "missing {expected}");
+        }
+        assert_eq!(scenario.triggers().max_party_id(), Some(6));
+    }
+
+    #[test]
+    fn parse_churn_and_deletes() {
+        let scenario = Scenario::from_toml(
+            r#"
+            [[scheduled]]
+            at = "0"
+            parties = [6, 7]
+            event = "leave"
+
+            [[scheduled]]
+            at = "4w"
+            parties = 6
+            event = { join = { snapshot = { from = 8, age = "1w" } } }
+
+            [[scheduled]]
+            at = "4w"
+            parties = 7
+            event = { join = "empty" }
+
+            [[scheduled]]
+            at = "5w"
+            parties = 7
+            event = { delete = { post = { author = 6, post_id = 0 } } }
+            "#,
+        )
+        .unwrap();
+
+        let events: Vec<_> = scenario
+            .scheduled
+            .iter()
+            .map(|spec| spec.event.to_event())
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                Event::Leave,
+                Event::Join(Seed::Snapshot {
+                    from: 8,
+                    age: SimDuration::WEEK
+                }),
+                Event::Join(Seed::Empty),
+                Event::Delete(Deletion::Post {
+                    author: 6,
+                    post_id: 0
+                }),
+            ]
+        );
+        assert_eq!(scenario.triggers().max_party_id(), Some(8));
+    }
+
+    #[test]
+    fn parse_gc() {
+        let scenario = Scenario::from_toml(
+            r#"
+            [[scheduled]]
+            at = "1d"
+            parties = 0
+            event = { gc = { max_age = "30d" } }
+
+            [[scheduled]]
+            at = "1d"
+            parties = 1
+            event = { gc = { max_items = 500 } }
+            "#,
+        )
+        .unwrap();
+
+        let events: Vec<_> = scenario
+            .scheduled
+            .iter()
+            .map(|spec| spec.event.to_event())
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                Event::Gc(Retention::MaxAge(30 * SimDuration::DAY)),
+                Event::Gc(Retention::MaxItems(500)),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_filters() {
+        let scenario = Scenario::from_toml(
+            r#"
+            [[scheduled]]
+            at = "1d"
+            parties = 3
+            event = { drop_probabilities = { or = [
+                { tag = "burst" },
+                { and = [{ kind = "sync" }, { not = { sync_partner = 8 } }] },
+            ] } }
+            "#,
+        )
+        .unwrap();
+
+        let expected = Event::DropProbabilities(EventFilter::Or(vec![
+            EventFilter::Tag("burst".to_string()),
+            EventFilter::And(vec![
+                EventFilter::Kind(EventKind::Sync),
+                EventFilter::Not(Box::new(EventFilter::SyncPartner(8))),
+            ]),
+        ]));
+        assert_eq!(scenario.scheduled[0].event.to_event(), expected);
+    }
+
+    #[test]
+    fn parse_errors() {
+        for (scenario, error) in [
+            (
+                "[[probabilistic]]\nparties = \"4..\"\nevery = \"1h\"\nevent = \"post\"",
+                "invalid party range",
+            ),
+            (
+                "[[probabilistic]]\nparties = 0\npercent = 101\nevent = \"post\"",
+                "must not exceed 100%",
+            ),
+            (
+                "[[scheduled]]\nat = \"3x\"\nparties = 0\nevent = \"post\"",
+                "unknown unit",
+            ),
+            (
+                "[[scheduled]]\nat = \"3h\"\nparties = 0\nevent = \"sleep\"",
+                "unknown variant",
+            ),
+            // misspelled keys, also next to a flattened rate
+            (
+                "[[probabilistic]]\nparties = 0\nevery = \"1h\"\nevent = \"post\"\noffest = \"1h\"",
+                "unknown field",
+            ),
+            (
+                "[[sleep]]\nparties = 0\nawake = \"8h\"\n\
+                 [[sleep.while_awake]]\nevery = \"1h\"\nevent = \"post\"\nparty = 1",
+                "unknown field",
+            ),
+            (
+                "[[topology]]\nparties = \"0..4\"\ngraph = \"ring\"\nevery = \"1h\"\nsed = 3",
+                "unknown field",
+            ),
+        ] {
+            let e = Scenario::from_toml(scenario).unwrap_err().to_string();
+            assert!(e.contains(error), "{e:?} should contain {error:?}");
+        }
+    }
+}
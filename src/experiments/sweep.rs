@@ -0,0 +1,687 @@
+//! Runs many experiments at once: a grid of suites, split factors, thresholds and seeds is turned
+//! into jobs, which are worked off by a bounded number of threads. Every job writes its trace to
+//! its own file, and an index file lists all jobs with their parameters and outcome.
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use super::{timestamped, uniform, ConfigError, ExperimentConfig, ExperimentError};
+use crate::scenarios::dynamic::{
+    CsvSink, Propagation, SimInstant, SimObject, Simulator, SyncTimes, Trace, TraceSink,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum Suite {
+    Timestamped,
+    Uniform,
+}
+
+impl Suite {
+    pub const ALL: [Suite; 2] = [Suite::Timestamped, Suite::Uniform];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Suite::Timestamped => "timestamped",
+            Suite::Uniform => "uniform",
+        }
+    }
+}
+
+impl std::str::FromStr for Suite {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Suite::ALL
+            .into_iter()
+            .find(|suite| suite.name() == s)
+            .ok_or_else(|| format!("unknown suite {s:?}, use one of timestamped, uniform"))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Split {
+    Fixed(usize),
+    Dynamic,
+}
+
+/// Parses a split factor, or `dyn` for dynamic splitting.
+impl std::str::FromStr for Split {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dyn" => Ok(Split::Dynamic),
+            _ => s
+                .parse()
+                .map(Split::Fixed)
+                .map_err(|_| format!("invalid split {s:?}: expected a number or \"dyn\"")),
+        }
+    }
+}
+
+/// The experiments we keep running, by name.
+pub fn presets() -> Vec<(&'static str, Suite, ExperimentConfig)> {
+    vec![
+        (
+            "timestamped_3_4",
+            Suite::Timestamped,
+            ExperimentConfig::new(3, 4),
+        ),
+        (
+            "timestamped_dyn_4",
+            Suite::Timestamped,
+            ExperimentConfig::dynamic_split(4),
+        ),
+        ("uniform_3_4", Suite::Uniform, ExperimentConfig::new(3, 4)),
+        ("uniform_2_2", Suite::Uniform, ExperimentConfig::new(2, 2)),
+    ]
+}
+
+/// The parameter grid of a sweep. Every combination of the listed values becomes a job, with
+/// everything else taken from `base`.
+#[derive(Debug, Clone)]
+pub struct Grid {
+    pub base: ExperimentConfig,
+    pub suites: Vec<Suite>,
+    pub splits: Vec<Split>,
+    pub thresholds: Vec<usize>,
+    pub seeds: Vec<u64>,
+}
+
+impl Grid {
+    pub fn jobs(&self) -> Vec<Job> {
+        let mut jobs = vec![];
+        for suite in &self.suites {
+            for split in &self.splits {
+                for threshold in &self.thresholds {
+                    for seed in &self.seeds {
+                        let mut job = Job::new(*suite, *seed, self.base.clone());
+                        job.set_split(*split);
+                        job.config.threshold = *threshold;
+                        jobs.push(job);
+                    }
+                }
+            }
+        }
+        jobs
+    }
+}
+
+/// Expands a seed number into the seed of the rng. Seed 0 is the all-zero seed we always used.
+pub fn seed_bytes(seed: u64) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    bytes[..8].copy_from_slice(&seed.to_le_bytes());
+    bytes
+}
+
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub suite: Suite,
+    pub seed: u64,
+    pub config: ExperimentConfig,
+}
+
+impl Job {
+    /// Replaces the split of the job's config.
+    pub fn set_split(&mut self, split: Split) {
+        match split {
+            Split::Fixed(split) => {
+                self.config.split = split;
+                self.config.dynamic_split = false;
+            }
+            Split::Dynamic => self.config.dynamic_split = true,
+        }
+    }
+
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.config.seed = seed_bytes(seed);
+    }
+
+    /// Checks whether the job can run, without running it.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.config.validate()?;
+        match self.suite {
+            Suite::Timestamped => timestamped::protocol_params(&self.config).map(|_| ()),
+            Suite::Uniform => uniform::protocol_params(&self.config).map(|_| ()),
+        }
+    }
+
+    pub fn new(suite: Suite, seed: u64, mut config: ExperimentConfig) -> Self {
+        config.seed = seed_bytes(seed);
+        Job {
+            suite,
+            seed,
+            config,
+        }
+    }
+
+    /// A name for the job that is unique within a grid, e.g. `timestamped_dyn_4_s0`.
+    pub fn label(&self) -> String {
+        let split = if self.config.dynamic_split {
+            "dyn".to_string()
+        } else {
+            self.config.split.to_string()
+        };
+
+        format!(
+            "{}_{split}_{}_s{}",
+            self.suite.name(),
+            self.config.threshold,
+            self.seed
+        )
+    }
+
+    /// Runs the experiment, streaming the trace to `<dir>/<label>.<ext>`, and writes the
+    /// propagation of the posts to `<dir>/<label>_propagation.<ext>`. With a network, the
+    /// durations of the syncs go to `<dir>/<label>_sync_times.<ext>`, and with
+    /// [`SimOptions::record_transcripts`](crate::scenarios::dynamic::SimOptions), the rounds of
+    /// the syncs go to `<dir>/<label>_transcripts.<ext>`.
+    pub fn run(&self, output: &Output) -> Result<JobOutcome, JobError> {
+        self.validate()?;
+        let path = output.path(&self.label());
+        let propagation_path = output.path(&format!("{}_propagation", self.label()));
+        let f = std::fs::File::create(path)?;
+        let transcripts = if self.config.options.record_transcripts {
+            let path = output.path(&format!("{}_transcripts", self.label()));
+            Some(output.writer(std::fs::File::create(path)?))
+        } else {
+            None
+        };
+
+        let (outcome, n_entries) = match self.suite {
+            Suite::Timestamped => {
+                let mut sink = trace_sink(f, output.format, transcripts);
+                let outcome = timestamped::timestamped_experiment_into(&self.config, &mut sink)?;
+                sink.flush()?;
+                (outcome, sink.n_entries())
+            }
+            Suite::Uniform => {
+                let mut sink = trace_sink(f, output.format, transcripts);
+                let outcome = uniform::uniform_experiment_into(&self.config, &mut sink)?;
+                sink.flush()?;
+                (outcome, sink.n_entries())
+            }
+        };
+
+        write_propagation(&propagation_path, output.format, &outcome.propagation)?;
+        if self.config.options.network.is_some() {
+            let sync_times_path = output.path(&format!("{}_sync_times", self.label()));
+            write_sync_times(&sync_times_path, output.format, &outcome.sync_times)?;
+        }
+        Ok(JobOutcome {
+            entries: n_entries,
+            aborted_at: outcome.aborted_at,
+        })
+    }
+}
+
+/// What a job that ran did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JobOutcome {
+    /// The number of trace entries written.
+    pub entries: usize,
+    /// When the run ended early because a sync failed, see
+    /// [`SyncFailurePolicy::Abort`](crate::scenarios::dynamic::SyncFailurePolicy::Abort).
+    pub aborted_at: Option<SimInstant>,
+}
+
+#[derive(Debug)]
+pub enum JobError {
+    Config(ConfigError),
+    Io(std::io::Error),
+    Panic(String),
+}
+
+impl From<ConfigError> for JobError {
+    fn from(value: ConfigError) -> Self {
+        JobError::Config(value)
+    }
+}
+
+impl From<std::io::Error> for JobError {
+    fn from(value: std::io::Error) -> Self {
+        JobError::Io(value)
+    }
+}
+
+impl From<ExperimentError> for JobError {
+    fn from(value: ExperimentError) -> Self {
+        match value {
+            ExperimentError::Config(e) => JobError::Config(e),
+            ExperimentError::Io(e) => JobError::Io(e),
+        }
+    }
+}
+
+impl std::fmt::Display for JobError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobError::Config(e) => write!(f, "invalid config: {e}"),
+            JobError::Io(e) => write!(f, "i/o error: {e}"),
+            JobError::Panic(msg) => write!(f, "panicked: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for JobError {}
+
+/// A row of the index file.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobReport {
+    pub label: String,
+    pub suite: Suite,
+    pub split: Option<usize>,
+    pub dynamic_split: bool,
+    pub threshold: usize,
+    pub seed: u64,
+    pub n_parties: usize,
+    pub length: u64,
+    pub file: Option<PathBuf>,
+    pub entries: Option<usize>,
+    /// The minute the run was aborted at, if it didn't run its full length.
+    pub aborted_at: Option<u64>,
+    pub seconds: f64,
+    pub error: Option<String>,
+}
+
+impl JobReport {
+    fn new(
+        job: &Job,
+        file: PathBuf,
+        elapsed: Duration,
+        result: Result<JobOutcome, JobError>,
+    ) -> Self {
+        let (file, outcome, error) = match result {
+            Ok(outcome) => (Some(file), Some(outcome), None),
+            Err(e) => (None, None, Some(e.to_string())),
+        };
+
+        JobReport {
+            label: job.label(),
+            suite: job.suite,
+            split: (!job.config.dynamic_split).then_some(job.config.split),
+            dynamic_split: job.config.dynamic_split,
+            threshold: job.config.threshold,
+            seed: job.seed,
+            n_parties: job.config.n_parties,
+            length: job.config.length.0,
+            file,
+            entries: outcome.map(|outcome| outcome.entries),
+            aborted_at: outcome.and_then(|outcome| outcome.aborted_at).map(|t| t.0),
+            seconds: elapsed.as_secs_f64(),
+            error,
+        }
+    }
+
+    /// Whether the job ran, also if it was aborted.
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Csv,
+    Tsv,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Tsv => "tsv",
+        }
+    }
+
+    fn delimiter(&self) -> u8 {
+        match self {
+            OutputFormat::Csv => b',',
+            OutputFormat::Tsv => b'\t',
+        }
+    }
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "tsv" => Ok(OutputFormat::Tsv),
+            _ => Err(format!("unknown output format {s:?}, use one of csv, tsv")),
+        }
+    }
+}
+
+/// Where and how the sweep writes its files.
+#[derive(Clone, Debug)]
+pub struct Output {
+    pub dir: PathBuf,
+    pub format: OutputFormat,
+}
+
+impl Output {
+    pub fn new<P: Into<PathBuf>>(dir: P, format: OutputFormat) -> Self {
+        Output {
+            dir: dir.into(),
+            format,
+        }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{name}.{}", self.format.extension()))
+    }
+
+    fn writer<W: std::io::Write>(&self, w: W) -> csv::Writer<W> {
+        csv::WriterBuilder::new()
+            .delimiter(self.format.delimiter())
+            .from_writer(w)
+    }
+}
+
+/// How far a sweep has come, passed to the progress callback after every finished job.
+#[derive(Debug)]
+pub struct Progress<'a> {
+    pub done: usize,
+    pub total: usize,
+    pub report: &'a JobReport,
+}
+
+/// Runs the jobs on at most `workers` threads. Traces go to the output directory, together with
+/// the `index` file, which gets a row as soon as a job finishes, so it survives aborted sweeps.
+/// A job that fails or panics is recorded in the index and does not stop the others.
+pub fn run_jobs<F>(
+    jobs: Vec<Job>,
+    output: &Output,
+    workers: usize,
+    mut progress: F,
+) -> std::io::Result<Vec<JobReport>>
+where
+    F: FnMut(Progress),
+{
+    std::fs::create_dir_all(&output.dir)?;
+    let mut index = output.writer(std::fs::File::create(output.path("index"))?);
+
+    let total = jobs.len();
+    let queue = Arc::new(Mutex::new(
+        jobs.into_iter().enumerate().collect::<VecDeque<_>>(),
+    ));
+    let (tx, rx) = mpsc::channel();
+
+    let handles: Vec<_> = (0..workers.clamp(1, total.max(1)))
+        .map(|_| {
+            let queue = queue.clone();
+            let tx = tx.clone();
+            let output = output.clone();
+            std::thread::spawn(move || loop {
+                let Some((i, job)) = queue.lock().unwrap().pop_front() else {
+                    break;
+                };
+
+                let start = Instant::now();
+                let result =
+                    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| job.run(&output)))
+                        .unwrap_or_else(|panic| Err(JobError::Panic(panic_message(panic))));
+
+                let file = output.path(&job.label());
+                let report = JobReport::new(&job, file, start.elapsed(), result);
+                if tx.send((i, report)).is_err() {
+                    break;
+                }
+            })
+        })
+        .collect();
+    drop(tx);
+
+    let mut reports: Vec<Option<JobReport>> = vec![None; total];
+    for (done, (i, report)) in rx.into_iter().enumerate() {
+        index.serialize(&report)?;
+        index.flush()?;
+        progress(Progress {
+            done: done + 1,
+            total,
+            report: &report,
+        });
+        reports[i] = Some(report);
+    }
+
+    for handle in handles {
+        // workers catch panics of the jobs, so they only fail if something is badly broken
+        handle.join().expect("sweep worker panicked");
+    }
+
+    Ok(reports.into_iter().flatten().collect())
+}
+
+fn panic_message(panic: Box<dyn std::any::Any + Send>) -> String {
+    if let Some(msg) = panic.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = panic.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// Writes the trace, one row per entry, and returns the number of rows.
+pub fn write_trace<S>(
+    path: &Path,
+    format: OutputFormat,
+    trace: &Trace<S::Item, SimObject>,
+) -> std::io::Result<usize>
+where
+    S: Simulator,
+    SimObject: unionize::Object<S::Item>,
+{
+    let f = std::fs::File::create(path)?;
+    let mut sink = CsvSink::<S, _>::new(trace_writer(f, format));
+
+    for (meta, entry) in trace.entries() {
+        sink.record(meta, entry.clone())?;
+    }
+    sink.flush()?;
+
+    Ok(sink.n_entries())
+}
+
+/// Trace rows have the columns of the meta and the record, so the writer has to be flexible.
+fn trace_writer<W: std::io::Write>(w: W, format: OutputFormat) -> csv::Writer<W> {
+    csv::WriterBuilder::new()
+        .flexible(true)
+        .delimiter(format.delimiter())
+        .from_writer(w)
+}
+
+/// Streams the trace to `f`, and the transcripts to their own writer, if given.
+fn trace_sink<S>(
+    f: std::fs::File,
+    format: OutputFormat,
+    transcripts: Option<csv::Writer<std::fs::File>>,
+) -> CsvSink<S, std::fs::File>
+where
+    S: Simulator,
+    SimObject: unionize::Object<S::Item>,
+{
+    let sink = CsvSink::new(trace_writer(f, format));
+    match transcripts {
+        Some(transcripts) => sink.with_transcripts(transcripts),
+        None => sink,
+    }
+}
+
+/// Writes one row per post, see [`PropagationRecord`](crate::scenarios::dynamic::PropagationRecord).
+pub fn write_propagation(
+    path: &Path,
+    format: OutputFormat,
+    propagation: &Propagation,
+) -> std::io::Result<()> {
+    let f = std::fs::File::create(path)?;
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(format.delimiter())
+        .from_writer(f);
+
+    for record in propagation.records() {
+        wtr.serialize(record)?;
+    }
+    wtr.flush()
+}
+
+/// Writes one row per completed sync, see
+/// [`SyncTimeRecord`](crate::scenarios::dynamic::SyncTimeRecord).
+pub fn write_sync_times(
+    path: &Path,
+    format: OutputFormat,
+    sync_times: &SyncTimes,
+) -> std::io::Result<()> {
+    let f = std::fs::File::create(path)?;
+    let mut wtr = csv::WriterBuilder::new()
+        .delimiter(format.delimiter())
+        .from_writer(f);
+
+    for record in sync_times.records() {
+        wtr.serialize(record)?;
+    }
+    wtr.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run_jobs, Grid, Job, Output, OutputFormat, Split, Suite};
+    use crate::experiments::ExperimentConfig;
+    use crate::scenarios::dynamic::{Link, Network, SimDuration, SimOptions, SyncFailurePolicy};
+
+    #[test]
+    fn sweep_writes_files_and_index() {
+        let out_dir = std::env::temp_dir().join(format!("sweep-test-{}", std::process::id()));
+        let grid = Grid {
+            base: ExperimentConfig {
+                length: SimDuration::DAY,
+                ..ExperimentConfig::new(2, 2)
+            },
+            suites: Suite::ALL.to_vec(),
+            splits: vec![Split::Fixed(2), Split::Dynamic],
+            thresholds: vec![4],
+            seeds: vec![0, 1],
+        };
+
+        let jobs = grid.jobs();
+        assert_eq!(jobs.len(), 8);
+
+        let mut progress = vec![];
+        let output = Output::new(&out_dir, OutputFormat::Csv);
+        let reports = run_jobs(jobs, &output, 3, |p| progress.push((p.done, p.total))).unwrap();
+
+        assert_eq!(progress.last(), Some(&(8, 8)));
+        assert_eq!(reports.len(), 8);
+        for report in &reports {
+            // the uniform suite has no dynamic split
+            let expect_ok = report.suite == Suite::Timestamped || !report.dynamic_split;
+            assert_eq!(report.is_ok(), expect_ok, "{report:?}");
+            if let Some(file) = &report.file {
+                assert!(file.exists());
+                let propagation = out_dir.join(format!("{}_propagation.csv", report.label));
+                assert!(propagation.exists());
+            }
+        }
+
+        let index = std::fs::read_to_string(out_dir.join("index.csv")).unwrap();
+        assert_eq!(index.lines().count(), 9);
+        assert!(index.contains("timestamped_dyn_4_s1"));
+        assert!(index.lines().next().unwrap().contains(",aborted_at,"));
+        assert!(reports.iter().all(|report| report.aborted_at.is_none()));
+
+        // runs that stopped at a failed sync are not reported as complete
+        let aborting = Job::new(
+            Suite::Uniform,
+            0,
+            ExperimentConfig {
+                length: SimDuration::DAY,
+                max_rounds: Some(3),
+                options: SimOptions {
+                    on_sync_failure: SyncFailurePolicy::Abort,
+                    ..Default::default()
+                },
+                ..ExperimentConfig::new(2, 2)
+            },
+        );
+        let reports = run_jobs(vec![aborting], &output, 1, |_| {}).unwrap();
+        assert!(reports[0].is_ok());
+        let aborted_at = reports[0].aborted_at.unwrap();
+        assert!(aborted_at < SimDuration::DAY.0);
+        let index = std::fs::read_to_string(out_dir.join("index.csv")).unwrap();
+        assert!(index.contains(&format!(",{aborted_at},")));
+
+        std::fs::remove_dir_all(out_dir).unwrap();
+    }
+
+    #[test]
+    fn sync_times_with_network() {
+        let out_dir = std::env::temp_dir().join(format!("sync-times-test-{}", std::process::id()));
+        std::fs::create_dir_all(&out_dir).unwrap();
+        let link = Link::new(std::time::Duration::from_millis(200), Some(10_000));
+        let job = Job::new(
+            Suite::Uniform,
+            0,
+            ExperimentConfig {
+                length: SimDuration::DAY,
+                options: SimOptions {
+                    network: Some(Network::new(link)),
+                    ..Default::default()
+                },
+                ..ExperimentConfig::new(2, 2)
+            },
+        );
+        let output = Output::new(&out_dir, OutputFormat::Csv);
+        job.run(&output).unwrap();
+
+        let sync_times =
+            std::fs::read_to_string(out_dir.join(format!("{}_sync_times.csv", job.label())))
+                .unwrap();
+        let mut lines = sync_times.lines();
+        assert_eq!(
+            lines.next(),
+            Some("initiator_party_id,responder_party_id,started_at,completed_at,duration_ms")
+        );
+        assert!(lines.count() > 0);
+
+        std::fs::remove_dir_all(out_dir).unwrap();
+    }
+
+    #[test]
+    fn transcripts_on_request() {
+        let out_dir = std::env::temp_dir().join(format!("transcripts-test-{}", std::process::id()));
+        std::fs::create_dir_all(&out_dir).unwrap();
+        let output = Output::new(&out_dir, OutputFormat::Csv);
+        let job = |record_transcripts| {
+            let config = ExperimentConfig {
+                length: SimDuration::DAY,
+                options: SimOptions {
+                    record_transcripts,
+                    ..Default::default()
+                },
+                ..ExperimentConfig::new(2, 2)
+            };
+            Job::new(Suite::Timestamped, 0, config)
+        };
+        let transcripts = out_dir.join(format!("{}_transcripts.csv", job(true).label()));
+
+        job(false).run(&output).unwrap();
+        assert!(!transcripts.exists());
+
+        job(true).run(&output).unwrap();
+        let transcripts = std::fs::read_to_string(transcripts).unwrap();
+        let mut lines = transcripts.lines();
+        assert!(lines
+            .next()
+            .unwrap()
+            .starts_with("time,initiator_party_id,"));
+        assert!(lines.count() > 0);
+
+        std::fs::remove_dir_all(out_dir).unwrap();
+    }
+}
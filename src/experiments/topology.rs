@@ -0,0 +1,380 @@
+//! Generates who syncs with whom, so scenarios with hundreds of parties don't have to list their
+//! sync partners by hand. A [`Topology`] describes the shape, [`Topology::generate`] makes a
+//! [`Graph`] of that shape, and [`Graph::sync_triggers`] turns its edges into sync triggers.
+//!
+//! Edges are undirected, but every sync has an initiator: the party with the higher id starts the
+//! sync with the party with the lower one. In a star or a two-tier topology that is the client.
+
+use std::collections::BTreeSet;
+
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::scenarios::dynamic::{Event, Probability, Triggers};
+
+/// The shape of a graph, see [`Topology::generate`].
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Topology {
+    /// Every party is connected to party 0.
+    Star,
+    /// Every party is connected to the next one, and the last one to party 0.
+    Ring,
+    /// Every party is connected to `degree` others, picked at random.
+    RandomRegular { degree: usize },
+    /// Every two parties are connected with probability `p`.
+    ErdosRenyi { p: f64 },
+    /// Parties join one after the other and connect to `m` of the parties before them, preferring
+    /// the ones with many connections. The first `m + 1` parties are all connected.
+    BarabasiAlbert { m: usize },
+    /// Parties `0..servers` are all connected with each other, and every other party is connected
+    /// to one of them, in turn.
+    TwoTier { servers: usize },
+}
+
+impl Topology {
+    /// Makes a graph of this shape between parties `0..n_parties`.
+    pub fn generate<R: Rng>(&self, n_parties: usize, rng: &mut R) -> Result<Graph, TopologyError> {
+        let mut graph = Graph::new(n_parties);
+        match *self {
+            Topology::Star => {
+                for party_id in 1..n_parties {
+                    graph.connect(0, party_id);
+                }
+            }
+            Topology::Ring => {
+                for party_id in 1..n_parties {
+                    graph.connect(party_id - 1, party_id);
+                }
+                if n_parties > 2 {
+                    graph.connect(0, n_parties - 1);
+                }
+            }
+            Topology::RandomRegular { degree } => {
+                if degree >= n_parties.max(1) || (n_parties * degree) % 2 == 1 {
+                    return Err(TopologyError::InvalidDegree { degree, n_parties });
+                }
+                graph = random_regular(n_parties, degree, rng);
+            }
+            Topology::ErdosRenyi { p } => {
+                if !(0.0..=1.0).contains(&p) {
+                    return Err(TopologyError::InvalidProbability(p));
+                }
+                for a in 0..n_parties {
+                    for b in a + 1..n_parties {
+                        if rng.gen_bool(p) {
+                            graph.connect(a, b);
+                        }
+                    }
+                }
+            }
+            Topology::BarabasiAlbert { m } => {
+                if m == 0 || m >= n_parties {
+                    return Err(TopologyError::InvalidDegree {
+                        degree: m,
+                        n_parties,
+                    });
+                }
+                // every party appears once per connection, so sampling from this prefers parties
+                // with many connections
+                let mut ends = vec![];
+                for a in 0..=m {
+                    for b in a + 1..=m {
+                        graph.connect(a, b);
+                        ends.extend([a, b]);
+                    }
+                }
+                for party_id in m + 1..n_parties {
+                    let mut targets = BTreeSet::new();
+                    while targets.len() < m {
+                        targets.insert(ends[rng.gen_range(0..ends.len())]);
+                    }
+                    for target in targets {
+                        graph.connect(target, party_id);
+                        ends.extend([target, party_id]);
+                    }
+                }
+            }
+            Topology::TwoTier { servers } => {
+                if servers == 0 || servers > n_parties {
+                    return Err(TopologyError::InvalidServers { servers, n_parties });
+                }
+                for a in 0..servers {
+                    for b in a + 1..servers {
+                        graph.connect(a, b);
+                    }
+                }
+                for party_id in servers..n_parties {
+                    graph.connect((party_id - servers) % servers, party_id);
+                }
+            }
+        }
+        Ok(graph)
+    }
+}
+
+/// Pairs up `degree` stubs per party at random, skipping pairs that would connect a party with
+/// itself or connect two parties twice, and starts over if only such pairs are left.
+fn random_regular<R: Rng>(n_parties: usize, degree: usize, rng: &mut R) -> Graph {
+    'attempt: loop {
+        let mut graph = Graph::new(n_parties);
+        let mut stubs: Vec<usize> = (0..n_parties)
+            .flat_map(|party_id| std::iter::repeat_n(party_id, degree))
+            .collect();
+
+        let mut misses = 0;
+        while !stubs.is_empty() {
+            let i = rng.gen_range(0..stubs.len());
+            let j = rng.gen_range(0..stubs.len());
+            let (a, b) = (stubs[i], stubs[j]);
+            if a == b || graph.is_connected_to(a, b) {
+                misses += 1;
+                if misses > 100 && !has_valid_pair(&graph, &stubs) {
+                    continue 'attempt;
+                }
+                continue;
+            }
+
+            misses = 0;
+            graph.connect(a, b);
+            stubs.swap_remove(i.max(j));
+            stubs.swap_remove(i.min(j));
+        }
+        return graph;
+    }
+}
+
+fn has_valid_pair(graph: &Graph, stubs: &[usize]) -> bool {
+    let parties: BTreeSet<usize> = stubs.iter().copied().collect();
+    parties.iter().any(|a| {
+        parties
+            .iter()
+            .any(|b| a < b && !graph.is_connected_to(*a, *b))
+    })
+}
+
+/// Who is connected to whom, see [`Topology`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Graph {
+    n_parties: usize,
+    edges: BTreeSet<(usize, usize)>, // lower id first
+}
+
+impl Graph {
+    pub fn new(n_parties: usize) -> Self {
+        Graph {
+            n_parties,
+            edges: BTreeSet::new(),
+        }
+    }
+
+    pub fn connect(&mut self, a: usize, b: usize) {
+        assert!(a != b && a.max(b) < self.n_parties);
+        self.edges.insert((a.min(b), a.max(b)));
+    }
+
+    pub fn n_parties(&self) -> usize {
+        self.n_parties
+    }
+
+    /// The edges as (responder, initiator), i.e. lower id first.
+    pub fn edges(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.edges.iter().copied()
+    }
+
+    pub fn n_edges(&self) -> usize {
+        self.edges.len()
+    }
+
+    pub fn is_connected_to(&self, a: usize, b: usize) -> bool {
+        self.edges.contains(&(a.min(b), a.max(b)))
+    }
+
+    pub fn neighbors(&self, party_id: usize) -> Vec<usize> {
+        self.edges
+            .iter()
+            .filter_map(|&(a, b)| {
+                if a == party_id {
+                    Some(b)
+                } else if b == party_id {
+                    Some(a)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub fn degree(&self, party_id: usize) -> usize {
+        self.neighbors(party_id).len()
+    }
+
+    /// Whether every party can reach every other party, i.e. whether posts can get everywhere.
+    pub fn is_connected(&self) -> bool {
+        if self.n_parties == 0 {
+            return true;
+        }
+        let mut reached = BTreeSet::from([0]);
+        let mut todo = vec![0];
+        while let Some(party_id) = todo.pop() {
+            for neighbor in self.neighbors(party_id) {
+                if reached.insert(neighbor) {
+                    todo.push(neighbor);
+                }
+            }
+        }
+        reached.len() == self.n_parties
+    }
+
+    /// One probabilistic sync trigger per edge. `rate` gets the initiator and the responder and
+    /// says how often they sync.
+    pub fn sync_triggers<F: Fn(usize, usize) -> Probability>(&self, rate: F) -> Triggers {
+        let probabilistic = self
+            .edges()
+            .map(|(responder, initiator)| {
+                (
+                    initiator,
+                    rate(initiator, responder),
+                    Event::Sync(responder),
+                )
+            })
+            .collect();
+        Triggers::new(Default::default(), probabilistic)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TopologyError {
+    InvalidDegree { degree: usize, n_parties: usize },
+    InvalidProbability(f64),
+    InvalidServers { servers: usize, n_parties: usize },
+}
+
+impl std::fmt::Display for TopologyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TopologyError::InvalidDegree { degree, n_parties } => {
+                write!(f, "no graph of {n_parties} parties has degree {degree}")
+            }
+            TopologyError::InvalidProbability(p) => {
+                write!(f, "edge probability {p} is not between 0 and 1")
+            }
+            TopologyError::InvalidServers { servers, n_parties } => {
+                write!(f, "can't have {servers} servers among {n_parties} parties")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TopologyError {}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::{Topology, TopologyError};
+    use crate::scenarios::dynamic::{Event, Frequency, Probability, SimDuration};
+
+    fn rng() -> ChaCha8Rng {
+        ChaCha8Rng::from_seed([0u8; 32])
+    }
+
+    #[test]
+    fn shapes() {
+        let n = 200;
+        let generate = |topology: Topology| topology.generate(n, &mut rng()).unwrap();
+
+        let star = generate(Topology::Star);
+        assert_eq!(star.degree(0), n - 1);
+        assert_eq!(star.n_edges(), n - 1);
+
+        let ring = generate(Topology::Ring);
+        assert!((0..n).all(|party_id| ring.degree(party_id) == 2));
+        assert!(ring.is_connected());
+
+        let regular = generate(Topology::RandomRegular { degree: 5 });
+        assert!((0..n).all(|party_id| regular.degree(party_id) == 5));
+        assert_eq!(regular.n_edges(), n * 5 / 2);
+
+        let sparse = generate(Topology::ErdosRenyi { p: 0.0 });
+        assert_eq!(sparse.n_edges(), 0);
+        assert!(!sparse.is_connected());
+        let dense = generate(Topology::ErdosRenyi { p: 0.1 });
+        let expected = n * (n - 1) / 2 / 10;
+        assert!(dense.n_edges().abs_diff(expected) < expected / 5);
+
+        let scale_free = generate(Topology::BarabasiAlbert { m: 2 });
+        assert_eq!(scale_free.n_edges(), 3 + (n - 3) * 2);
+        assert!(scale_free.is_connected());
+        assert!((0..n).all(|party_id| scale_free.degree(party_id) >= 2));
+        // the early parties collect most connections
+        assert!(scale_free.degree(0) > 10);
+
+        let two_tier = generate(Topology::TwoTier { servers: 4 });
+        assert_eq!(two_tier.neighbors(0)[..5], [1, 2, 3, 4, 8]);
+        assert_eq!(two_tier.degree(0), 3 + (n - 4) / 4);
+        assert!((4..8).all(|party_id| two_tier.neighbors(party_id) == vec![party_id - 4]));
+        assert!(two_tier.is_connected());
+
+        // the same seed gives the same graph
+        assert_eq!(regular, generate(Topology::RandomRegular { degree: 5 }));
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        let generate = |topology: Topology, n| topology.generate(n, &mut rng()).unwrap_err();
+        assert_eq!(
+            generate(Topology::RandomRegular { degree: 3 }, 5),
+            TopologyError::InvalidDegree {
+                degree: 3,
+                n_parties: 5
+            }
+        );
+        assert!(matches!(
+            generate(Topology::RandomRegular { degree: 5 }, 5),
+            TopologyError::InvalidDegree { .. }
+        ));
+        assert!(matches!(
+            generate(Topology::BarabasiAlbert { m: 0 }, 5),
+            TopologyError::InvalidDegree { .. }
+        ));
+        assert_eq!(
+            generate(Topology::ErdosRenyi { p: 1.5 }, 5),
+            TopologyError::InvalidProbability(1.5)
+        );
+        assert!(matches!(
+            generate(Topology::TwoTier { servers: 6 }, 5),
+            TopologyError::InvalidServers { .. }
+        ));
+    }
+
+    #[test]
+    fn sync_triggers() {
+        let hourly = Probability::from_frequency(Frequency::from_period(SimDuration::HOUR));
+        let daily = Probability::from_frequency(Frequency::from_period(SimDuration::DAY));
+        let graph = Topology::TwoTier { servers: 2 }
+            .generate(5, &mut rng())
+            .unwrap();
+
+        // servers sync with each other less often than clients with their server
+        let triggers =
+            graph.sync_triggers(|initiator, _| if initiator < 2 { daily } else { hourly });
+        assert_eq!(triggers.max_party_id(), Some(4));
+        assert_eq!(
+            format!("{triggers:?}"),
+            format!(
+                "{:?}",
+                crate::scenarios::dynamic::Triggers::new(
+                    Default::default(),
+                    vec![
+                        (1, daily, Event::Sync(0)),
+                        (2, hourly, Event::Sync(0)),
+                        (4, hourly, Event::Sync(0)),
+                        (3, hourly, Event::Sync(1)),
+                    ]
+                )
+            )
+        );
+    }
+}
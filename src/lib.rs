@@ -1,3 +1,4 @@
 pub mod experiments;
 pub mod scenarios;
 pub mod suites;
+pub mod time_series;
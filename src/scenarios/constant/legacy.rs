@@ -15,6 +15,7 @@ use crate::scenarios::tree::Tree;
 use rand::prelude::*;
 use rand_chacha::ChaCha8Rng;
 
+#[allow(dead_code, reason = "the old benchmark, kept to compare against")]
 fn sync_10k_msgs<M, N, T>()
 where
     M: ProtocolMonoid<Item = UniformItem>,
@@ -42,20 +43,20 @@ where
     let mut rng = ChaCha8Rng::from_seed([23u8; 32]);
     for msg in &mut shared_msgs {
         rng.fill(&mut msg.0);
-        alice_tree.insert(msg.clone());
-        alice_object_store.insert(msg.clone(), (msg.clone(), true));
-        bob_tree.insert(msg.clone());
-        bob_object_store.insert(msg.clone(), (msg.clone(), true));
+        alice_tree.insert(*msg);
+        alice_object_store.insert(*msg, (*msg, true));
+        bob_tree.insert(*msg);
+        bob_object_store.insert(*msg, (*msg, true));
     }
     for msg in &mut alices_msgs {
         rng.fill(&mut msg.0);
-        alice_tree.insert(msg.clone());
-        alice_object_store.insert(msg.clone(), (msg.clone(), true));
+        alice_tree.insert(*msg);
+        alice_object_store.insert(*msg, (*msg, true));
     }
     for msg in &mut bobs_msgs {
         rng.fill(&mut msg.0);
-        bob_tree.insert(msg.clone());
-        bob_object_store.insert(msg.clone(), (msg.clone(), true));
+        bob_tree.insert(*msg);
+        bob_object_store.insert(*msg, (*msg, true));
     }
     println!("done after {:?}.", gen_start_time.elapsed());
 
@@ -1,39 +1,77 @@
 //! We do provide some helpers so we can say "they post about twice a day", and it gets transformed
 //! into that other form.
 //
-use std::{collections::BTreeMap, marker::PhantomData, rc::Rc};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, BinaryHeap},
+    io,
+    marker::PhantomData,
+};
 
-use rand::RngCore;
+use rand::{Rng, RngCore};
 use serde::{Deserialize, Serialize};
-use unionize::{
-    protocol::{ProtocolMonoid, RespondError},
-    Item, Node, Object,
+use unionize::{protocol::ProtocolMonoid, Item, Monoid, Node, Object};
+
+use super::{
+    protocol::{
+        ProtocolParams, ProtocolResult, Role, RunStats, SyncError, SyncErrorKind, Transcript,
+    },
+    tree::Tree,
 };
 
-use super::{protocol::RunStats, tree::Tree};
+mod propagation;
+pub use propagation::{Distribution, PostPropagation, Propagation, PropagationRecord};
+mod network;
+pub use network::{Link, Network, SyncTimeRecord, SyncTimes, TimeDistribution};
+mod observer;
+pub use observer::{Emitter, Metric, Observer};
+mod reader;
+pub use reader::{TraceReadError, TraceReader, TRACE_SCHEMA_VERSION};
+mod sink;
+pub use sink::{CountingSink, CsvSink, MemorySink, NullSink, TraceSink};
 
 pub type RunProtocolFn<S> = fn(
+    params: ProtocolParams,
     initiator_node: &<S as Simulator>::Node,
     initiator_objects: &BTreeMap<<S as Simulator>::Item, SimObject>,
     responder_node: &<S as Simulator>::Node,
     responder_objects: &BTreeMap<<S as Simulator>::Item, SimObject>,
-) -> Result<
-    (
-        Vec<SimObject>, // new objects for initiator
-        Vec<SimObject>, // and responder
-        RunStats,       // stats for initiator
-        RunStats,       // and responder
-    ),
-    RespondError<<S as Simulator>::Monoid>,
->;
+    transcript: Option<&mut Transcript>,
+) -> ProtocolResult<SimObject>;
+
+/// The protocol implementation the simulated parties sync with, and its parameters.
+pub struct Protocol<S: Simulator>
+where
+    SimObject: Object<S::Item>,
+{
+    pub run: RunProtocolFn<S>,
+    pub params: ProtocolParams,
+}
+
+impl<S: Simulator> Clone for Protocol<S>
+where
+    SimObject: Object<S::Item>,
+{
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<S: Simulator> Copy for Protocol<S> where SimObject: Object<S::Item> {}
+
+impl<S: Simulator> Protocol<S>
+where
+    SimObject: Object<S::Item>,
+{
+    pub fn new(run: RunProtocolFn<S>, params: ProtocolParams) -> Self {
+        Protocol { run, params }
+    }
+}
 
 pub trait Simulator: Sized + Clone
 where
     SimObject: Object<Self::Item>,
 {
-    const ITEM_SIZE: usize;
-    const MONOID_SIZE: usize;
-
     type Item: Item + Serialize + for<'de2> Deserialize<'de2>;
     type Monoid: ProtocolMonoid<Item = Self::Item, Encoded = Self::EncodedMonoid>;
     type Node: Node<Self::Monoid>;
@@ -45,40 +83,166 @@ where
         n_parties: usize,
         initial_triggers: Triggers,
         length: SimDuration,
-        run_protocol: RunProtocolFn<Self>,
+        protocol: Protocol<Self>,
+    ) -> Trace<Self::Item, SimObject> {
+        Self::sim_with_options(
+            rng,
+            n_parties,
+            initial_triggers,
+            length,
+            protocol,
+            SimOptions::default(),
+        )
+    }
+
+    fn sim_with_options<R: RngCore>(
+        rng: &mut R,
+        n_parties: usize,
+        initial_triggers: Triggers,
+        length: SimDuration,
+        protocol: Protocol<Self>,
+        options: SimOptions,
     ) -> Trace<Self::Item, SimObject> {
-        let mut state = SystemState::<Self>::new(n_parties, initial_triggers);
-        let mut trace = vec![];
-
-        for t in 0..length.0 {
-            let t = SimInstant(t);
-            // eprint!(".");
-            if let Some(triggers) = state.triggers.scheduled.get(&t) {
-                for (party_id, event) in triggers.clone() {
-                    let trace_meta = TraceMeta::new(t, party_id, &event);
-                    // eprint!("x");
-                    let trace_entry = state.handle_event(&event, t, party_id, run_protocol);
-                    trace.push((trace_meta, trace_entry));
+        let mut sink = MemorySink::new();
+        let outcome = Self::sim_into(
+            rng,
+            n_parties,
+            initial_triggers,
+            length,
+            protocol,
+            options,
+            &mut sink,
+        )
+        .expect("memory sinks don't fail");
+
+        Trace {
+            entries: sink.into_entries(),
+            propagation: outcome.propagation,
+            sync_times: outcome.sync_times,
+            aborted_at: outcome.aborted_at,
+        }
+    }
+
+    /// Like [`Simulator::sim_with_options`], but hands every trace entry to the sink as soon as
+    /// it happens instead of collecting them.
+    fn sim_into<R: RngCore, K: TraceSink<Self>>(
+        rng: &mut R,
+        n_parties: usize,
+        initial_triggers: Triggers,
+        length: SimDuration,
+        protocol: Protocol<Self>,
+        options: SimOptions,
+        sink: &mut K,
+    ) -> io::Result<SimOutcome> {
+        Self::sim_observed(
+            rng,
+            n_parties,
+            initial_triggers,
+            length,
+            protocol,
+            options,
+            sink,
+            &mut (),
+        )
+    }
+
+    /// Like [`Simulator::sim_into`], but calls the observer along the way.
+    #[allow(clippy::too_many_arguments)]
+    fn sim_observed<R: RngCore, K: TraceSink<Self>, O: Observer<Self>>(
+        rng: &mut R,
+        n_parties: usize,
+        initial_triggers: Triggers,
+        length: SimDuration,
+        protocol: Protocol<Self>,
+        options: SimOptions,
+        sink: &mut K,
+        observer: &mut O,
+    ) -> io::Result<SimOutcome> {
+        let mut state = SystemState::<Self>::with_options(n_parties, initial_triggers, options);
+        state.observe_rounds = observer.observes_rounds();
+        let end = SimInstant::zero() + length;
+        let sample_every = observer
+            .sample_every()
+            .map(|every| every.max(SimDuration(1)));
+        let mut next_sample = SimInstant::zero();
+
+        // Instead of rolling a die for every probabilistic trigger on every tick, each trigger
+        // samples the instant it fires next, and we jump straight to the earliest pending event.
+        state.queue.sample_pending(rng, SimInstant::zero());
+
+        'sim: while let Some(t) = state.next_instant() {
+            if t >= end {
+                break;
+            }
+
+            if let Some(every) = sample_every {
+                while next_sample <= t {
+                    state.sample(sink, observer, next_sample)?;
+                    next_sample = next_sample + every;
+                }
+            }
+
+            state.complete_syncs(sink, observer, t)?;
+
+            if let Some(triggers) = state.queue.scheduled.remove(&t) {
+                for (party_id, event) in triggers {
+                    state.step(rng, sink, observer, &event, t, party_id, protocol)?;
+                    if state.aborted_at.is_some() {
+                        break 'sim;
+                    }
                 }
             }
 
-            for i in 0..state.triggers.probabilistic.len() {
-                let possible_event = state.triggers.probabilistic[i].clone();
-                let roll = DiceRoll::roll(rng);
-                let (party_id, prob, event) = possible_event;
-                if prob.does_fire(&roll) {
-                    let trace_meta = TraceMeta::new(t, party_id, &event);
-                    // eprint!("+");
-                    let trace_entry = state.handle_event(&event, t, party_id, run_protocol);
-                    trace.push((trace_meta, trace_entry));
+            // probabilistic triggers added by scheduled events may already fire now
+            state.queue.sample_pending(rng, t);
+
+            while let Some(id) = state.queue.pop_firing(t) {
+                let (party_id, _, event) = state.queue.probabilistic[&id].clone();
+                state.step(rng, sink, observer, &event, t, party_id, protocol)?;
+                if state.aborted_at.is_some() {
+                    break 'sim;
                 }
+                state.queue.reschedule(rng, id, t + SimDuration(1));
+            }
+
+            // ...but the ones added by probabilistic events only fire from the next instant on
+            state.queue.sample_pending(rng, t + SimDuration(1));
+
+            // syncs that took no time at all
+            state.complete_syncs(sink, observer, t)?;
+
+            // events scheduled for the current instant while handling it are not run, same as
+            // when we used to tick through every instant.
+            state.queue.scheduled.remove(&t);
+        }
+
+        // nothing happens anymore, but the observer still gets its samples
+        if let (Some(every), None) = (sample_every, state.aborted_at) {
+            while next_sample < end {
+                state.sample(sink, observer, next_sample)?;
+                next_sample = next_sample + every;
             }
         }
 
-        Trace(trace)
+        Ok(SimOutcome {
+            propagation: state.propagation,
+            sync_times: state.sync_times,
+            aborted_at: state.aborted_at,
+        })
     }
 }
 
+/// What a simulation run leaves besides the trace entries.
+#[derive(Debug, Clone)]
+pub struct SimOutcome {
+    /// When each party first held each post.
+    pub propagation: Propagation,
+    /// How long the syncs took, see [`SimOptions::network`].
+    pub sync_times: SyncTimes,
+    /// When the simulation ended early because a sync failed, see [`SyncFailurePolicy::Abort`].
+    pub aborted_at: Option<SimInstant>,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct SimInstant(pub u64);
 
@@ -88,7 +252,7 @@ impl SimInstant {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct SimDuration(pub u64);
 
 impl std::ops::Add<SimDuration> for SimInstant {
@@ -111,6 +275,59 @@ impl SimDuration {
     }
 }
 
+/// Parses durations like `90min`, `12h`, `3d`, `2w`, `18mo` or `1y`. A number without unit is
+/// taken as minutes.
+impl std::str::FromStr for SimDuration {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let split_at = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (number, unit) = s.split_at(split_at);
+        let number: u64 = number
+            .parse()
+            .map_err(|_| format!("invalid duration {s:?}: expected a number followed by a unit"))?;
+
+        let unit = match unit.trim() {
+            "" | "m" | "min" => SimDuration::MINUTE,
+            "h" => SimDuration::HOUR,
+            "d" => SimDuration::DAY,
+            "w" => SimDuration::WEEK,
+            "mo" => SimDuration::MONTH,
+            "y" => SimDuration::YEAR,
+            unit => {
+                return Err(format!(
+                    "invalid duration {s:?}: unknown unit {unit:?}, use one of min, h, d, w, mo, y"
+                ))
+            }
+        };
+
+        number
+            .checked_mul(unit.0)
+            .map(SimDuration)
+            .ok_or_else(|| format!("invalid duration {s:?}: too long"))
+    }
+}
+
+/// Writes the duration in the largest unit that divides it, in the format it is parsed from.
+impl std::fmt::Display for SimDuration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let units = [
+            (SimDuration::YEAR, "y"),
+            (SimDuration::MONTH, "mo"),
+            (SimDuration::WEEK, "w"),
+            (SimDuration::DAY, "d"),
+            (SimDuration::HOUR, "h"),
+        ];
+        for (unit, name) in units {
+            if self.0 > 0 && self.0.is_multiple_of(unit.0) {
+                return write!(f, "{}{name}", self.0 / unit.0);
+            }
+        }
+        write!(f, "{}min", self.0)
+    }
+}
+
 impl std::ops::Mul<SimDuration> for u64 {
     type Output = SimDuration;
 
@@ -161,7 +378,7 @@ impl Frequency {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Probability(u64);
 
 impl Probability {
@@ -182,7 +399,24 @@ impl Probability {
     }
 
     pub fn does_fire(&self, roll: &DiceRoll<{ Self::ONE.0 }>) -> bool {
-        self.0 >= roll.0
+        self.0 > roll.0
+    }
+
+    /// Samples how many instants pass before an event that fires with this probability on every
+    /// instant actually fires, i.e. draws from the geometric distribution. Returns `None` if the
+    /// probability is zero and the event never fires.
+    pub fn sample_wait<R: RngCore>(&self, rng: &mut R) -> Option<SimDuration> {
+        if self.0 == 0 {
+            return None;
+        }
+        if self.0 >= Self::ONE.0 {
+            return Some(SimDuration::zero());
+        }
+
+        let p = self.0 as f64 / Self::ONE.0 as f64;
+        let u = 1.0 - rng.gen::<f64>();
+        let wait = (u.ln() / (-p).ln_1p()).floor();
+        Some(SimDuration(wait as u64))
     }
 }
 
@@ -194,7 +428,7 @@ impl<const SIDES: u64> DiceRoll<SIDES> {
         loop {
             let mut sample: u64 = rng.next_u64();
             let bits = SIDES.ilog2() + 1;
-            sample = sample & ((1 << bits) - 1);
+            sample &= (1 << bits) - 1;
             if sample < SIDES {
                 break Self(sample);
             }
@@ -207,6 +441,10 @@ pub struct SimObject {
     pub author: usize,
     pub post_id: usize,
     pub timestamp: SimInstant,
+    /// Makes this a tombstone for the given object. Parties that hold the tombstone drop the
+    /// object and don't take it again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deletes: Option<Box<SimObject>>,
 }
 
 impl<I: Item> SimObjecty<I> for SimObject
@@ -222,50 +460,273 @@ where
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Event {
     Post,
     Sync(usize), // partner's party id
-    DropProbabilities(#[serde(skip_serializing)] ProbabilisticEventFilterFn),
-    AddProbabilities(#[serde(skip_serializing)] Vec<(usize, Probability, Event)>),
+    /// A sync with a partner picked when the event fires, see [`PeerSelection`].
+    SyncWith(PeerSelection),
+    DropProbabilities(EventFilter),
+    AddProbabilities(Vec<(usize, Probability, Event)>),
     ScheduleRelative(SimDuration, Vec<(usize, Event)>),
     Repeat(SimDuration, Box<Event>),
+    /// Behaves like the inner event, but can be told apart by an [`EventFilter::Tag`].
+    Tagged(String, Box<Event>),
+    /// The party joins with the given objects. Parties are there from the start, so parties that
+    /// join later leave at instant 0 first. A party that is already there starts over.
+    Join(Seed),
+    /// The party leaves and drops its objects. Its posts and syncs, and syncs with it, are
+    /// skipped until it joins again.
+    Leave,
+    /// The party drops its objects, but stays.
+    Wipe,
+    /// The party posts a tombstone for one of the objects it holds.
+    Delete(Deletion),
+    /// The party drops the objects outside the retention, and from then on neither takes nor
+    /// syncs objects older than the ones it kept.
+    Gc(Retention),
+    /// Splits the parties in two: the given ones and all others. Syncs between the two sides are
+    /// skipped until [`Event::Heal`]. Replaces the partition before, if there is one. The party
+    /// the event belongs to doesn't matter.
+    Partition(Vec<usize>),
+    /// Ends the partition. The first sync of every pair of parties it separated is followed by a
+    /// [`TraceEntry::HealingSync`].
+    Heal,
 }
 
-impl Event {
-    pub fn drop_probabilities<F: Fn(&(usize, Probability, Event)) -> bool + 'static>(f: F) -> Self {
-        Event::DropProbabilities(ProbabilisticEventFilterFn(Rc::new(f)))
+/// Which objects a party keeps when it collects garbage, see [`Event::Gc`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Retention {
+    /// The objects posted at most this long before the collection.
+    MaxAge(SimDuration),
+    /// The newest objects. Objects as old as the oldest of them are kept as well.
+    MaxItems(usize),
+}
+
+/// Parses `age:<duration>` or `items:<n>`, e.g. `age:30d`.
+impl std::str::FromStr for Retention {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("age", age)) => Ok(Retention::MaxAge(age.parse()?)),
+            Some(("items", n)) => match n.parse() {
+                Ok(0) | Err(_) => Err(format!("invalid number of items {n:?}")),
+                Ok(n) => Ok(Retention::MaxItems(n)),
+            },
+            _ => Err(format!(
+                "unknown retention {s:?}, use age:<duration> or items:<n>"
+            )),
+        }
     }
+}
+
+/// The object a [`Event::Delete`] deletes.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Deletion {
+    /// The party's own most recent post.
+    Newest,
+    /// The party's own oldest post.
+    Oldest,
+    /// Any post, e.g. by a moderator.
+    Post { author: usize, post_id: usize },
+}
+
+/// How a [`Event::SyncWith`] picks the partner. Only parties that are there are picked, and
+/// never the party itself. An empty list of peers stands for all other parties.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerSelection {
+    /// Any of the peers, all equally likely.
+    Random(Vec<usize>),
+    /// The peers in turn.
+    RoundRobin(Vec<usize>),
+    /// The peer the party synced with longest ago, peers it never synced with first. Ties go to
+    /// the peer listed first.
+    LeastRecent(Vec<usize>),
+    /// Any of the peers, with a likelihood proportional to its weight.
+    Weighted(Vec<(usize, u32)>),
+}
+
+impl PeerSelection {
+    /// The peers in the list, not counting weights.
+    pub fn peers(&self) -> Vec<usize> {
+        match self {
+            PeerSelection::Random(peers)
+            | PeerSelection::RoundRobin(peers)
+            | PeerSelection::LeastRecent(peers) => peers.clone(),
+            PeerSelection::Weighted(peers) => peers.iter().map(|(peer, _)| *peer).collect(),
+        }
+    }
+}
+
+/// What a party holds when it joins.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Seed {
+    Empty,
+    /// The objects of party `from` that were posted at least `age` before the join, like a
+    /// backup of it.
+    Snapshot {
+        from: usize,
+        age: SimDuration,
+    },
+}
 
+impl Event {
     pub fn repeat(period: SimDuration, event: Event) -> Self {
         Event::Repeat(period, Box::new(event))
     }
-}
 
-#[derive(Clone)]
-pub struct ProbabilisticEventFilterFn(pub Rc<dyn Fn(&(usize, Probability, Event)) -> bool>);
+    pub fn tagged<T: Into<String>>(tag: T, event: Event) -> Self {
+        Event::Tagged(tag.into(), Box::new(event))
+    }
 
-impl PartialEq for ProbabilisticEventFilterFn {
-    fn eq(&self, other: &Self) -> bool {
-        true
+    /// The event without its tags.
+    pub fn untagged(&self) -> &Event {
+        let mut event = self;
+        while let Event::Tagged(_, inner) = event {
+            event = inner;
+        }
+        event
     }
-}
 
-impl Eq for ProbabilisticEventFilterFn {}
+    /// The tags of the event, outermost first.
+    pub fn tags(&self) -> impl Iterator<Item = &str> {
+        let mut event = self;
+        std::iter::from_fn(move || match event {
+            Event::Tagged(tag, inner) => {
+                event = inner;
+                Some(tag.as_str())
+            }
+            _ => None,
+        })
+    }
 
-impl std::fmt::Debug for ProbabilisticEventFilterFn {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "<filter function>")
+    /// What kind of event this is. Tags are looked through.
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Event::Post => EventKind::Post,
+            Event::Sync(_) | Event::SyncWith(_) => EventKind::Sync,
+            Event::DropProbabilities(_) => EventKind::DropProbabilities,
+            Event::AddProbabilities(_) => EventKind::AddProbabilities,
+            Event::ScheduleRelative(_, _) => EventKind::ScheduleRelative,
+            Event::Repeat(_, _) => EventKind::Repeat,
+            Event::Tagged(_, event) => event.kind(),
+            Event::Join(_) => EventKind::Join,
+            Event::Leave => EventKind::Leave,
+            Event::Wipe => EventKind::Wipe,
+            Event::Delete(_) => EventKind::Delete,
+            Event::Gc(_) => EventKind::Gc,
+            Event::Partition(_) => EventKind::Partition,
+            Event::Heal => EventKind::Heal,
+        }
     }
+
+    /// The highest party id this event refers to, including nested events.
+    pub fn max_party_id(&self) -> Option<usize> {
+        match self {
+            Event::Post
+            | Event::DropProbabilities(_)
+            | Event::Join(Seed::Empty)
+            | Event::Leave
+            | Event::Wipe
+            | Event::Delete(Deletion::Newest | Deletion::Oldest)
+            | Event::Gc(_)
+            | Event::Heal => None,
+            Event::Delete(Deletion::Post { author, .. }) => Some(*author),
+            Event::Sync(partner_party_id) => Some(*partner_party_id),
+            Event::SyncWith(selection) => selection.peers().into_iter().max(),
+            Event::Join(Seed::Snapshot { from, .. }) => Some(*from),
+            Event::Partition(side) => side.iter().copied().max(),
+            Event::AddProbabilities(probs) => probs
+                .iter()
+                .filter_map(|(party_id, _, event)| max_id(*party_id, event))
+                .max(),
+            Event::ScheduleRelative(_, entries) => entries
+                .iter()
+                .filter_map(|(party_id, event)| max_id(*party_id, event))
+                .max(),
+            Event::Repeat(_, event) | Event::Tagged(_, event) => event.max_party_id(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Post,
+    Sync,
+    DropProbabilities,
+    AddProbabilities,
+    ScheduleRelative,
+    Repeat,
+    Join,
+    Leave,
+    Wipe,
+    Delete,
+    Gc,
+    Partition,
+    Heal,
+}
+
+fn max_id(party_id: usize, event: &Event) -> Option<usize> {
+    Some(event.max_party_id().map_or(party_id, |id| id.max(party_id)))
 }
 
-impl ProbabilisticEventFilterFn {
-    pub fn filter(&self, entry: &(usize, Probability, Event)) -> bool {
-        self.0(entry)
+pub type ProbabilisticTrigger = (usize, Probability, Event);
+
+/// Selects probabilistic triggers, e.g. the ones that stop when a party goes to sleep.
+/// `And(vec![])` matches every trigger, `Or(vec![])` none.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventFilter {
+    /// The party the trigger belongs to.
+    Party(usize),
+    Kind(EventKind),
+    /// Syncs with the given party. Syncs with a [`PeerSelection`] are not matched.
+    SyncPartner(usize),
+    Probability(Probability),
+    /// Events tagged with [`Event::Tagged`], also among other tags. Like the other filters, this
+    /// looks through tags, but not into repeated or scheduled events.
+    Tag(String),
+    /// Exactly this event.
+    Event(Box<Event>),
+    And(Vec<EventFilter>),
+    Or(Vec<EventFilter>),
+    Not(Box<EventFilter>),
+}
+
+impl EventFilter {
+    /// Matches exactly the given trigger.
+    pub fn trigger((party_id, prob, event): &ProbabilisticTrigger) -> Self {
+        EventFilter::And(vec![
+            EventFilter::Party(*party_id),
+            EventFilter::Probability(*prob),
+            EventFilter::Event(Box::new(event.clone())),
+        ])
+    }
+
+    pub fn matches(&self, trigger: &ProbabilisticTrigger) -> bool {
+        let (party_id, prob, event) = trigger;
+        match self {
+            EventFilter::Party(id) => party_id == id,
+            EventFilter::Kind(kind) => event.kind() == *kind,
+            EventFilter::SyncPartner(partner) => *event.untagged() == Event::Sync(*partner),
+            EventFilter::Probability(p) => prob == p,
+            EventFilter::Tag(tag) => event.tags().any(|t| t == tag),
+            EventFilter::Event(e) => event == e.as_ref(),
+            EventFilter::And(filters) => filters.iter().all(|filter| filter.matches(trigger)),
+            EventFilter::Or(filters) => filters.iter().any(|filter| filter.matches(trigger)),
+            EventFilter::Not(filter) => !filter.matches(trigger),
+        }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Triggers {
     scheduled: BTreeMap<SimInstant, Vec<(usize, Event)>>,
     probabilistic: Vec<(usize, Probability, Event)>,
@@ -292,26 +753,237 @@ impl Triggers {
         }
         self.probabilistic.append(&mut other.probabilistic);
     }
+
+    /// The highest party id any of the triggers refers to.
+    pub fn max_party_id(&self) -> Option<usize> {
+        let scheduled = self
+            .scheduled
+            .values()
+            .flatten()
+            .filter_map(|(party_id, event)| max_id(*party_id, event));
+        let probabilistic = self
+            .probabilistic
+            .iter()
+            .filter_map(|(party_id, _, event)| max_id(*party_id, event));
+
+        scheduled.chain(probabilistic).max()
+    }
 }
 
-impl Default for Triggers {
-    fn default() -> Self {
-        Triggers {
-            scheduled: BTreeMap::default(),
-            probabilistic: vec![],
+/// The events that are still to come in a running simulation.
+///
+/// Scheduled events are kept by the instant they run at. Probabilistic triggers get ids in the
+/// order they are added, which is also the order in which triggers firing at the same instant
+/// are handled. The instant each trigger fires next is sampled up front and kept in a heap.
+#[derive(Debug, Clone)]
+struct EventQueue {
+    scheduled: BTreeMap<SimInstant, Vec<(usize, Event)>>,
+    probabilistic: BTreeMap<u64, (usize, Probability, Event)>,
+    firing: BinaryHeap<Reverse<(SimInstant, u64)>>,
+    pending: Vec<u64>, // added, but next firing not sampled yet
+    next_id: u64,
+}
+
+impl EventQueue {
+    fn new(triggers: Triggers) -> Self {
+        let mut queue = EventQueue {
+            scheduled: triggers.scheduled,
+            probabilistic: BTreeMap::new(),
+            firing: BinaryHeap::new(),
+            pending: vec![],
+            next_id: 0,
+        };
+        queue.add_probabilistic(triggers.probabilistic);
+        queue
+    }
+
+    fn add_probabilistic<I: IntoIterator<Item = (usize, Probability, Event)>>(&mut self, probs: I) {
+        for prob in probs {
+            self.probabilistic.insert(self.next_id, prob);
+            self.pending.push(self.next_id);
+            self.next_id += 1;
+        }
+    }
+
+    /// Drops all probabilistic triggers matched by the filter and returns the number of
+    /// triggers before and after.
+    fn drop_probabilistic(&mut self, filter: &EventFilter) -> (usize, usize) {
+        let n_before = self.probabilistic.len();
+        self.probabilistic.retain(|_, entry| !filter.matches(entry));
+
+        let probabilistic = &self.probabilistic;
+        self.pending.retain(|id| probabilistic.contains_key(id));
+        self.firing
+            .retain(|Reverse((_, id))| probabilistic.contains_key(id));
+
+        (n_before, self.probabilistic.len())
+    }
+
+    /// Samples when the triggers added since the last call fire first, starting at `from`.
+    fn sample_pending<R: RngCore>(&mut self, rng: &mut R, from: SimInstant) {
+        for id in std::mem::take(&mut self.pending) {
+            self.reschedule(rng, id, from);
+        }
+    }
+
+    /// Samples when the trigger fires next, starting at `from`.
+    fn reschedule<R: RngCore>(&mut self, rng: &mut R, id: u64, from: SimInstant) {
+        if let Some((_, prob, _)) = self.probabilistic.get(&id) {
+            if let Some(wait) = prob.sample_wait(rng) {
+                self.firing.push(Reverse((from + wait, id)));
+            }
+        }
+    }
+
+    fn next_instant(&self) -> Option<SimInstant> {
+        let next_scheduled = self.scheduled.keys().next().copied();
+        let next_firing = self.firing.peek().map(|Reverse((t, _))| *t);
+
+        match (next_scheduled, next_firing) {
+            (Some(scheduled), Some(firing)) => Some(scheduled.min(firing)),
+            (scheduled, firing) => scheduled.or(firing),
+        }
+    }
+
+    /// Returns the id of the next trigger that fires at `t`, if any.
+    fn pop_firing(&mut self, t: SimInstant) -> Option<u64> {
+        match self.firing.peek() {
+            Some(Reverse((t_next, id))) if *t_next == t => {
+                let id = *id;
+                self.firing.pop();
+                Some(id)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Things a simulation run can do in addition to what it always does. They are off by default,
+/// because they cost memory or time.
+#[derive(Debug, Clone, Default)]
+pub struct SimOptions {
+    /// Attach a round-by-round transcript to every sync in the trace.
+    pub record_transcripts: bool,
+    /// Whether to check that both parties hold the same objects after every sync.
+    pub check_convergence: ConvergenceCheck,
+    /// What to do when a sync fails.
+    pub on_sync_failure: SyncFailurePolicy,
+    /// Add the whole event in Debug form to every entry, next to the structured columns. Nested
+    /// events make this column long.
+    pub record_event_debug: bool,
+    /// Make syncs take time. The objects arrive when the sync is over, which is recorded as a
+    /// [`TraceEntry::SyncCompleted`], and convergence is checked then.
+    pub network: Option<Network>,
+    /// Keep the objects that arrived before a sync failed, like an app that stores objects as
+    /// they come in. Otherwise a failed sync changes nothing. The objects are kept right when the
+    /// sync fails, also with a network.
+    pub keep_partial_syncs: bool,
+}
+
+/// What the simulation does after a sync failed. The failure is always recorded as a
+/// [`TraceEntry::SyncFailed`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncFailurePolicy {
+    /// Go on as if the sync never happened.
+    #[default]
+    Continue,
+    /// Try the sync again after the given time, up to `attempts` times in a row.
+    Retry { after: SimDuration, attempts: usize },
+    /// End the simulation, keeping the trace so far.
+    Abort,
+}
+
+/// Parses `continue`, `abort` or `retry:<duration>[:<attempts>]`, e.g. `retry:1h:3`. Retries
+/// default to three attempts.
+impl std::str::FromStr for SyncFailurePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let retry = |after: &str, attempts: usize| {
+            let after: SimDuration = after.parse()?;
+            if after == SimDuration::zero() {
+                return Err("retries must be at least a minute apart".to_string());
+            }
+            Ok(SyncFailurePolicy::Retry { after, attempts })
+        };
+
+        match s.split(':').collect::<Vec<_>>()[..] {
+            ["continue"] => Ok(SyncFailurePolicy::Continue),
+            ["abort"] => Ok(SyncFailurePolicy::Abort),
+            ["retry", after] => retry(after, 3),
+            ["retry", after, attempts] => match attempts.parse() {
+                Ok(attempts) => retry(after, attempts),
+                Err(_) => Err(format!("invalid number of attempts {attempts:?}")),
+            },
+            _ => Err(format!(
+                "unknown sync failure policy {s:?}, \
+                 use one of continue, abort, retry:<duration>[:<attempts>]"
+            )),
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConvergenceCheck {
+    #[default]
+    Off,
+    /// Add a [`TraceEntry::Diverged`] after every sync that left the parties with different
+    /// objects. With a [`SimOptions::network`], syncs are checked once they complete, and only
+    /// for the objects the parties held when they started.
+    Record,
+    /// Panic on the first sync that left the parties with different objects.
+    Panic,
+}
+
+impl std::str::FromStr for ConvergenceCheck {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(ConvergenceCheck::Off),
+            "record" => Ok(ConvergenceCheck::Record),
+            "panic" => Ok(ConvergenceCheck::Panic),
+            _ => Err(format!(
+                "unknown convergence check {s:?}, use one of off, record, panic"
+            )),
+        }
+    }
+}
+
+/// What two parties disagree on after syncing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence<I> {
+    pub responder_party_id: usize,
+    /// Items only the responder has.
+    pub missing_at_initiator: Vec<I>,
+    /// Items only the initiator has.
+    pub missing_at_responder: Vec<I>,
+    /// Whether the fingerprints of the trees' roots are equal. They can differ even if the
+    /// objects are the same, if a tree is out of step with the objects.
+    pub roots_match: bool,
+}
+
 // N: number of parties
 #[derive(Debug, Clone)]
 pub struct SystemState<S: Simulator>
 where
     SimObject: Object<S::Item>,
 {
-    triggers: Triggers,
+    queue: EventQueue,
     party_states: Vec<PartyState<S>>,
+    absent: BTreeSet<usize>,
     cur_post_id: usize,
+    propagation: Propagation,
+    options: SimOptions,
+    sync_failures: BTreeMap<(usize, usize), usize>, // consecutive failures by (initiator, responder)
+    last_synced: BTreeMap<(usize, usize), SimInstant>, // by (party, peer), in both directions
+    round_robin: BTreeMap<(usize, Vec<usize>), usize>, // next position by (party, peers)
+    partition: Option<Partition>,
+    healed: Option<(Partition, SimInstant)>, // the last partition, and when it healed
+    in_flight: BTreeMap<SimInstant, Vec<network::InFlight<S::Item>>>, // by when they complete
+    sync_times: SyncTimes,
+    aborted_at: Option<SimInstant>,
+    observe_rounds: bool, // record transcripts for the observer, even if the trace doesn't get them
     _phantom: PhantomData<S>,
 }
 
@@ -320,153 +992,1033 @@ where
     SimObject: Object<S::Item>,
 {
     pub fn new(n_parties: usize, initial_triggers: Triggers) -> Self {
+        Self::with_options(n_parties, initial_triggers, SimOptions::default())
+    }
+
+    pub fn with_options(n_parties: usize, initial_triggers: Triggers, options: SimOptions) -> Self {
         SystemState {
-            triggers: initial_triggers,
+            queue: EventQueue::new(initial_triggers),
             party_states: vec![PartyState::new(); n_parties],
+            absent: BTreeSet::new(),
             cur_post_id: 0,
+            propagation: Propagation::new(n_parties),
+            options,
+            sync_failures: BTreeMap::new(),
+            last_synced: BTreeMap::new(),
+            round_robin: BTreeMap::new(),
+            partition: None,
+            healed: None,
+            in_flight: BTreeMap::new(),
+            sync_times: SyncTimes::default(),
+            aborted_at: None,
+            observe_rounds: false,
             _phantom: PhantomData,
         }
     }
 
-    pub fn handle_event(
-        &mut self,
-        event: &Event,
-        time: SimInstant,
-        party_id: usize,
-
-        run_protocol: RunProtocolFn<S>,
-    ) -> TraceEntry<S::Item, SimObject> {
-        match event {
-            Event::Post => {
-                let obj = SimObject {
-                    author: party_id,
-                    post_id: self.cur_post_id,
-                    timestamp: time,
-                };
+    /// The states of all parties, including the ones that left, which hold nothing.
+    pub fn party_states(&self) -> &[PartyState<S>] {
+        &self.party_states
+    }
 
-                self.party_states[party_id].post(obj.clone());
-                self.cur_post_id += 1;
-                TraceEntry::Posted(obj)
-            }
-            Event::Sync(partner_party_id) => {
-                // eprint!("s{party_id}-{partner_party_id}");
-                let initiator_node = self.party_states[party_id].tree.node();
-                let responder_node = self.party_states[*partner_party_id].tree.node();
+    /// Whether the party is there, i.e. it exists and didn't leave.
+    pub fn is_present(&self, party_id: usize) -> bool {
+        party_id < self.party_states.len() && !self.absent.contains(&party_id)
+    }
 
-                let initiator_objects = &self.party_states[party_id].objects;
-                let responder_objects = &self.party_states[*partner_party_id].objects;
-                let (
-                    initiator_new_objects,
-                    responder_new_objects,
-                    initiator_stats,
-                    responder_stats,
-                ) = (run_protocol)(
-                    initiator_node,
-                    initiator_objects,
-                    responder_node,
-                    responder_objects,
-                )
-                .unwrap();
+    /// Whether a partition keeps the two parties from syncing.
+    pub fn is_partitioned(&self, a: usize, b: usize) -> bool {
+        self.partition
+            .as_ref()
+            .is_some_and(|partition| partition.separates(a, b))
+    }
 
-                for obj in initiator_new_objects {
-                    let initiator_state = &mut self.party_states[party_id];
-                    initiator_state.tree.insert(obj.to_item());
-                    initiator_state.objects.insert(obj.to_item(), obj);
-                }
+    /// When each party first held each post, so far.
+    pub fn propagation(&self) -> &Propagation {
+        &self.propagation
+    }
 
-                for obj in responder_new_objects {
-                    let responder_state = &mut self.party_states[*partner_party_id];
-                    responder_state.tree.insert(obj.to_item());
-                    responder_state.objects.insert(obj.to_item(), obj);
-                }
+    /// The next instant at which an event fires or a sync completes.
+    fn next_instant(&self) -> Option<SimInstant> {
+        let next_completion = self.in_flight.keys().next().copied();
+        match (self.queue.next_instant(), next_completion) {
+            (Some(event), Some(completion)) => Some(event.min(completion)),
+            (event, completion) => event.or(completion),
+        }
+    }
 
-                TraceEntry::Sync(*partner_party_id, initiator_stats, responder_stats)
+    /// Hands the objects of the syncs that are over by `time` to the parties, see
+    /// [`SimOptions::network`]. Parties that left in the meantime don't get them.
+    pub fn complete_syncs<K: TraceSink<S>, O: Observer<S>>(
+        &mut self,
+        sink: &mut K,
+        observer: &mut O,
+        time: SimInstant,
+    ) -> io::Result<()> {
+        while let Some(entry) = self.in_flight.first_entry() {
+            if *entry.key() > time {
+                break;
             }
-            Event::DropProbabilities(filter) => {
-                let mut new_probabilitistic = Vec::with_capacity(self.triggers.probabilistic.len());
-                for prob_tuple in &self.triggers.probabilistic {
-                    if !filter.filter(prob_tuple) {
-                        new_probabilitistic.push(prob_tuple.clone());
+            let (completed_at, syncs) = entry.remove_entry();
+
+            for sync in syncs {
+                let initiator_party_id = sync.initiator_party_id;
+                let responder_party_id = sync.responder_party_id;
+                for (party_id, objects) in [
+                    (initiator_party_id, &sync.initiator_new_objects),
+                    (responder_party_id, &sync.responder_new_objects),
+                ] {
+                    if self.is_present(party_id) {
+                        self.receive_all(party_id, objects, completed_at);
                     }
                 }
+                self.sync_times.completed(&sync, completed_at);
 
-                let n_old_probs = self.triggers.probabilistic.len();
-                let n_new_probs = new_probabilitistic.len();
-                self.triggers.probabilistic = new_probabilitistic;
-
-                TraceEntry::DropProbabilities(n_old_probs, n_new_probs)
-            }
-            Event::AddProbabilities(probs) => {
-                self.triggers.probabilistic.extend(probs.iter().cloned());
-                TraceEntry::AddProbabilities(probs.len())
-            }
-            Event::ScheduleRelative(t_rel, entries) => {
-                for entry in entries {
-                    self.triggers
-                        .scheduled
-                        .entry(time + *t_rel)
-                        .or_default()
-                        .push(entry.clone());
+                let trace_meta = TraceMeta::new(
+                    completed_at,
+                    initiator_party_id,
+                    &Event::Sync(responder_party_id),
+                    self.options.record_event_debug,
+                );
+                let trace_entry =
+                    TraceEntry::SyncCompleted(responder_party_id, sync.started_at, sync.duration);
+                let mut out = Emitter::default();
+                observer.after_event(
+                    self,
+                    completed_at,
+                    initiator_party_id,
+                    &trace_entry,
+                    &mut out,
+                );
+                sink.record(&trace_meta, trace_entry)?;
+
+                if self.options.check_convergence != ConvergenceCheck::Off {
+                    let divergence = self.compare(
+                        initiator_party_id,
+                        responder_party_id,
+                        sync.synced_items.as_ref(),
+                    );
+                    self.record_divergence(sink, &trace_meta, divergence)?;
                 }
-                TraceEntry::ScheduleRelative(entries.len())
-            }
-            Event::Repeat(t_rel_every, inner_event) => {
-                self.triggers
-                    .scheduled
-                    .entry(time + *t_rel_every)
-                    .or_default()
-                    .push((party_id, event.clone()));
-                self.handle_event(inner_event, time, party_id, run_protocol)
+                record_metrics(sink, &trace_meta, &mut out)?;
             }
         }
+        Ok(())
     }
-}
-
-#[derive(Debug, Clone)]
-pub struct PartyState<S: Simulator>
-where
-    SimObject: Object<S::Item>,
-{
-    tree: S::Tree,
-    objects: BTreeMap<S::Item, SimObject>,
-    _phantom: PhantomData<S>,
-}
 
-impl<S: Simulator> PartyState<S>
-where
-    SimObject: Object<S::Item>,
-{
-    pub fn new() -> Self {
-        PartyState {
-            tree: S::Tree::nil(),
-            objects: BTreeMap::new(),
-            _phantom: PhantomData,
+    /// Adds the objects a party got from a sync, and records when it first held them.
+    fn receive_all(&mut self, party_id: usize, objects: &[SimObject], time: SimInstant) {
+        for obj in self.party_states[party_id].receive_all(objects) {
+            self.propagation.received(party_id, obj, time);
         }
     }
 
-    pub fn post(&mut self, obj: SimObject) {
-        self.tree.insert(obj.to_item());
-        self.objects.insert(obj.to_item(), obj);
+    /// Records the divergence, or panics if the options say so.
+    fn record_divergence<K: TraceSink<S>>(
+        &self,
+        sink: &mut K,
+        trace_meta: &TraceMeta,
+        divergence: Option<Divergence<S::Item>>,
+    ) -> io::Result<()> {
+        let Some(divergence) = divergence else {
+            return Ok(());
+        };
+        if self.options.check_convergence == ConvergenceCheck::Panic {
+            panic!(
+                "parties {} and {} diverged after syncing at {:?}: {divergence:?}",
+                trace_meta.party_id, divergence.responder_party_id, trace_meta.time
+            );
+        }
+        sink.record(trace_meta, TraceEntry::Diverged(divergence))
     }
-}
 
-#[derive(Debug, Clone, Serialize)]
-pub struct TraceMeta {
-    time: SimInstant,
-    party_id: usize,
-    event: String,
-}
+    /// Calls the observer's sample hook and adds its metrics to the trace.
+    fn sample<K: TraceSink<S>, O: Observer<S>>(
+        &self,
+        sink: &mut K,
+        observer: &mut O,
+        time: SimInstant,
+    ) -> io::Result<()> {
+        let mut out = Emitter::default();
+        observer.sample(self, time, &mut out);
+        record_metrics(sink, &TraceMeta::sample(time), &mut out)
+    }
 
-impl TraceMeta {
-    fn new(time: SimInstant, party_id: usize, event: &Event) -> Self {
-        TraceMeta {
-            time,
-            party_id,
-            event: format!("{event:?}"),
+    /// Handles the event and hands what happened, and what the observer made of it, to the sink.
+    #[allow(clippy::too_many_arguments)]
+    pub fn step<R: RngCore, K: TraceSink<S>, O: Observer<S>>(
+        &mut self,
+        rng: &mut R,
+        sink: &mut K,
+        observer: &mut O,
+        event: &Event,
+        time: SimInstant,
+        party_id: usize,
+        protocol: Protocol<S>,
+    ) -> io::Result<()> {
+        let trace_meta = TraceMeta::new(time, party_id, event, self.options.record_event_debug);
+        let mut out = Emitter::default();
+        observer.before_event(self, time, party_id, event, &mut out);
+        record_metrics(sink, &trace_meta, &mut out)?;
+
+        let mut trace_entry = self.handle_event(rng, event, time, party_id, protocol);
+
+        if let TraceEntry::Sync(responder_party_id, _, _, transcript) = &mut trace_entry {
+            if self.observe_rounds {
+                for round in transcript.iter().flatten() {
+                    let responder_party_id = *responder_party_id;
+                    observer.after_round(self, time, party_id, responder_party_id, round, &mut out);
+                }
+            }
+            if !self.options.record_transcripts {
+                *transcript = None;
+            }
+        }
+
+        observer.after_event(self, time, party_id, &trace_entry, &mut out);
+
+        // with a network, the objects are still on their way
+        let divergence = match (&trace_entry, self.options.check_convergence) {
+            (_, ConvergenceCheck::Off) => None,
+            (TraceEntry::Sync(responder_party_id, ..), _) if self.options.network.is_none() => {
+                self.check_convergence(party_id, *responder_party_id)
+            }
+            _ => None,
+        };
+
+        let healing = match &trace_entry {
+            TraceEntry::Sync(responder_party_id, init, resp, _) => {
+                self.healing_sync(time, party_id, *responder_party_id, init, resp)
+            }
+            _ => None,
+        };
+
+        sink.record(&trace_meta, trace_entry)?;
+
+        if let Some(healing) = healing {
+            sink.record(&trace_meta, TraceEntry::HealingSync(healing))?;
+        }
+
+        self.record_divergence(sink, &trace_meta, divergence)?;
+        record_metrics(sink, &trace_meta, &mut out)
+    }
+
+    /// Describes the sync if it is the first one between two parties since the partition that
+    /// separated them healed.
+    fn healing_sync(
+        &mut self,
+        time: SimInstant,
+        initiator_party_id: usize,
+        responder_party_id: usize,
+        init: &RunStats,
+        resp: &RunStats,
+    ) -> Option<HealingSync> {
+        let (partition, healed_at) = self.healed.as_mut()?;
+        let pair = (
+            initiator_party_id.min(responder_party_id),
+            initiator_party_id.max(responder_party_id),
+        );
+        if !partition.separates(pair.0, pair.1) || !partition.healed_pairs.insert(pair) {
+            return None;
+        }
+
+        Some(HealingSync {
+            responder_party_id,
+            partitioned_for: SimDuration(healed_at.0 - partition.since.0),
+            healed_for: SimDuration(time.0 - healed_at.0),
+            msgs_sent: init.msgs_sent + resp.msgs_sent,
+            objects_sent: init.objects_sent + resp.objects_sent,
+            bytes_sent: init.bytes_sent + resp.bytes_sent,
+        })
+    }
+
+    /// Compares the objects and tree roots of what the two parties sync, which leaves out the
+    /// objects one of them collected. Returns `None` if they agree.
+    pub fn check_convergence(
+        &mut self,
+        initiator_party_id: usize,
+        responder_party_id: usize,
+    ) -> Option<Divergence<S::Item>> {
+        self.compare(initiator_party_id, responder_party_id, None)
+    }
+
+    /// Like [`SystemState::check_convergence`], but only for the given items if there are any.
+    /// Items one of the parties holds a tombstone for don't count either, and the roots are
+    /// those of the items that do.
+    fn compare(
+        &mut self,
+        initiator_party_id: usize,
+        responder_party_id: usize,
+        items: Option<&BTreeSet<S::Item>>,
+    ) -> Option<Divergence<S::Item>> {
+        let horizon = self.update_windows(initiator_party_id, responder_party_id);
+        let initiator = &self.party_states[initiator_party_id];
+        let responder = &self.party_states[responder_party_id];
+        let counts = |item: &S::Item| match items {
+            None => true,
+            Some(items) => {
+                items.contains(item)
+                    && !initiator.deleted.contains(item)
+                    && !responder.deleted.contains(item)
+            }
+        };
+        let initiator = initiator.window(horizon);
+        let responder = responder.window(horizon);
+
+        let missing_at_initiator: Vec<_> = responder
+            .objects
+            .keys()
+            .filter(|item| counts(item) && !initiator.objects.contains_key(item))
+            .cloned()
+            .collect();
+        let missing_at_responder: Vec<_> = initiator
+            .objects
+            .keys()
+            .filter(|item| counts(item) && !responder.objects.contains_key(item))
+            .cloned()
+            .collect();
+        let roots_match = match items {
+            None => initiator.tree.node().monoid() == responder.tree.node().monoid(),
+            Some(_) => {
+                let root = |party: &PartyState<S>| {
+                    party
+                        .objects
+                        .keys()
+                        .filter(|item| counts(item))
+                        .fold(S::Monoid::neutral(), |root, item| {
+                            root.combine(&S::Monoid::lift(item))
+                        })
+                };
+                root(initiator) == root(responder)
+            }
+        };
+
+        if missing_at_initiator.is_empty() && missing_at_responder.is_empty() && roots_match {
+            return None;
+        }
+
+        Some(Divergence {
+            responder_party_id,
+            missing_at_initiator,
+            missing_at_responder,
+            roots_match,
+        })
+    }
+
+    /// What the two parties sync: only the objects both of them keep, see [`Event::Gc`].
+    fn windows(&mut self, a: usize, b: usize) -> (&PartyState<S>, &PartyState<S>) {
+        let horizon = self.update_windows(a, b);
+        (
+            self.party_states[a].window(horizon),
+            self.party_states[b].window(horizon),
+        )
+    }
+
+    /// Updates the windows of the two parties and returns the horizon of both.
+    fn update_windows(&mut self, a: usize, b: usize) -> SimInstant {
+        let horizon = self.party_states[a]
+            .horizon
+            .max(self.party_states[b].horizon);
+        self.party_states[a].update_window(horizon);
+        self.party_states[b].update_window(horizon);
+        horizon
+    }
+
+    /// Applies the failure policy and returns the trace entry for the failed sync.
+    fn sync_failed(
+        &mut self,
+        error: Box<SyncError<SimObject>>,
+        time: SimInstant,
+        party_id: usize,
+        partner_party_id: usize,
+    ) -> TraceEntry<S::Item, SimObject> {
+        let mut retry_at = None;
+        match self.options.on_sync_failure {
+            SyncFailurePolicy::Continue => {}
+            SyncFailurePolicy::Abort => self.aborted_at = Some(time),
+            SyncFailurePolicy::Retry { after, attempts } => {
+                let failures = self
+                    .sync_failures
+                    .entry((party_id, partner_party_id))
+                    .or_default();
+                *failures += 1;
+
+                if *failures <= attempts {
+                    // retries scheduled for the current instant would never run
+                    let t = time + after.max(SimDuration(1));
+                    self.queue
+                        .scheduled
+                        .entry(t)
+                        .or_default()
+                        .push((party_id, Event::Sync(partner_party_id)));
+                    retry_at = Some(t);
+                } else {
+                    self.sync_failures.remove(&(party_id, partner_party_id));
+                }
+            }
+        }
+
+        let kept = self.options.keep_partial_syncs;
+        if kept {
+            self.receive_all(party_id, &error.initiator_new_objects, time);
+            self.receive_all(partner_party_id, &error.responder_new_objects, time);
+        }
+
+        TraceEntry::SyncFailed(partner_party_id, error, retry_at, kept)
+    }
+
+    /// Picks the partner of a [`Event::SyncWith`], or `None` if there is no peer to pick.
+    fn select_peer<R: RngCore>(
+        &mut self,
+        rng: &mut R,
+        selection: &PeerSelection,
+        party_id: usize,
+    ) -> Option<usize> {
+        let candidates = |peers: &[usize]| -> Vec<usize> {
+            let mut candidates: Vec<usize> = if peers.is_empty() {
+                (0..self.party_states.len()).collect()
+            } else {
+                peers.to_vec()
+            };
+            candidates.retain(|peer| *peer != party_id && self.is_present(*peer));
+            candidates
+        };
+
+        match selection {
+            PeerSelection::Random(peers) => {
+                let candidates = candidates(peers);
+                if candidates.is_empty() {
+                    return None;
+                }
+                Some(candidates[rng.gen_range(0..candidates.len())])
+            }
+            PeerSelection::RoundRobin(peers) => {
+                let all: Vec<usize> = if peers.is_empty() {
+                    (0..self.party_states.len()).collect()
+                } else {
+                    peers.clone()
+                };
+                let next = self
+                    .round_robin
+                    .entry((party_id, peers.clone()))
+                    .or_default();
+                // skip the peers that aren't there, but keep their place in the order
+                for i in 0..all.len() {
+                    let pos = (*next + i) % all.len();
+                    let peer = all[pos];
+                    if peer != party_id
+                        && peer < self.party_states.len()
+                        && !self.absent.contains(&peer)
+                    {
+                        *next = pos + 1;
+                        return Some(peer);
+                    }
+                }
+                None
+            }
+            PeerSelection::LeastRecent(peers) => candidates(peers)
+                .into_iter()
+                .min_by_key(|peer| self.last_synced.get(&(party_id, *peer))),
+            PeerSelection::Weighted(peers) => {
+                let present = candidates(&selection.peers());
+                let weighted: Vec<(usize, u64)> = peers
+                    .iter()
+                    .filter(|(peer, weight)| *weight > 0 && present.contains(peer))
+                    .map(|(peer, weight)| (*peer, *weight as u64))
+                    .collect();
+                let total: u64 = weighted.iter().map(|(_, weight)| weight).sum();
+                if total == 0 {
+                    return None;
+                }
+                let mut roll = rng.gen_range(0..total);
+                for (peer, weight) in weighted {
+                    if roll < weight {
+                        return Some(peer);
+                    }
+                    roll -= weight;
+                }
+                unreachable!("the roll is less than the total weight")
+            }
+        }
+    }
+
+    /// Handles the event. The random number generator is only used to pick sync partners, see
+    /// [`PeerSelection`], and to seed the [`Faults`](crate::scenarios::protocol::Faults) of
+    /// every sync.
+    pub fn handle_event<R: RngCore>(
+        &mut self,
+        rng: &mut R,
+        event: &Event,
+        time: SimInstant,
+        party_id: usize,
+        protocol: Protocol<S>,
+    ) -> TraceEntry<S::Item, SimObject> {
+        self.handle_nested(rng, event, event, time, party_id, protocol)
+    }
+
+    /// Handles `event`, which is `scheduled` or nested in its tags. A [`Event::Repeat`]
+    /// schedules `scheduled` again, so the repetitions keep the tags.
+    fn handle_nested<R: RngCore>(
+        &mut self,
+        rng: &mut R,
+        event: &Event,
+        scheduled: &Event,
+        time: SimInstant,
+        party_id: usize,
+        protocol: Protocol<S>,
+    ) -> TraceEntry<S::Item, SimObject> {
+        match event {
+            Event::Post
+            | Event::Sync(_)
+            | Event::SyncWith(_)
+            | Event::Leave
+            | Event::Wipe
+            | Event::Delete(_)
+            | Event::Gc(_)
+                if !self.is_present(party_id) =>
+            {
+                TraceEntry::Absent(party_id)
+            }
+            Event::Sync(partner_party_id) if !self.is_present(*partner_party_id) => {
+                TraceEntry::Absent(*partner_party_id)
+            }
+            Event::Sync(partner_party_id) if self.is_partitioned(party_id, *partner_party_id) => {
+                TraceEntry::Partitioned(*partner_party_id)
+            }
+            Event::Post => {
+                let obj = SimObject {
+                    author: party_id,
+                    post_id: self.cur_post_id,
+                    timestamp: time,
+                    deletes: None,
+                };
+
+                self.party_states[party_id].post(obj.clone());
+                self.propagation.posted(&obj);
+                self.cur_post_id += 1;
+                TraceEntry::Posted(obj)
+            }
+            Event::Sync(partner_party_id) => {
+                // eprint!("s{party_id}-{partner_party_id}");
+                let mut transcript =
+                    (self.options.record_transcripts || self.observe_rounds).then(Transcript::new);
+                let mut params = protocol.params;
+                if let Some(faults) = &mut params.faults {
+                    // different faults for every sync, which still only depend on the seeds
+                    faults.seed ^= rng.next_u64();
+                }
+                let keep_synced_items = self.options.network.is_some()
+                    && self.options.check_convergence != ConvergenceCheck::Off;
+
+                let (initiator, responder) = self.windows(party_id, *partner_party_id);
+                let initiator_node = initiator.tree.node();
+                let responder_node = responder.tree.node();
+                let initiator_objects = &initiator.objects;
+                let responder_objects = &responder.objects;
+                let result = (protocol.run)(
+                    params,
+                    initiator_node,
+                    initiator_objects,
+                    responder_node,
+                    responder_objects,
+                    transcript.as_mut(),
+                );
+                let synced_items = keep_synced_items.then(|| {
+                    initiator_objects
+                        .keys()
+                        .chain(responder_objects.keys())
+                        .cloned()
+                        .collect()
+                });
+                let (
+                    initiator_new_objects,
+                    responder_new_objects,
+                    initiator_stats,
+                    responder_stats,
+                ) = match result {
+                    Ok(result) => result,
+                    Err(error) => {
+                        return self.sync_failed(error, time, party_id, *partner_party_id)
+                    }
+                };
+                self.sync_failures.remove(&(party_id, *partner_party_id));
+                self.last_synced.insert((party_id, *partner_party_id), time);
+                self.last_synced.insert((*partner_party_id, party_id), time);
+
+                match &self.options.network {
+                    Some(network) => {
+                        let duration = network.sync_duration(
+                            party_id,
+                            *partner_party_id,
+                            &initiator_stats,
+                            &responder_stats,
+                        );
+                        self.in_flight
+                            .entry(network::completion(time, duration))
+                            .or_default()
+                            .push(network::InFlight {
+                                initiator_party_id: party_id,
+                                responder_party_id: *partner_party_id,
+                                started_at: time,
+                                duration,
+                                initiator_new_objects,
+                                responder_new_objects,
+                                synced_items,
+                            });
+                    }
+                    None => {
+                        self.receive_all(party_id, &initiator_new_objects, time);
+                        self.receive_all(*partner_party_id, &responder_new_objects, time);
+                    }
+                }
+
+                TraceEntry::Sync(
+                    *partner_party_id,
+                    initiator_stats,
+                    responder_stats,
+                    transcript,
+                )
+            }
+            Event::SyncWith(selection) => match self.select_peer(rng, selection, party_id) {
+                Some(partner_party_id) => self.handle_event(
+                    rng,
+                    &Event::Sync(partner_party_id),
+                    time,
+                    party_id,
+                    protocol,
+                ),
+                None => TraceEntry::NoPeer,
+            },
+            Event::DropProbabilities(filter) => {
+                let (n_old_probs, n_new_probs) = self.queue.drop_probabilistic(filter);
+                TraceEntry::DropProbabilities(n_old_probs, n_new_probs)
+            }
+            Event::AddProbabilities(probs) => {
+                self.queue.add_probabilistic(probs.iter().cloned());
+                TraceEntry::AddProbabilities(probs.len())
+            }
+            Event::ScheduleRelative(t_rel, entries) => {
+                for entry in entries {
+                    self.queue
+                        .scheduled
+                        .entry(time + *t_rel)
+                        .or_default()
+                        .push(entry.clone());
+                }
+                TraceEntry::ScheduleRelative(entries.len())
+            }
+            Event::Repeat(t_rel_every, inner_event) => {
+                self.queue
+                    .scheduled
+                    .entry(time + *t_rel_every)
+                    .or_default()
+                    .push((party_id, scheduled.clone()));
+                self.handle_event(rng, inner_event, time, party_id, protocol)
+            }
+            Event::Tagged(_, inner_event) => {
+                self.handle_nested(rng, inner_event, scheduled, time, party_id, protocol)
+            }
+            Event::Join(seed) => {
+                let objects = match seed {
+                    Seed::Empty => vec![],
+                    Seed::Snapshot { from, age } => match self.party_states.get(*from) {
+                        Some(state) => state
+                            .objects
+                            .values()
+                            .filter(|obj| obj.timestamp + *age <= time)
+                            .cloned()
+                            .collect(),
+                        None => vec![],
+                    },
+                };
+
+                if party_id >= self.party_states.len() {
+                    self.absent.extend(self.party_states.len()..party_id);
+                    self.party_states.resize_with(party_id + 1, PartyState::new);
+                }
+                self.absent.remove(&party_id);
+                self.propagation.joined(party_id);
+                self.party_states[party_id] = PartyState::new();
+
+                self.receive_all(party_id, &objects, time);
+                TraceEntry::Joined(objects.len())
+            }
+            Event::Leave => {
+                let state = std::mem::take(&mut self.party_states[party_id]);
+                self.absent.insert(party_id);
+                self.propagation.left(party_id);
+                self.sync_failures.retain(|(initiator, responder), _| {
+                    ![*initiator, *responder].contains(&party_id)
+                });
+                // a party that joins again doesn't remember its peers
+                self.last_synced.retain(|(party, _), _| *party != party_id);
+                self.round_robin.retain(|(party, _), _| *party != party_id);
+                TraceEntry::Left(state.objects.len())
+            }
+            Event::Wipe => {
+                let state = std::mem::take(&mut self.party_states[party_id]);
+                TraceEntry::Wiped(state.objects.len())
+            }
+            Event::Delete(deletion) => {
+                let state = &self.party_states[party_id];
+                let mut posts = state.objects.values().filter(|obj| obj.deletes.is_none());
+                let target = match deletion {
+                    Deletion::Newest => posts
+                        .filter(|obj| obj.author == party_id)
+                        .max_by_key(|obj| obj.post_id),
+                    Deletion::Oldest => posts
+                        .filter(|obj| obj.author == party_id)
+                        .min_by_key(|obj| obj.post_id),
+                    Deletion::Post { author, post_id } => {
+                        posts.find(|obj| obj.author == *author && obj.post_id == *post_id)
+                    }
+                };
+                let Some(target) = target else {
+                    return TraceEntry::Deleted(None);
+                };
+
+                let tombstone = SimObject {
+                    author: party_id,
+                    post_id: self.cur_post_id,
+                    timestamp: time,
+                    deletes: Some(Box::new(target.clone())),
+                };
+                self.party_states[party_id].receive(tombstone.clone());
+                self.propagation.posted(&tombstone);
+                self.cur_post_id += 1;
+                TraceEntry::Deleted(Some(tombstone))
+            }
+            Event::Gc(retention) => {
+                TraceEntry::Collected(self.party_states[party_id].collect(*retention, time))
+            }
+            Event::Partition(side) => {
+                self.partition = Some(Partition {
+                    side: side.iter().copied().collect(),
+                    since: time,
+                    healed_pairs: BTreeSet::new(),
+                });
+                TraceEntry::PartitionStarted(side.len())
+            }
+            Event::Heal => match self.partition.take() {
+                Some(partition) => {
+                    let partitioned_for = SimDuration(time.0 - partition.since.0);
+                    self.healed = Some((partition, time));
+                    TraceEntry::Healed(Some(partitioned_for))
+                }
+                None => TraceEntry::Healed(None),
+            },
+        }
+    }
+}
+
+/// Which parties can't sync with each other, see [`Event::Partition`].
+#[derive(Debug, Clone)]
+struct Partition {
+    side: BTreeSet<usize>,
+    since: SimInstant,
+    healed_pairs: BTreeSet<(usize, usize)>, // synced since the heal, lower id first
+}
+
+impl Partition {
+    fn separates(&self, a: usize, b: usize) -> bool {
+        self.side.contains(&a) != self.side.contains(&b)
+    }
+}
+
+/// The first sync between two parties after the partition that separated them healed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealingSync {
+    pub responder_party_id: usize,
+    /// How long the partition lasted.
+    pub partitioned_for: SimDuration,
+    /// How long after the heal the parties synced.
+    pub healed_for: SimDuration,
+    /// By both parties, as in the [`TraceEntry::Sync`] before.
+    pub msgs_sent: usize,
+    pub objects_sent: usize,
+    pub bytes_sent: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct PartyState<S: Simulator>
+where
+    SimObject: Object<S::Item>,
+{
+    tree: S::Tree,
+    objects: BTreeMap<S::Item, SimObject>,
+    deleted: BTreeSet<S::Item>, // objects the party holds tombstones for
+    horizon: SimInstant,        // objects older than this were collected
+    /// What the party syncs with parties that collected more, kept up to date until objects
+    /// are removed, see [`PartyState::update_window`].
+    window: Option<(SimInstant, Box<PartyState<S>>)>,
+    _phantom: PhantomData<S>,
+}
+
+impl<S: Simulator> PartyState<S>
+where
+    SimObject: Object<S::Item>,
+{
+    pub fn new() -> Self {
+        PartyState {
+            tree: S::Tree::nil(),
+            objects: BTreeMap::new(),
+            deleted: BTreeSet::new(),
+            horizon: SimInstant::zero(),
+            window: None,
+            _phantom: PhantomData,
+        }
+    }
+
+    pub fn post(&mut self, obj: SimObject) {
+        // a post made right after collecting everything is kept all the same
+        self.horizon = self.horizon.min(obj.timestamp);
+        if let Some((horizon, window)) = &mut self.window {
+            if obj.timestamp >= *horizon {
+                window.post(obj.clone());
+            }
+        }
+        self.tree.insert(obj.to_item());
+        self.objects.insert(obj.to_item(), obj);
+    }
+
+    /// Adds an object the party got from another party. Tombstones remove the object they
+    /// delete, and deleted objects are not added again. Returns whether the object was added.
+    pub fn receive(&mut self, obj: SimObject) -> bool {
+        !self.receive_all(std::slice::from_ref(&obj)).is_empty()
+    }
+
+    /// Like [`PartyState::receive`], for all objects of a sync. Returns the objects that were
+    /// added. Trees can't remove items cheaply, so if tombstones delete objects the party holds,
+    /// the tree is built again once at the end.
+    pub fn receive_all<'a>(&mut self, objs: &'a [SimObject]) -> Vec<&'a SimObject> {
+        let mut removed = false;
+        let mut added = vec![];
+        for obj in objs {
+            let item = obj.to_item();
+            if obj.timestamp < self.horizon
+                || self.deleted.contains(&item)
+                || self.objects.contains_key(&item)
+            {
+                continue;
+            }
+
+            if let Some(target) = &obj.deletes {
+                let target = target.to_item();
+                removed |= self.objects.remove(&target).is_some();
+                self.deleted.insert(target);
+            }
+            if !removed {
+                self.tree.insert(item.clone());
+            }
+            self.objects.insert(item, obj.clone());
+            added.push(obj);
+        }
+
+        if removed {
+            self.rebuild_tree();
+            self.window = None;
+        } else if let Some((horizon, window)) = &mut self.window {
+            for obj in added.iter().filter(|obj| obj.timestamp >= *horizon) {
+                window.post((*obj).clone());
+            }
+        }
+        added
+    }
+
+    fn rebuild_tree(&mut self) {
+        self.tree = S::Tree::nil();
+        for item in self.objects.keys() {
+            self.tree.insert(item.clone());
+        }
+    }
+
+    /// Drops the objects outside the retention and returns how many.
+    pub fn collect(&mut self, retention: Retention, time: SimInstant) -> usize {
+        let horizon = match retention {
+            Retention::MaxAge(age) => SimInstant(time.0.saturating_sub(age.0)),
+            Retention::MaxItems(0) => time + SimDuration(1),
+            Retention::MaxItems(n) => {
+                let mut timestamps: Vec<_> = self.objects.values().map(|o| o.timestamp).collect();
+                timestamps.sort_unstable_by(|a, b| b.cmp(a));
+                timestamps.get(n - 1).copied().unwrap_or(SimInstant::zero())
+            }
+        };
+        self.horizon = self.horizon.max(horizon);
+
+        let n_before = self.objects.len();
+        self.objects.retain(|_, obj| obj.timestamp >= self.horizon);
+        let n_collected = n_before - self.objects.len();
+        if n_collected > 0 {
+            self.rebuild_tree();
+            self.window = None;
+        }
+        n_collected
+    }
+
+    /// Makes sure [`PartyState::window`] has the objects from `horizon` on. The window is kept
+    /// for the next syncs with the same horizon, so it is only built again when the horizon
+    /// changes or objects are removed.
+    fn update_window(&mut self, horizon: SimInstant) {
+        if horizon <= self.horizon
+            || matches!(&self.window, Some((window_horizon, _)) if *window_horizon == horizon)
+        {
+            return;
+        }
+
+        let mut window = PartyState::new();
+        for obj in self.objects.values() {
+            if obj.timestamp >= horizon {
+                window.post(obj.clone());
+            }
+        }
+        self.window = Some((horizon, Box::new(window)));
+    }
+
+    /// The state without the objects older than `horizon`, which is what the party syncs with
+    /// a party that collected them. Call [`PartyState::update_window`] first.
+    fn window(&self, horizon: SimInstant) -> &Self {
+        match &self.window {
+            _ if horizon <= self.horizon => self,
+            Some((window_horizon, window)) if *window_horizon == horizon => window,
+            _ => panic!("no window from {horizon:?}"),
+        }
+    }
+
+    pub fn tree(&self) -> &S::Tree {
+        &self.tree
+    }
+
+    pub fn objects(&self) -> &BTreeMap<S::Item, SimObject> {
+        &self.objects
+    }
+}
+
+impl<S: Simulator> Default for PartyState<S>
+where
+    SimObject: Object<S::Item>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// When and where an entry happened, and the event that caused it. Nested events are described by
+/// the event they wrap: a repeated sync has the kind `repeat`, its period and the partner.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceMeta {
+    time: SimInstant,
+    party_id: usize,
+    /// `None` for rows that don't belong to an event, like the metrics of observer samples.
+    event_kind: Option<EventKind>,
+    sync_partner: Option<usize>,
+    repeat_period: Option<SimDuration>,
+    /// The kind of the event a [`Event::Repeat`] repeats.
+    nested_event_kind: Option<EventKind>,
+    added_probabilities: Option<usize>,
+    /// See [`SimOptions::record_event_debug`].
+    event_debug: Option<String>,
+}
+
+impl TraceMeta {
+    fn new(time: SimInstant, party_id: usize, event: &Event, debug: bool) -> Self {
+        let mut meta = TraceMeta {
+            event_kind: Some(event.kind()),
+            event_debug: debug.then(|| format!("{event:?}")),
+            ..TraceMeta::sample(time)
+        };
+        meta.party_id = party_id;
+
+        let mut event = event;
+        loop {
+            match event {
+                Event::Tagged(_, inner) => event = inner,
+                Event::Repeat(period, inner) if meta.repeat_period.is_none() => {
+                    meta.repeat_period = Some(*period);
+                    meta.nested_event_kind = Some(inner.kind());
+                    event = inner;
+                }
+                Event::Repeat(_, inner) => event = inner,
+                Event::Sync(partner_party_id) => {
+                    meta.sync_partner = Some(*partner_party_id);
+                    break;
+                }
+                Event::AddProbabilities(probs) => {
+                    meta.added_probabilities = Some(probs.len());
+                    break;
+                }
+                _ => break,
+            }
+        }
+        meta
+    }
+
+    pub fn time(&self) -> SimInstant {
+        self.time
+    }
+
+    pub fn party_id(&self) -> usize {
+        self.party_id
+    }
+
+    pub fn event_kind(&self) -> Option<EventKind> {
+        self.event_kind
+    }
+
+    /// The partner of the sync, also if it is repeated. `None` for a [`Event::SyncWith`], whose
+    /// partner is only known from the [`TraceEntry::Sync`].
+    pub fn sync_partner(&self) -> Option<usize> {
+        self.sync_partner
+    }
+
+    pub fn repeat_period(&self) -> Option<SimDuration> {
+        self.repeat_period
+    }
+
+    pub fn nested_event_kind(&self) -> Option<EventKind> {
+        self.nested_event_kind
+    }
+
+    /// How many probabilistic triggers the event added, also if it is repeated.
+    pub fn added_probabilities(&self) -> Option<usize> {
+        self.added_probabilities
+    }
+
+    pub fn event_debug(&self) -> Option<&str> {
+        self.event_debug.as_deref()
+    }
+
+    /// For the metrics of an observer's sample, which doesn't belong to an event or party.
+    fn sample(time: SimInstant) -> Self {
+        TraceMeta {
+            time,
+            party_id: 0,
+            event_kind: None,
+            sync_partner: None,
+            repeat_period: None,
+            nested_event_kind: None,
+            added_probabilities: None,
+            event_debug: None,
         }
     }
 }
 
+/// Adds the emitted metrics to the trace, each under the party it was emitted for.
+fn record_metrics<S, K>(sink: &mut K, meta: &TraceMeta, out: &mut Emitter) -> io::Result<()>
+where
+    S: Simulator,
+    K: TraceSink<S>,
+    SimObject: Object<S::Item>,
+{
+    for (party_id, metric) in out.drain() {
+        let meta = TraceMeta {
+            party_id,
+            ..meta.clone()
+        };
+        sink.record(&meta, TraceEntry::Metric(metric))?;
+    }
+    Ok(())
+}
+
 #[derive(Debug, Clone)]
 pub enum TraceEntry<I, O>
 where
@@ -474,51 +2026,151 @@ where
     O: Object<I>,
 {
     Posted(O),
-    Sync(usize, RunStats, RunStats),
+    Sync(usize, RunStats, RunStats, Option<Transcript>),
+    /// Follows a sync after which the parties disagree, see [`SimOptions::check_convergence`].
+    Diverged(Divergence<I>),
+    /// A sync with the given partner that failed, when it is retried, if at all, and whether the
+    /// objects that arrived before it failed were kept. See [`SimOptions::on_sync_failure`] and
+    /// [`SimOptions::keep_partial_syncs`].
+    SyncFailed(usize, Box<SyncError<O>>, Option<SimInstant>, bool),
+    /// Emitted by an [`Observer`].
+    Metric(Metric),
     DropProbabilities(usize, usize),
     AddProbabilities(usize),
     ScheduleRelative(usize),
+    /// A party joined with this many objects, see [`Event::Join`].
+    Joined(usize),
+    /// A party left, dropping this many objects.
+    Left(usize),
+    /// A party dropped this many objects.
+    Wiped(usize),
+    /// The event was skipped because the given party isn't there, see [`Event::Leave`].
+    Absent(usize),
+    /// A [`Event::SyncWith`] was skipped because none of the peers is there.
+    NoPeer,
+    /// A sync with the given partner was skipped because a partition separates the parties.
+    Partitioned(usize),
+    /// A partition started, with this many parties on the given side.
+    PartitionStarted(usize),
+    /// A partition healed after the given time, or there was none.
+    Healed(Option<SimDuration>),
+    /// Follows the first sync of two parties after a partition healed, see [`Event::Heal`].
+    HealingSync(HealingSync),
+    /// A sync with the given partner that started at the given instant is over after the given
+    /// time, see [`SimOptions::network`].
+    SyncCompleted(usize, SimInstant, std::time::Duration),
+    /// The tombstone a party posted, or `None` if it held nothing to delete.
+    Deleted(Option<O>),
+    /// A party collected this many objects, see [`Event::Gc`].
+    Collected(usize),
     Phantom(I),
 }
 
-#[derive(Clone, Debug, Serialize)]
+impl<I, O> TraceEntry<I, O>
+where
+    I: Item,
+    O: Object<I>,
+{
+    /// The name of the variant, as written to the `kind` column.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TraceEntry::Posted(_) => "Posted",
+            TraceEntry::Sync(..) => "Sync",
+            TraceEntry::Diverged(_) => "Diverged",
+            TraceEntry::SyncFailed(..) => "SyncFailed",
+            TraceEntry::Metric(_) => "Metric",
+            TraceEntry::DropProbabilities(..) => "DropProbabilities",
+            TraceEntry::AddProbabilities(_) => "AddProbabilities",
+            TraceEntry::ScheduleRelative(_) => "ScheduleRelative",
+            TraceEntry::Joined(_) => "Joined",
+            TraceEntry::Left(_) => "Left",
+            TraceEntry::Wiped(_) => "Wiped",
+            TraceEntry::Absent(_) => "Absent",
+            TraceEntry::NoPeer => "NoPeer",
+            TraceEntry::Partitioned(_) => "Partitioned",
+            TraceEntry::PartitionStarted(_) => "PartitionStarted",
+            TraceEntry::Healed(_) => "Healed",
+            TraceEntry::HealingSync(_) => "HealingSync",
+            TraceEntry::SyncCompleted(..) => "SyncCompleted",
+            TraceEntry::Deleted(_) => "Deleted",
+            TraceEntry::Collected(_) => "Collected",
+            TraceEntry::Phantom(_) => "Phantom",
+        }
+    }
+}
+
+/// A trace entry flattened into columns, as written by [`CsvSink`] and read by [`TraceReader`].
+/// Columns that are missing in older files are `None`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct TraceEntryRecord<S: Simulator>
 where
     SimObject: Object<S::Item>,
 {
-    kind: String,
-    posted_object_author: Option<usize>,
-    posted_object_post_id: Option<usize>,
-    sync_resp_party_id: Option<usize>,
-    sync_initiator_msgs_sent: Option<usize>,
-    sync_initiator_item_sets_sent: Option<usize>,
-    sync_initiator_fingerprints_sent: Option<usize>,
-    sync_initiator_items_sent: Option<usize>,
-    sync_initiator_items_wanted: Option<usize>,
-    sync_initiator_objects_sent: Option<usize>,
-    sync_initiator_items_known: Option<usize>,
-    sync_initiator_bytes_sent: Option<usize>,
-    sync_responder_msgs_sent: Option<usize>,
-    sync_responder_item_sets_sent: Option<usize>,
-    sync_responder_fingerprints_sent: Option<usize>,
-    sync_responder_items_sent: Option<usize>,
-    sync_responder_items_wanted: Option<usize>,
-    sync_responder_objects_sent: Option<usize>,
-    sync_responder_items_known: Option<usize>,
-    sync_responder_bytes_sent: Option<usize>,
-    drop_probabilities_entries_before: Option<usize>,
-    drop_probabilities_entries_after: Option<usize>,
-    add_probabilities_added: Option<usize>,
-    schedule_relative_added: Option<usize>,
-    #[serde(skip_serializing)]
+    pub kind: String,
+    pub posted_object_author: Option<usize>,
+    pub posted_object_post_id: Option<usize>,
+    pub sync_resp_party_id: Option<usize>,
+    pub sync_initiator_msgs_sent: Option<usize>,
+    pub sync_initiator_item_sets_sent: Option<usize>,
+    pub sync_initiator_fingerprints_sent: Option<usize>,
+    pub sync_initiator_items_sent: Option<usize>,
+    pub sync_initiator_items_wanted: Option<usize>,
+    pub sync_initiator_objects_sent: Option<usize>,
+    pub sync_initiator_items_known: Option<usize>,
+    pub sync_initiator_bytes_sent: Option<usize>,
+    pub sync_initiator_object_bytes_sent: Option<usize>,
+    pub sync_responder_msgs_sent: Option<usize>,
+    pub sync_responder_item_sets_sent: Option<usize>,
+    pub sync_responder_fingerprints_sent: Option<usize>,
+    pub sync_responder_items_sent: Option<usize>,
+    pub sync_responder_items_wanted: Option<usize>,
+    pub sync_responder_objects_sent: Option<usize>,
+    pub sync_responder_items_known: Option<usize>,
+    pub sync_responder_bytes_sent: Option<usize>,
+    pub sync_responder_object_bytes_sent: Option<usize>,
+    pub diverged_missing_at_initiator: Option<usize>,
+    pub diverged_missing_at_responder: Option<usize>,
+    pub diverged_roots_match: Option<bool>,
+    pub sync_failed_error: Option<SyncErrorKind>,
+    pub sync_failed_message: Option<String>,
+    pub sync_failed_round: Option<usize>,
+    pub sync_failed_retry_at: Option<SimInstant>,
+    pub metric_name: Option<String>,
+    pub metric_value: Option<f64>,
+    pub drop_probabilities_entries_before: Option<usize>,
+    pub drop_probabilities_entries_after: Option<usize>,
+    pub add_probabilities_added: Option<usize>,
+    pub schedule_relative_added: Option<usize>,
+    pub joined_objects: Option<usize>,
+    pub left_objects: Option<usize>,
+    pub wiped_objects: Option<usize>,
+    pub absent_party_id: Option<usize>,
+    pub deleted_object_author: Option<usize>,
+    pub deleted_object_post_id: Option<usize>,
+    pub tombstone_post_id: Option<usize>,
+    pub collected_objects: Option<usize>,
+    pub partitioned_party_id: Option<usize>,
+    pub partition_side_parties: Option<usize>,
+    pub healed_partitioned_for: Option<SimDuration>,
+    pub healing_healed_for: Option<SimDuration>,
+    pub healing_msgs_sent: Option<usize>,
+    pub healing_objects_sent: Option<usize>,
+    pub healing_bytes_sent: Option<usize>,
+    pub sync_started_at: Option<SimInstant>,
+    pub sync_duration_ms: Option<u64>,
+    pub sync_failed_initiator_new_objects: Option<usize>,
+    pub sync_failed_responder_new_objects: Option<usize>,
+    pub sync_failed_partial_kept: Option<bool>,
+    #[serde(skip)]
     _phantom: PhantomData<S>,
 }
 
-impl<S: Simulator> TraceEntryRecord<S>
+impl<S: Simulator> Default for TraceEntryRecord<S>
 where
     SimObject: Object<S::Item>,
 {
-    fn empty() -> Self {
+    fn default() -> Self {
         Self {
             kind: String::new(),
             posted_object_author: None,
@@ -532,6 +2184,7 @@ where
             sync_initiator_objects_sent: None,
             sync_initiator_items_known: None,
             sync_initiator_bytes_sent: None,
+            sync_initiator_object_bytes_sent: None,
             sync_responder_msgs_sent: None,
             sync_responder_item_sets_sent: None,
             sync_responder_fingerprints_sent: None,
@@ -540,10 +2193,40 @@ where
             sync_responder_objects_sent: None,
             sync_responder_items_known: None,
             sync_responder_bytes_sent: None,
+            sync_responder_object_bytes_sent: None,
+            diverged_missing_at_initiator: None,
+            diverged_missing_at_responder: None,
+            diverged_roots_match: None,
+            sync_failed_error: None,
+            sync_failed_message: None,
+            sync_failed_round: None,
+            sync_failed_retry_at: None,
+            metric_name: None,
+            metric_value: None,
             drop_probabilities_entries_before: None,
             drop_probabilities_entries_after: None,
             add_probabilities_added: None,
             schedule_relative_added: None,
+            joined_objects: None,
+            left_objects: None,
+            wiped_objects: None,
+            absent_party_id: None,
+            deleted_object_author: None,
+            deleted_object_post_id: None,
+            tombstone_post_id: None,
+            collected_objects: None,
+            partitioned_party_id: None,
+            partition_side_parties: None,
+            healed_partitioned_for: None,
+            healing_healed_for: None,
+            healing_msgs_sent: None,
+            healing_objects_sent: None,
+            healing_bytes_sent: None,
+            sync_started_at: None,
+            sync_duration_ms: None,
+            sync_failed_initiator_new_objects: None,
+            sync_failed_responder_new_objects: None,
+            sync_failed_partial_kept: None,
             _phantom: PhantomData,
         }
     }
@@ -554,57 +2237,83 @@ pub trait SimObjecty<I: Item>: Object<I> {
     fn post_id(&self) -> usize;
 }
 
-impl<S: Simulator> From<TraceEntry<S::Item, SimObject>> for TraceEntryRecord<S>
+impl<S: Simulator> From<&TraceEntry<S::Item, SimObject>> for TraceEntryRecord<S>
 where
     SimObject: Object<S::Item>,
 {
-    fn from(value: TraceEntry<S::Item, SimObject>) -> Self {
-        let mut res = Self::empty();
+    fn from(value: &TraceEntry<S::Item, SimObject>) -> Self {
+        let mut res = TraceEntryRecord {
+            kind: value.kind().to_string(),
+            ..Self::default()
+        };
         match value {
             TraceEntry::Posted(obj) => {
-                res.kind = "Posted".to_string();
                 res.posted_object_post_id = Some(obj.post_id());
                 res.posted_object_author = Some(obj.author());
             }
-            TraceEntry::Sync(resp_party_id, init, resp) => {
-                res.kind = "Sync".to_string();
-                res.sync_resp_party_id = Some(resp_party_id);
-                res.sync_initiator_msgs_sent = Some(init.msgs_sent);
-                res.sync_initiator_item_sets_sent = Some(init.item_sets_sent);
-                res.sync_initiator_fingerprints_sent = Some(init.fingerprints_sent);
-                res.sync_initiator_items_sent = Some(init.items_sent);
-                res.sync_initiator_items_wanted = Some(init.items_wanted);
-                res.sync_initiator_objects_sent = Some(init.objects_sent);
-                res.sync_initiator_items_known = Some(init.items_known);
-                res.sync_responder_msgs_sent = Some(resp.msgs_sent);
-                res.sync_responder_item_sets_sent = Some(resp.item_sets_sent);
-                res.sync_responder_fingerprints_sent = Some(resp.fingerprints_sent);
-                res.sync_responder_items_sent = Some(resp.items_sent);
-                res.sync_responder_items_wanted = Some(resp.items_wanted);
-                res.sync_responder_objects_sent = Some(resp.objects_sent);
-                res.sync_responder_items_known = Some(resp.items_known);
-
-                res.sync_initiator_bytes_sent = Some(
-                    (2 * S::ITEM_SIZE + S::MONOID_SIZE) * init.fingerprints_sent
-                        + (2 * S::ITEM_SIZE) * init.item_sets_sent
-                        + S::ITEM_SIZE * init.items_sent,
-                );
-                res.sync_responder_bytes_sent = Some(
-                    90 * resp.fingerprints_sent + 60 * resp.item_sets_sent + 30 * resp.items_sent,
-                );
+            TraceEntry::Sync(resp_party_id, init, resp, _) => {
+                res.sync_resp_party_id = Some(*resp_party_id);
+                res.set_sync_stats(init, resp);
+            }
+            TraceEntry::SyncFailed(resp_party_id, error, retry_at, kept) => {
+                res.sync_resp_party_id = Some(*resp_party_id);
+                res.set_sync_stats(&error.initiator_stats, &error.responder_stats);
+                res.sync_failed_error = Some(error.kind);
+                res.sync_failed_round = Some(error.round);
+                res.sync_failed_message = Some(error.message.clone());
+                res.sync_failed_retry_at = *retry_at;
+                res.sync_failed_initiator_new_objects = Some(error.initiator_new_objects.len());
+                res.sync_failed_responder_new_objects = Some(error.responder_new_objects.len());
+                res.sync_failed_partial_kept = Some(*kept);
+            }
+            TraceEntry::Metric(metric) => {
+                res.metric_name = Some(metric.name.clone());
+                res.metric_value = Some(metric.value);
+            }
+            TraceEntry::Diverged(divergence) => {
+                res.sync_resp_party_id = Some(divergence.responder_party_id);
+                res.diverged_missing_at_initiator = Some(divergence.missing_at_initiator.len());
+                res.diverged_missing_at_responder = Some(divergence.missing_at_responder.len());
+                res.diverged_roots_match = Some(divergence.roots_match);
             }
             TraceEntry::DropProbabilities(before, after) => {
-                res.kind = "DropProbabilities".to_string();
-                res.drop_probabilities_entries_before = Some(before);
-                res.drop_probabilities_entries_after = Some(after);
+                res.drop_probabilities_entries_before = Some(*before);
+                res.drop_probabilities_entries_after = Some(*after);
             }
             TraceEntry::AddProbabilities(added) => {
-                res.kind = "AddProbabilities".to_string();
-                res.add_probabilities_added = Some(added);
+                res.add_probabilities_added = Some(*added);
             }
-            TraceEntry::ScheduleRelative(added) => {
-                res.kind = "ScheduleRelative".to_string();
-                res.schedule_relative_added = Some(added)
+            TraceEntry::ScheduleRelative(added) => res.schedule_relative_added = Some(*added),
+            TraceEntry::Joined(n_objects) => res.joined_objects = Some(*n_objects),
+            TraceEntry::Left(n_objects) => res.left_objects = Some(*n_objects),
+            TraceEntry::Wiped(n_objects) => res.wiped_objects = Some(*n_objects),
+            TraceEntry::Absent(party_id) => res.absent_party_id = Some(*party_id),
+            TraceEntry::Deleted(Some(tombstone)) => {
+                res.tombstone_post_id = Some(tombstone.post_id);
+                if let Some(target) = &tombstone.deletes {
+                    res.deleted_object_author = Some(target.author);
+                    res.deleted_object_post_id = Some(target.post_id);
+                }
+            }
+            TraceEntry::Deleted(None) | TraceEntry::NoPeer => {}
+            TraceEntry::Collected(n_objects) => res.collected_objects = Some(*n_objects),
+            TraceEntry::Partitioned(party_id) => res.partitioned_party_id = Some(*party_id),
+            TraceEntry::PartitionStarted(n_parties) => {
+                res.partition_side_parties = Some(*n_parties)
+            }
+            TraceEntry::Healed(partitioned_for) => res.healed_partitioned_for = *partitioned_for,
+            TraceEntry::SyncCompleted(resp_party_id, started_at, duration) => {
+                res.sync_resp_party_id = Some(*resp_party_id);
+                res.sync_started_at = Some(*started_at);
+                res.sync_duration_ms = Some(duration.as_millis() as u64);
+            }
+            TraceEntry::HealingSync(healing) => {
+                res.sync_resp_party_id = Some(healing.responder_party_id);
+                res.healed_partitioned_for = Some(healing.partitioned_for);
+                res.healing_healed_for = Some(healing.healed_for);
+                res.healing_msgs_sent = Some(healing.msgs_sent);
+                res.healing_objects_sent = Some(healing.objects_sent);
+                res.healing_bytes_sent = Some(healing.bytes_sent);
             }
             TraceEntry::Phantom(_) => {}
         }
@@ -612,32 +2321,1323 @@ where
     }
 }
 
+impl<S: Simulator> From<TraceEntry<S::Item, SimObject>> for TraceEntryRecord<S>
+where
+    SimObject: Object<S::Item>,
+{
+    fn from(value: TraceEntry<S::Item, SimObject>) -> Self {
+        (&value).into()
+    }
+}
+
+impl<S: Simulator> TraceEntryRecord<S>
+where
+    SimObject: Object<S::Item>,
+{
+    fn set_sync_stats(&mut self, init: &RunStats, resp: &RunStats) {
+        self.sync_initiator_msgs_sent = Some(init.msgs_sent);
+        self.sync_initiator_item_sets_sent = Some(init.item_sets_sent);
+        self.sync_initiator_fingerprints_sent = Some(init.fingerprints_sent);
+        self.sync_initiator_items_sent = Some(init.items_sent);
+        self.sync_initiator_items_wanted = Some(init.items_wanted);
+        self.sync_initiator_objects_sent = Some(init.objects_sent);
+        self.sync_initiator_items_known = Some(init.items_known);
+        self.sync_responder_msgs_sent = Some(resp.msgs_sent);
+        self.sync_responder_item_sets_sent = Some(resp.item_sets_sent);
+        self.sync_responder_fingerprints_sent = Some(resp.fingerprints_sent);
+        self.sync_responder_items_sent = Some(resp.items_sent);
+        self.sync_responder_items_wanted = Some(resp.items_wanted);
+        self.sync_responder_objects_sent = Some(resp.objects_sent);
+        self.sync_responder_items_known = Some(resp.items_known);
+
+        self.sync_initiator_bytes_sent = Some(init.bytes_sent);
+        self.sync_initiator_object_bytes_sent = Some(init.object_bytes_sent);
+        self.sync_responder_bytes_sent = Some(resp.bytes_sent);
+        self.sync_responder_object_bytes_sent = Some(resp.object_bytes_sent);
+    }
+}
+
 #[derive(Debug, Clone)]
-pub struct Trace<I: Item, O: Object<I>>(Vec<(TraceMeta, TraceEntry<I, O>)>);
+pub struct Trace<I: Item, O: Object<I>> {
+    entries: Vec<(TraceMeta, TraceEntry<I, O>)>,
+    propagation: Propagation,
+    sync_times: SyncTimes,
+    aborted_at: Option<SimInstant>,
+}
 
 impl<I: Item, O: Object<I>> Trace<I, O> {
     pub fn entries(&self) -> &Vec<(TraceMeta, TraceEntry<I, O>)> {
-        &self.0
+        &self.entries
+    }
+
+    /// When each party first held each post.
+    pub fn propagation(&self) -> &Propagation {
+        &self.propagation
+    }
+
+    /// How long the syncs took, see [`SimOptions::network`].
+    pub fn sync_times(&self) -> &SyncTimes {
+        &self.sync_times
+    }
+
+    /// When the simulation ended early because a sync failed, see [`SyncFailurePolicy::Abort`].
+    pub fn aborted_at(&self) -> Option<SimInstant> {
+        self.aborted_at
+    }
+
+    /// Flattens the transcripts of all syncs into one record per round. Empty unless the trace
+    /// was recorded with [`SimOptions::record_transcripts`].
+    pub fn transcript_records(&self) -> Vec<TranscriptRecord> {
+        self.entries
+            .iter()
+            .flat_map(|(meta, entry)| TranscriptRecord::of_entry(meta, entry))
+            .collect()
     }
 }
 
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-//     #[test]
-//     fn probabilities() {
-//         let p = Probability::from_freq_per_hour(1);
-//         let mut rng = rand::thread_rng();
-//
-//         let mut count = 0;
-//
-//         for t in 0..(60 * 100) {
-//             let roll = DiceRoll::roll(&mut rng);
-//             if p.does_fire(&roll) {
-//                 count += 1;
-//             }
-//         }
-//
-//         println!("{count}");
-//     }
-// }
+/// A single round of a sync, flattened so it can be written as CSV.
+#[derive(Clone, Debug, Serialize)]
+pub struct TranscriptRecord {
+    time: SimInstant,
+    initiator_party_id: usize,
+    responder_party_id: usize,
+    round: usize,
+    sender: Role,
+    fingerprints: usize,
+    item_sets: usize,
+    items: usize,
+    item_set_sizes: String, // separated by semicolons
+    wants: usize,
+    provides: usize,
+    bytes: usize,
+}
+
+impl TranscriptRecord {
+    /// The rounds of the entry, if it is a sync with a transcript.
+    pub fn of_entry<I: Item, O: Object<I>>(
+        meta: &TraceMeta,
+        entry: &TraceEntry<I, O>,
+    ) -> Vec<TranscriptRecord> {
+        let TraceEntry::Sync(responder_party_id, _, _, Some(transcript)) = entry else {
+            return vec![];
+        };
+        transcript
+            .iter()
+            .map(|round| TranscriptRecord {
+                time: meta.time,
+                initiator_party_id: meta.party_id,
+                responder_party_id: *responder_party_id,
+                round: round.round,
+                sender: round.sender,
+                fingerprints: round.fingerprints,
+                item_sets: round.item_set_sizes.len(),
+                items: round.item_set_sizes.iter().sum(),
+                item_set_sizes: round
+                    .item_set_sizes
+                    .iter()
+                    .map(|size| size.to_string())
+                    .collect::<Vec<_>>()
+                    .join(";"),
+                wants: round.wants,
+                provides: round.provides,
+                bytes: round.bytes,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::experiments::uniform::UniformSim;
+    use crate::scenarios::protocol::Faults;
+    use crate::scenarios::tree;
+    use crate::suites::uniform;
+
+    pub(crate) fn protocol() -> Protocol<UniformSim> {
+        Protocol::new(
+            uniform::run_protocol,
+            ProtocolParams::new(2, uniform::split::<2>),
+        )
+    }
+
+    fn sim(seed: [u8; 32], triggers: Triggers, length: SimDuration) -> Vec<(TraceMeta, String)> {
+        let mut rng = ChaCha8Rng::from_seed(seed);
+        let trace = UniformSim::sim(&mut rng, 3, triggers, length, protocol());
+
+        trace
+            .entries()
+            .iter()
+            .map(|(meta, entry)| (meta.clone(), format!("{entry:?}")))
+            .collect()
+    }
+
+    /// Triggers for events that each happen about once an hour.
+    pub(crate) fn hourly(events: Vec<(usize, Event)>) -> Triggers {
+        let prob = Probability::from_frequency(Frequency::from_period(SimDuration::HOUR));
+        Triggers::new(
+            Default::default(),
+            events
+                .into_iter()
+                .map(|(party_id, event)| (party_id, prob, event))
+                .collect(),
+        )
+    }
+
+    /// Runs two parties for a day: both post about once an hour, and the first syncs with the
+    /// second as often.
+    pub(crate) fn sim_two_parties<K: TraceSink<UniformSim>, O: Observer<UniformSim>>(
+        options: SimOptions,
+        sink: &mut K,
+        observer: &mut O,
+    ) -> io::Result<SimOutcome> {
+        UniformSim::sim_observed(
+            &mut ChaCha8Rng::from_seed([0u8; 32]),
+            2,
+            hourly(vec![
+                (0, Event::Post),
+                (1, Event::Post),
+                (0, Event::Sync(1)),
+            ]),
+            SimDuration::DAY,
+            protocol(),
+            options,
+            sink,
+            observer,
+        )
+    }
+
+    /// Steps the state through the event at minute `t`, into the sink.
+    fn step_into(
+        state: &mut SystemState<UniformSim>,
+        rng: &mut ChaCha8Rng,
+        sink: &mut MemorySink<UniformSim>,
+        t: u64,
+        party_id: usize,
+        event: Event,
+    ) {
+        state
+            .step(
+                rng,
+                sink,
+                &mut (),
+                &event,
+                SimInstant(t),
+                party_id,
+                protocol(),
+            )
+            .unwrap();
+    }
+
+    /// Steps the state through the event at minute `t`, and returns the entries it recorded.
+    fn step_entries(
+        state: &mut SystemState<UniformSim>,
+        rng: &mut ChaCha8Rng,
+        t: u64,
+        party_id: usize,
+        event: Event,
+    ) -> Vec<TraceEntry<<UniformSim as Simulator>::Item, SimObject>> {
+        let mut sink = MemorySink::new();
+        step_into(state, rng, &mut sink, t, party_id, event);
+        sink.into_entries()
+            .into_iter()
+            .map(|(_, entry)| entry)
+            .collect()
+    }
+
+    #[test]
+    fn parse_durations() {
+        assert_eq!("90".parse(), Ok(90 * SimDuration::MINUTE));
+        assert_eq!("90min".parse(), Ok(90 * SimDuration::MINUTE));
+        assert_eq!("12h".parse(), Ok(12 * SimDuration::HOUR));
+        assert_eq!("18mo".parse(), Ok(18 * SimDuration::MONTH));
+        assert_eq!("1y".parse(), Ok(SimDuration::YEAR));
+        assert!("3x".parse::<SimDuration>().is_err());
+        for s in ["90min", "12h", "3d", "2w", "18mo", "1y", "0min"] {
+            assert_eq!(s.parse::<SimDuration>().unwrap().to_string(), s);
+        }
+        assert!("h".parse::<SimDuration>().is_err());
+        assert!("99999999999999999y".parse::<SimDuration>().is_err());
+        assert_eq!(
+            format!("{}min", u64::MAX).parse::<SimDuration>(),
+            Ok(SimDuration(u64::MAX))
+        );
+    }
+
+    #[test]
+    fn probabilities() {
+        let p = Probability::from_frequency(Frequency::from_period(SimDuration::HOUR));
+        let mut rng = ChaCha8Rng::from_seed([0u8; 32]);
+        let length = 1000 * SimDuration::DAY;
+        let expected = length.0 / SimDuration::HOUR.0;
+
+        let mut rolled: u64 = 0;
+        for _ in 0..length.0 {
+            if p.does_fire(&DiceRoll::roll(&mut rng)) {
+                rolled += 1;
+            }
+        }
+
+        let mut sampled: u64 = 0;
+        let mut t = p.sample_wait(&mut rng).unwrap().0;
+        while t < length.0 {
+            sampled += 1;
+            t += 1 + p.sample_wait(&mut rng).unwrap().0;
+        }
+
+        for count in [rolled, sampled] {
+            assert!(
+                count.abs_diff(expected) < expected / 50,
+                "{count} vs {expected}"
+            );
+        }
+
+        assert_eq!(Probability(0).sample_wait(&mut rng), None);
+        assert_eq!(
+            Probability::ONE.sample_wait(&mut rng),
+            Some(SimDuration::zero())
+        );
+    }
+
+    #[test]
+    fn same_seed_same_trace() {
+        let triggers = || {
+            hourly(vec![
+                (0, Event::Post),
+                (1, Event::Post),
+                (2, Event::Sync(0)),
+                (0, Event::Sync(1)),
+            ])
+        };
+
+        let a = sim([1u8; 32], triggers(), SimDuration::WEEK);
+        let b = sim([1u8; 32], triggers(), SimDuration::WEEK);
+        let c = sim([2u8; 32], triggers(), SimDuration::WEEK);
+
+        assert!(!a.is_empty());
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn event_columns() {
+        let repeated_sync = Event::repeat(SimDuration(4), Event::tagged("daily", Event::Sync(1)));
+        let add = Event::AddProbabilities(vec![
+            (2, Probability::ONE, Event::Post),
+            (2, Probability::ONE, Event::Sync(0)),
+        ]);
+        let scheduled =
+            BTreeMap::from_iter([(SimInstant(0), vec![(0, repeated_sync.clone()), (1, add)])]);
+        let run = |options| {
+            let mut rng = ChaCha8Rng::from_seed([0u8; 32]);
+            let triggers = Triggers::new(scheduled.clone(), vec![]);
+            UniformSim::sim_with_options(
+                &mut rng,
+                3,
+                triggers,
+                SimDuration(10),
+                protocol(),
+                options,
+            )
+        };
+
+        let trace = run(SimOptions::default());
+        let (meta, _) = trace
+            .entries()
+            .iter()
+            .find(|(meta, _)| meta.party_id() == 0)
+            .unwrap();
+        assert_eq!(meta.event_kind(), Some(EventKind::Repeat));
+        assert_eq!(meta.repeat_period(), Some(SimDuration(4)));
+        assert_eq!(meta.nested_event_kind(), Some(EventKind::Sync));
+        assert_eq!(meta.sync_partner(), Some(1));
+        assert_eq!(meta.added_probabilities(), None);
+        assert_eq!(meta.event_debug(), None);
+
+        let (meta, _) = trace
+            .entries()
+            .iter()
+            .find(|(_, entry)| matches!(entry, TraceEntry::AddProbabilities(_)))
+            .unwrap();
+        assert_eq!(meta.event_kind(), Some(EventKind::AddProbabilities));
+        assert_eq!(meta.added_probabilities(), Some(2));
+        assert_eq!(meta.sync_partner(), None);
+        assert_eq!(meta.repeat_period(), None);
+
+        let trace = run(SimOptions {
+            record_event_debug: true,
+            ..Default::default()
+        });
+        assert!(trace
+            .entries()
+            .iter()
+            .all(|(meta, _)| meta.event_debug().is_some()));
+        let (meta, _) = &trace.entries()[0];
+        assert_eq!(
+            meta.event_debug(),
+            Some(format!("{repeated_sync:?}").as_str())
+        );
+    }
+
+    #[test]
+    fn transcripts_are_opt_in() {
+        let triggers = || {
+            hourly(vec![
+                (0, Event::Post),
+                (1, Event::Post),
+                (0, Event::Sync(1)),
+            ])
+        };
+        let run = |options| {
+            let mut rng = ChaCha8Rng::from_seed([0u8; 32]);
+            UniformSim::sim_with_options(
+                &mut rng,
+                2,
+                triggers(),
+                SimDuration::DAY,
+                protocol(),
+                options,
+            )
+        };
+
+        let without = run(SimOptions::default());
+        assert!(without.transcript_records().is_empty());
+
+        let with = run(SimOptions {
+            record_transcripts: true,
+            ..Default::default()
+        });
+        let mut n_syncs = 0;
+        for (_, entry) in with.entries() {
+            if let TraceEntry::Sync(_, init, resp, transcript) = entry {
+                n_syncs += 1;
+                let transcript = transcript.as_ref().unwrap();
+                assert_eq!(transcript.len(), init.msgs_sent + resp.msgs_sent);
+                assert_eq!(transcript[0].sender, Role::Initiator);
+                let bytes: usize = transcript.iter().map(|round| round.bytes).sum();
+                assert_eq!(bytes, init.bytes_sent + resp.bytes_sent);
+            }
+        }
+        assert!(n_syncs > 0);
+        assert!(with.transcript_records().len() >= 2 * n_syncs);
+    }
+
+    #[test]
+    fn ordering_within_an_instant() {
+        let scheduled = BTreeMap::from_iter([(SimInstant(5), vec![(0, Event::Post)])]);
+        let probabilistic = vec![
+            (1, Probability::ONE, Event::Post),
+            (
+                2,
+                Probability::ONE,
+                Event::AddProbabilities(vec![(2, Probability::ONE, Event::Post)]),
+            ),
+            (
+                2,
+                Probability::ONE,
+                Event::ScheduleRelative(SimDuration::zero(), vec![(0, Event::Post)]),
+            ),
+        ];
+
+        let trace = sim(
+            [0u8; 32],
+            Triggers::new(scheduled, probabilistic),
+            SimDuration(10),
+        );
+        let posts: Vec<_> = trace
+            .iter()
+            .filter(|(_, entry)| entry.starts_with("Posted"))
+            .map(|(meta, _)| (meta.time, meta.party_id))
+            .collect();
+
+        // the scheduled post comes first at t=5
+        let at_5: Vec<_> = posts.iter().filter(|(t, _)| *t == SimInstant(5)).collect();
+        assert_eq!(at_5[0], &(SimInstant(5), 0));
+
+        // triggers added by a probabilistic event at t fire from t+1 on
+        assert!(posts
+            .iter()
+            .all(|(t, party)| *party != 2 || *t > SimInstant(0)));
+        assert!(posts.contains(&(SimInstant(1), 2)));
+
+        // events scheduled for the current instant are never run
+        assert_eq!(posts.iter().filter(|(_, party)| *party == 0).count(), 1);
+        assert_eq!(posts.iter().filter(|(_, party)| *party == 1).count(), 10);
+    }
+
+    #[test]
+    fn event_filters() {
+        let prob = Probability::from_percent(10);
+        let tagged_post = Event::tagged("burst", Event::Post);
+        let triggers = [
+            (0, prob, Event::Post),
+            (0, prob, Event::Sync(1)),
+            (1, Probability::ONE, tagged_post.clone()),
+            (1, prob, Event::tagged("burst", Event::Sync(0))),
+            (
+                2,
+                prob,
+                Event::tagged("outer", Event::tagged("burst", Event::Sync(0))),
+            ),
+        ];
+        let matching = |filter: EventFilter| -> Vec<usize> {
+            (0..triggers.len())
+                .filter(|i| filter.matches(&triggers[*i]))
+                .collect()
+        };
+
+        assert_eq!(matching(EventFilter::Party(0)), vec![0, 1]);
+        assert_eq!(matching(EventFilter::Kind(EventKind::Post)), vec![0, 2]);
+        assert_eq!(matching(EventFilter::SyncPartner(0)), vec![3, 4]);
+        assert_eq!(
+            matching(EventFilter::Probability(Probability::ONE)),
+            vec![2]
+        );
+        assert_eq!(
+            matching(EventFilter::Tag("burst".to_string())),
+            vec![2, 3, 4]
+        );
+        assert_eq!(matching(EventFilter::Tag("outer".to_string())), vec![4]);
+        assert_eq!(matching(EventFilter::Event(Box::new(Event::Post))), vec![0]);
+        assert_eq!(matching(EventFilter::trigger(&triggers[2])), vec![2]);
+        assert_eq!(matching(EventFilter::And(vec![])), vec![0, 1, 2, 3, 4]);
+        assert_eq!(matching(EventFilter::Or(vec![])), Vec::<usize>::new());
+        assert_eq!(
+            matching(EventFilter::Not(Box::new(EventFilter::Or(vec![
+                EventFilter::Party(1),
+                EventFilter::Kind(EventKind::Sync),
+            ])))),
+            vec![0]
+        );
+
+        // filters are plain data, so events compare and round-trip properly
+        let drop = |tag: &str| Event::DropProbabilities(EventFilter::Tag(tag.to_string()));
+        assert_ne!(drop("burst"), drop("other"));
+        let event = Event::repeat(SimDuration::DAY, drop("burst"));
+        let bytes = serde_cbor::to_vec(&event).unwrap();
+        assert_eq!(serde_cbor::from_slice::<Event>(&bytes).unwrap(), event);
+
+        // a dropped trigger stops firing
+        let scheduled = BTreeMap::from_iter([(SimInstant(5), vec![(1, drop("burst"))])]);
+        let trace = sim(
+            [0u8; 32],
+            Triggers::new(scheduled, vec![(1, Probability::ONE, tagged_post)]),
+            SimDuration(10),
+        );
+        let posts = trace
+            .iter()
+            .filter(|(_, entry)| entry.starts_with("Posted"))
+            .count();
+        assert_eq!(posts, 5);
+        assert!(trace
+            .iter()
+            .any(|(meta, _)| meta.event_kind() == Some(EventKind::DropProbabilities)));
+    }
+
+    #[test]
+    fn tagged_repeats_keep_their_tags() {
+        let event = Event::tagged("daily", Event::repeat(SimDuration(3), Event::Post));
+        let scheduled = BTreeMap::from_iter([(SimInstant(1), vec![(0, event.clone())])]);
+        let mut rng = ChaCha8Rng::from_seed([0u8; 32]);
+        let trace = UniformSim::sim_with_options(
+            &mut rng,
+            1,
+            Triggers::new(scheduled, vec![]),
+            SimDuration(10),
+            protocol(),
+            SimOptions {
+                record_event_debug: true,
+                ..Default::default()
+            },
+        );
+
+        let events: Vec<_> = trace
+            .entries()
+            .iter()
+            .map(|(meta, _)| (meta.time.0, meta.event_debug().unwrap()))
+            .collect();
+        let expected = format!("{event:?}");
+        assert_eq!(
+            events,
+            vec![(1, expected.as_str()), (4, &expected), (7, &expected)]
+        );
+    }
+
+    #[test]
+    fn propagation_follows_syncs() {
+        let scheduled = BTreeMap::from_iter([
+            (SimInstant(0), vec![(0, Event::Post)]),
+            (SimInstant(5), vec![(0, Event::Sync(1))]),
+            (SimInstant(9), vec![(2, Event::Sync(1))]),
+        ]);
+        let mut rng = ChaCha8Rng::from_seed([0u8; 32]);
+        let trace = UniformSim::sim(
+            &mut rng,
+            3,
+            Triggers::new(scheduled, vec![]),
+            SimDuration(10),
+            protocol(),
+        );
+
+        let post = trace.propagation().get(0, 0).unwrap();
+        assert_eq!(
+            post.received_at,
+            vec![
+                Some(SimInstant(0)),
+                Some(SimInstant(5)),
+                Some(SimInstant(9))
+            ]
+        );
+        assert_eq!(post.full_coverage(), Some(SimDuration(9)));
+    }
+
+    #[test]
+    fn churn() {
+        let snapshot = Seed::Snapshot {
+            from: 0,
+            age: SimDuration(3),
+        };
+        let scheduled = BTreeMap::from_iter([
+            (SimInstant(0), vec![(0, Event::Post), (2, Event::Leave)]),
+            (
+                SimInstant(1),
+                vec![(0, Event::Post), (0, Event::Sync(2)), (2, Event::Post)],
+            ),
+            (SimInstant(3), vec![(2, Event::Join(snapshot))]),
+            (SimInstant(4), vec![(2, Event::Sync(0))]),
+            (SimInstant(5), vec![(1, Event::Sync(7))]),
+            (SimInstant(6), vec![(0, Event::Wipe)]),
+            (SimInstant(7), vec![(5, Event::Join(Seed::Empty))]),
+            (
+                SimInstant(8),
+                vec![(3, Event::Sync(5)), (5, Event::Sync(2))],
+            ),
+        ]);
+        let mut rng = ChaCha8Rng::from_seed([0u8; 32]);
+        let trace = UniformSim::sim(
+            &mut rng,
+            3,
+            Triggers::new(scheduled, vec![]),
+            SimDuration(10),
+            protocol(),
+        );
+
+        let entries: Vec<_> = trace
+            .entries()
+            .iter()
+            .map(|(meta, entry)| match entry {
+                TraceEntry::Posted(_) | TraceEntry::Sync(..) => (meta.party_id(), entry.kind(), 0),
+                TraceEntry::Joined(n)
+                | TraceEntry::Left(n)
+                | TraceEntry::Wiped(n)
+                | TraceEntry::Absent(n) => (meta.party_id(), entry.kind(), *n),
+                _ => panic!("unexpected entry {entry:?}"),
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                (0, "Posted", 0),
+                (2, "Left", 0),
+                (0, "Posted", 0),
+                (0, "Absent", 2),
+                (2, "Absent", 2),
+                (2, "Joined", 1), // only the first post is old enough
+                (2, "Sync", 0),
+                (1, "Absent", 7), // there is no party 7
+                (0, "Wiped", 2),
+                (5, "Joined", 0),
+                (3, "Absent", 3), // joining party 5 added 3 and 4 as absent parties
+                (5, "Sync", 0),
+            ]
+        );
+
+        let received_at = |post_id| {
+            trace
+                .propagation()
+                .get(0, post_id)
+                .unwrap()
+                .received_at
+                .clone()
+        };
+        let t = |t| Some(SimInstant(t));
+        assert_eq!(received_at(0), vec![t(0), None, t(3), None, None, t(8)]);
+        assert_eq!(received_at(1), vec![t(1), None, t(4), None, None, t(8)]);
+    }
+
+    #[test]
+    fn deletions() {
+        let mut state = SystemState::<UniformSim>::with_options(
+            3,
+            Triggers::default(),
+            SimOptions {
+                check_convergence: ConvergenceCheck::Panic,
+                ..Default::default()
+            },
+        );
+        let mut rng = ChaCha8Rng::from_seed([0u8; 32]);
+        let mut step = |t, party_id, event| {
+            step_entries(&mut state, &mut rng, t, party_id, event)
+                .pop()
+                .unwrap()
+        };
+
+        step(0, 0, Event::Post);
+        step(0, 0, Event::Post);
+        step(1, 0, Event::Sync(1));
+        let TraceEntry::Deleted(Some(tombstone)) = step(2, 0, Event::Delete(Deletion::Newest))
+        else {
+            panic!("party 0 has posts to delete");
+        };
+        assert_eq!(tombstone.deletes.as_ref().unwrap().post_id, 1);
+
+        // party 1 still has the deleted post, but drops it, and party 0 doesn't take it back
+        step(3, 1, Event::Sync(0));
+        step(4, 2, Event::Sync(1));
+        step(
+            5,
+            1,
+            Event::Delete(Deletion::Post {
+                author: 0,
+                post_id: 0,
+            }),
+        );
+        assert!(matches!(
+            step(6, 2, Event::Delete(Deletion::Oldest)),
+            TraceEntry::Deleted(None)
+        ));
+        step(7, 1, Event::Sync(0));
+        step(8, 2, Event::Sync(0));
+
+        for party in state.party_states() {
+            let post_ids: Vec<_> = party.objects().values().map(|obj| obj.post_id).collect();
+            assert_eq!(post_ids, vec![2, 3]);
+            let items: Vec<_> = party.objects().keys().cloned().collect();
+            assert_eq!(tree::items(party.tree().node()), items);
+        }
+
+        let propagation = state.propagation();
+        let t = |t| Some(SimInstant(t));
+        assert_eq!(
+            propagation.get(0, 1).unwrap().received_at,
+            vec![t(0), t(1), None]
+        );
+        assert_eq!(
+            propagation.get(0, 2).unwrap().received_at,
+            vec![t(2), t(3), t(4)]
+        );
+        assert_eq!(propagation.get(0, 2).unwrap().deletes, Some((0, 1)));
+        assert_eq!(propagation.delays().unwrap().count, 3);
+        assert_eq!(propagation.deletion_delays().unwrap().count, 4);
+        assert_eq!(
+            propagation.deletion_full_coverage().unwrap().max,
+            SimDuration(3)
+        );
+    }
+
+    #[test]
+    fn peer_selection() {
+        let mut state = SystemState::<UniformSim>::new(4, Triggers::default());
+        let mut rng = ChaCha8Rng::from_seed([0u8; 32]);
+        let mut t = 0;
+        let mut partner = |party_id, event| {
+            t += 1;
+            match step_entries(&mut state, &mut rng, t, party_id, event)
+                .pop()
+                .unwrap()
+            {
+                TraceEntry::Sync(partner_party_id, ..) => Some(partner_party_id),
+                TraceEntry::NoPeer | TraceEntry::Left(_) => None,
+                entry => panic!("unexpected entry {entry:?}"),
+            }
+        };
+
+        let round_robin = Event::SyncWith(PeerSelection::RoundRobin(vec![1, 2, 3]));
+        let partners: Vec<_> = (0..4).map(|_| partner(0, round_robin.clone())).collect();
+        assert_eq!(partners, vec![Some(1), Some(2), Some(3), Some(1)]);
+
+        // party 0 synced with 1 last, and 3 never synced with anyone but 0
+        let least_recent = Event::SyncWith(PeerSelection::LeastRecent(vec![]));
+        assert_eq!(partner(0, least_recent.clone()), Some(2));
+        assert_eq!(partner(3, least_recent.clone()), Some(1));
+
+        let weighted = Event::SyncWith(PeerSelection::Weighted(vec![(1, 0), (2, 3), (3, 1)]));
+        let random = Event::SyncWith(PeerSelection::Random(vec![]));
+        let mut weighted_partners = BTreeMap::new();
+        let mut random_partners = BTreeSet::new();
+        for _ in 0..100 {
+            *weighted_partners
+                .entry(partner(0, weighted.clone()).unwrap())
+                .or_insert(0) += 1;
+            random_partners.insert(partner(2, random.clone()).unwrap());
+        }
+        assert_eq!(weighted_partners.keys().collect::<Vec<_>>(), vec![&2, &3]);
+        assert!(weighted_partners[&2] > weighted_partners[&3]);
+        assert_eq!(random_partners, BTreeSet::from([0, 1, 3]));
+
+        // parties that left are skipped, and if no peer is left there is nothing to sync with
+        partner(2, Event::Leave);
+        assert_eq!(partner(0, round_robin.clone()), Some(3));
+        partner(3, Event::Leave);
+        assert_eq!(partner(0, round_robin), Some(1));
+        assert_eq!(
+            partner(1, Event::SyncWith(PeerSelection::Random(vec![2, 3]))),
+            None
+        );
+        assert_eq!(partner(0, weighted), None);
+
+        // the simulation's random number generator picks the peers, so runs can be repeated
+        let triggers = || {
+            hourly(vec![
+                (0, Event::Post),
+                (0, Event::SyncWith(PeerSelection::Random(vec![]))),
+                (1, Event::SyncWith(PeerSelection::Random(vec![]))),
+            ])
+        };
+        let trace = sim([1u8; 32], triggers(), SimDuration::DAY);
+        assert_eq!(trace, sim([1u8; 32], triggers(), SimDuration::DAY));
+        assert!(trace.iter().any(|(_, entry)| entry.starts_with("Sync(2,")));
+    }
+
+    #[test]
+    fn partition_and_heal() {
+        let mut state = SystemState::<UniformSim>::new(4, Triggers::default());
+        let mut rng = ChaCha8Rng::from_seed([0u8; 32]);
+        let mut step = |t, party_id, event| step_entries(&mut state, &mut rng, t, party_id, event);
+        let kinds = |entries: Vec<TraceEntry<_, _>>| -> Vec<_> {
+            entries.iter().map(|entry| entry.kind()).collect()
+        };
+
+        assert!(matches!(
+            step(0, 0, Event::Heal)[..],
+            [TraceEntry::Healed(None)]
+        ));
+        step(0, 2, Event::Partition(vec![0, 1]));
+        for party_id in 0..4 {
+            step(1, party_id, Event::Post);
+        }
+        assert!(matches!(
+            step(2, 0, Event::Sync(2))[..],
+            [TraceEntry::Partitioned(2)]
+        ));
+        assert_eq!(kinds(step(2, 0, Event::Sync(1))), vec!["Sync"]);
+        assert_eq!(kinds(step(2, 3, Event::Sync(2))), vec!["Sync"]);
+        assert!(matches!(
+            step(10, 3, Event::Heal)[..],
+            [TraceEntry::Healed(Some(SimDuration(10)))]
+        ));
+
+        let entries = step(12, 2, Event::Sync(0));
+        let [TraceEntry::Sync(0, init, resp, _), TraceEntry::HealingSync(healing)] = &entries[..]
+        else {
+            panic!("expected a sync and its healing, got {entries:?}");
+        };
+        assert_eq!(
+            *healing,
+            HealingSync {
+                responder_party_id: 0,
+                partitioned_for: SimDuration(10),
+                healed_for: SimDuration(2),
+                msgs_sent: init.msgs_sent + resp.msgs_sent,
+                objects_sent: 4,
+                bytes_sent: init.bytes_sent + resp.bytes_sent,
+            }
+        );
+
+        // only the first sync of each pair counts, in either direction
+        assert_eq!(kinds(step(13, 0, Event::Sync(2))), vec!["Sync"]);
+        assert_eq!(kinds(step(13, 1, Event::Sync(0))), vec!["Sync"]);
+        let entries = step(14, 3, Event::Sync(1));
+        assert_eq!(kinds(entries.clone()), vec!["Sync", "HealingSync"]);
+        let TraceEntry::HealingSync(healing) = &entries[1] else {
+            unreachable!()
+        };
+        // party 1 already got everything from party 0, but party 3 only had half of it
+        assert_eq!(healing.objects_sent, 2);
+    }
+
+    #[test]
+    fn sync_duration() {
+        // every message takes 30 seconds
+        let network = Network::new(Link::new(std::time::Duration::from_secs(60), None));
+        let mut state = SystemState::<UniformSim>::with_options(
+            2,
+            Triggers::default(),
+            SimOptions {
+                check_convergence: ConvergenceCheck::Record,
+                network: Some(network.clone()),
+                ..Default::default()
+            },
+        );
+        let mut rng = ChaCha8Rng::from_seed([0u8; 32]);
+        let mut sink = MemorySink::new();
+        let mut step = |state: &mut SystemState<UniformSim>, sink: &mut _, t, party_id, event| {
+            step_into(state, &mut rng, sink, t, party_id, event)
+        };
+
+        step(&mut state, &mut sink, 0, 0, Event::Post);
+        step(&mut state, &mut sink, 1, 1, Event::Sync(0));
+        let Some((_, TraceEntry::Sync(0, init, resp, _))) = sink.entries().last() else {
+            panic!("the sync should succeed");
+        };
+        let duration = network.sync_duration(1, 0, init, resp);
+        let msgs = init.msgs_sent + resp.msgs_sent;
+        assert_eq!(duration, std::time::Duration::from_secs(30 * msgs as u64));
+        let completed_at = SimInstant(1 + (msgs as u64).div_ceil(2));
+        assert!(completed_at > SimInstant(2));
+
+        // the post made during the sync isn't part of it, and the other one isn't there yet
+        step(&mut state, &mut sink, 2, 0, Event::Post);
+        assert!(state.party_states()[1].objects().is_empty());
+        state
+            .complete_syncs(&mut sink, &mut (), SimInstant(completed_at.0 - 1))
+            .unwrap();
+        assert!(state.party_states()[1].objects().is_empty());
+
+        state
+            .complete_syncs(&mut sink, &mut (), completed_at)
+            .unwrap();
+        let post_ids: Vec<_> = state.party_states()[1]
+            .objects()
+            .values()
+            .map(|obj| obj.post_id)
+            .collect();
+        assert_eq!(post_ids, vec![0]);
+        assert_eq!(
+            state.propagation().get(0, 0).unwrap().received_at[1],
+            Some(completed_at)
+        );
+
+        // party 1 misses the post made while the sync was in flight, but the sync did its job
+        let entries = &sink.entries()[3..];
+        let [(meta, TraceEntry::SyncCompleted(0, SimInstant(1), completed_in))] = entries else {
+            panic!("expected only the completed sync, got {entries:?}");
+        };
+        assert_eq!((meta.time(), meta.party_id()), (completed_at, 1));
+        assert_eq!(*completed_in, duration);
+
+        let times = state.sync_times.distribution().unwrap();
+        assert_eq!(
+            (times.count, times.max_ms),
+            (1, duration.as_millis() as u64)
+        );
+
+        // syncs in flight at the end of a run never complete, and the others converge on what
+        // they synced, even with posts and syncs in the meantime
+        let trace = UniformSim::sim_with_options(
+            &mut ChaCha8Rng::from_seed([0u8; 32]),
+            3,
+            hourly(vec![
+                (0, Event::Post),
+                (1, Event::Post),
+                (1, Event::Sync(0)),
+                (2, Event::Sync(1)),
+                (0, Event::Sync(2)),
+            ]),
+            SimDuration::DAY,
+            protocol(),
+            SimOptions {
+                check_convergence: ConvergenceCheck::Panic,
+                network: Some(network),
+                ..Default::default()
+            },
+        );
+        let count = |kind| {
+            trace
+                .entries()
+                .iter()
+                .filter(|(_, entry)| entry.kind() == kind)
+                .count()
+        };
+        let completed = trace.sync_times().records().len();
+        assert_eq!(count("SyncCompleted"), completed);
+        assert!(completed > 0 && completed >= count("Sync") - 2);
+        assert!(trace
+            .sync_times()
+            .records()
+            .iter()
+            .all(|sync| sync.completed_at > sync.started_at));
+    }
+
+    #[test]
+    fn garbage_collection() {
+        // parties that collected different objects still agree on what they sync
+        let options = SimOptions {
+            check_convergence: ConvergenceCheck::Panic,
+            ..Default::default()
+        };
+        let mut state =
+            SystemState::<UniformSim>::with_options(2, Triggers::default(), options.clone());
+        let mut rng = ChaCha8Rng::from_seed([0u8; 32]);
+        let mut step = |t, party_id, event| {
+            step_entries(&mut state, &mut rng, t, party_id, event)
+                .pop()
+                .unwrap()
+        };
+
+        for t in 0..4 {
+            step(t, 0, Event::Post);
+        }
+        step(4, 1, Event::Sync(0));
+        assert!(matches!(
+            step(5, 0, Event::Gc(Retention::MaxAge(SimDuration(2)))),
+            TraceEntry::Collected(3)
+        ));
+
+        // party 1 still has the old posts, but they are neither synced nor taken back
+        step(6, 0, Event::Post);
+        let TraceEntry::Sync(_, init, resp, _) = step(6, 0, Event::Sync(1)) else {
+            panic!("the sync should succeed");
+        };
+        assert_eq!((init.items_known, resp.items_known), (2, 1));
+        assert_eq!(resp.objects_sent, 0);
+        assert!(matches!(
+            step(7, 1, Event::Gc(Retention::MaxItems(2))),
+            TraceEntry::Collected(3)
+        ));
+
+        let timestamps = |party_id: usize| -> Vec<_> {
+            let mut timestamps: Vec<_> = state.party_states()[party_id]
+                .objects()
+                .values()
+                .map(|obj| obj.timestamp.0)
+                .collect();
+            timestamps.sort();
+            timestamps
+        };
+        assert_eq!(timestamps(0), vec![3, 6]);
+        assert_eq!(timestamps(1), vec![3, 6]);
+
+        // and so do parties that collect at their own pace, whatever the horizons
+        let trace = UniformSim::sim_with_options(
+            &mut ChaCha8Rng::from_seed([0u8; 32]),
+            3,
+            hourly(vec![
+                (0, Event::Post),
+                (1, Event::Post),
+                (0, Event::Sync(1)),
+                (1, Event::Sync(2)),
+                (2, Event::Sync(0)),
+                (0, Event::Gc(Retention::MaxAge(6 * SimDuration::HOUR))),
+                (1, Event::Gc(Retention::MaxItems(4))),
+            ]),
+            SimDuration::WEEK,
+            protocol(),
+            options,
+        );
+        let collected: usize = trace
+            .entries()
+            .iter()
+            .map(|(_, entry)| match entry {
+                TraceEntry::Collected(n) => *n,
+                _ => 0,
+            })
+            .sum();
+        assert!(collected > 0);
+    }
+
+    #[test]
+    fn parse_retention() {
+        assert_eq!(
+            "age:30d".parse(),
+            Ok(Retention::MaxAge(30 * SimDuration::DAY))
+        );
+        assert_eq!("items:1000".parse(), Ok(Retention::MaxItems(1000)));
+        for invalid in ["30d", "age:3x", "items:0", "items:x", "size:3"] {
+            assert!(invalid.parse::<Retention>().is_err(), "{invalid}");
+        }
+    }
+
+    /// Syncs properly, but the initiator loses what it receives.
+    fn lossy_run(
+        params: ProtocolParams,
+        initiator_node: &<UniformSim as Simulator>::Node,
+        initiator_objects: &BTreeMap<<UniformSim as Simulator>::Item, SimObject>,
+        responder_node: &<UniformSim as Simulator>::Node,
+        responder_objects: &BTreeMap<<UniformSim as Simulator>::Item, SimObject>,
+        transcript: Option<&mut Transcript>,
+    ) -> ProtocolResult<SimObject> {
+        let (_, responder_new, init, resp) = uniform::run_protocol(
+            params,
+            initiator_node,
+            initiator_objects,
+            responder_node,
+            responder_objects,
+            transcript,
+        )?;
+        Ok((vec![], responder_new, init, resp))
+    }
+
+    fn convergence_trace(
+        run: RunProtocolFn<UniformSim>,
+        check_convergence: ConvergenceCheck,
+    ) -> Trace<<UniformSim as Simulator>::Item, SimObject> {
+        let scheduled = BTreeMap::from_iter([
+            (SimInstant(0), vec![(0, Event::Post), (1, Event::Post)]),
+            (SimInstant(1), vec![(0, Event::Sync(1))]),
+        ]);
+        let mut rng = ChaCha8Rng::from_seed([0u8; 32]);
+        UniformSim::sim_with_options(
+            &mut rng,
+            2,
+            Triggers::new(scheduled, vec![]),
+            SimDuration(2),
+            Protocol::new(run, protocol().params),
+            SimOptions {
+                check_convergence,
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn convergence_check() {
+        let divergences = |trace: &Trace<_, _>| -> Vec<Divergence<_>> {
+            trace
+                .entries()
+                .iter()
+                .filter_map(|(_, entry)| match entry {
+                    TraceEntry::Diverged(divergence) => Some(divergence.clone()),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        let honest = convergence_trace(uniform::run_protocol, ConvergenceCheck::Record);
+        assert!(divergences(&honest).is_empty());
+
+        let unchecked = convergence_trace(lossy_run, ConvergenceCheck::Off);
+        assert!(divergences(&unchecked).is_empty());
+
+        let checked = convergence_trace(lossy_run, ConvergenceCheck::Record);
+        let divergences = divergences(&checked);
+        assert_eq!(divergences.len(), 1);
+        assert_eq!(divergences[0].responder_party_id, 1);
+        assert_eq!(divergences[0].missing_at_initiator.len(), 1);
+        assert!(divergences[0].missing_at_responder.is_empty());
+        assert!(!divergences[0].roots_match);
+
+        // the divergence directly follows the sync
+        let n = checked.entries().len();
+        assert!(matches!(checked.entries()[n - 2].1, TraceEntry::Sync(..)));
+    }
+
+    #[test]
+    #[should_panic(expected = "diverged after syncing")]
+    fn convergence_check_panics() {
+        convergence_trace(lossy_run, ConvergenceCheck::Panic);
+    }
+
+    /// Fails every sync in its second round.
+    fn failing_run(
+        _params: ProtocolParams,
+        _initiator_node: &<UniformSim as Simulator>::Node,
+        initiator_objects: &BTreeMap<<UniformSim as Simulator>::Item, SimObject>,
+        _responder_node: &<UniformSim as Simulator>::Node,
+        responder_objects: &BTreeMap<<UniformSim as Simulator>::Item, SimObject>,
+        _transcript: Option<&mut Transcript>,
+    ) -> ProtocolResult<SimObject> {
+        let mut initiator_stats = RunStats::new(initiator_objects.len());
+        initiator_stats.msgs_sent = 1;
+        Err(Box::new(SyncError {
+            kind: SyncErrorKind::Decode,
+            message: "garbled".to_string(),
+            round: 1,
+            initiator_stats,
+            responder_stats: RunStats::new(responder_objects.len()),
+            initiator_new_objects: vec![],
+            responder_new_objects: vec![],
+        }))
+    }
+
+    fn failing_trace(
+        on_sync_failure: SyncFailurePolicy,
+    ) -> Trace<<UniformSim as Simulator>::Item, SimObject> {
+        let scheduled = BTreeMap::from_iter([
+            (SimInstant(1), vec![(0, Event::Sync(1))]),
+            (SimInstant(2), vec![(0, Event::Post)]),
+        ]);
+        let mut rng = ChaCha8Rng::from_seed([0u8; 32]);
+        UniformSim::sim_with_options(
+            &mut rng,
+            2,
+            Triggers::new(scheduled, vec![]),
+            SimDuration(20),
+            Protocol::new(failing_run, protocol().params),
+            SimOptions {
+                on_sync_failure,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn failures(
+        trace: &Trace<<UniformSim as Simulator>::Item, SimObject>,
+    ) -> Vec<(u64, Option<u64>)> {
+        trace
+            .entries()
+            .iter()
+            .filter_map(|(meta, entry)| match entry {
+                TraceEntry::SyncFailed(1, error, retry_at, false) => {
+                    assert_eq!(error.round, 1);
+                    assert_eq!(error.initiator_stats.msgs_sent, 1);
+                    Some((meta.time.0, retry_at.map(|t| t.0)))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sync_failure_policies() {
+        let posted = |trace: &Trace<_, _>| {
+            trace
+                .entries()
+                .iter()
+                .any(|(_, entry)| matches!(entry, TraceEntry::Posted(_)))
+        };
+
+        let trace = failing_trace(SyncFailurePolicy::Continue);
+        assert_eq!(failures(&trace), vec![(1, None)]);
+        assert!(posted(&trace));
+        assert_eq!(trace.aborted_at(), None);
+
+        let trace = failing_trace(SyncFailurePolicy::Abort);
+        assert_eq!(failures(&trace), vec![(1, None)]);
+        assert!(!posted(&trace));
+        assert_eq!(trace.aborted_at(), Some(SimInstant(1)));
+
+        let trace = failing_trace(SyncFailurePolicy::Retry {
+            after: SimDuration(3),
+            attempts: 2,
+        });
+        assert_eq!(
+            failures(&trace),
+            vec![(1, Some(4)), (4, Some(7)), (7, None)]
+        );
+        assert!(posted(&trace));
+
+        let record: TraceEntryRecord<UniformSim> = trace.entries()[0].1.clone().into();
+        assert_eq!(record.kind, "SyncFailed");
+        assert_eq!(record.sync_failed_error, Some(SyncErrorKind::Decode));
+        assert_eq!(record.sync_initiator_msgs_sent, Some(1));
+    }
+
+    #[test]
+    fn parse_sync_failure_policies() {
+        assert_eq!("continue".parse(), Ok(SyncFailurePolicy::Continue));
+        assert_eq!("abort".parse(), Ok(SyncFailurePolicy::Abort));
+        assert_eq!(
+            "retry:1h".parse(),
+            Ok(SyncFailurePolicy::Retry {
+                after: SimDuration::HOUR,
+                attempts: 3
+            })
+        );
+        assert_eq!(
+            "retry:2d:5".parse(),
+            Ok(SyncFailurePolicy::Retry {
+                after: 2 * SimDuration::DAY,
+                attempts: 5
+            })
+        );
+        for invalid in ["retry", "retry:0", "retry:1h:x", "ignore"] {
+            assert!(invalid.parse::<SyncFailurePolicy>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn partial_syncs() {
+        let posts = (0..10).map(|i| (i % 2, Event::Post)).collect();
+        let scheduled = BTreeMap::from_iter([
+            (SimInstant(1), posts),
+            (SimInstant(2), vec![(0, Event::Sync(1))]),
+        ]);
+        let params = ProtocolParams {
+            faults: Some(Faults {
+                kill_after: Some(4),
+                ..Default::default()
+            }),
+            ..protocol().params
+        };
+        let run = |keep_partial_syncs| {
+            let mut rng = ChaCha8Rng::from_seed([0u8; 32]);
+            UniformSim::sim_with_options(
+                &mut rng,
+                2,
+                Triggers::new(scheduled.clone(), vec![]),
+                SimDuration(5),
+                Protocol::new(uniform::run_protocol, params),
+                SimOptions {
+                    keep_partial_syncs,
+                    ..Default::default()
+                },
+            )
+        };
+        let received = |trace: &Trace<_, _>| -> usize {
+            let propagation = trace.propagation();
+            propagation.posts().map(|post| post.n_reached() - 1).sum()
+        };
+
+        for keep in [false, true] {
+            let trace = run(keep);
+            let (_, entry) = trace.entries().last().unwrap();
+            let TraceEntry::SyncFailed(1, error, None, kept) = entry else {
+                panic!("expected the sync to fail, got {entry:?}");
+            };
+            assert_eq!(error.kind, SyncErrorKind::Killed);
+            assert_eq!(*kept, keep);
+
+            let partial = error.initiator_new_objects.len() + error.responder_new_objects.len();
+            assert!(partial > 0 && partial < 10, "{partial}");
+            assert_eq!(received(&trace), if keep { partial } else { 0 });
+
+            let record: TraceEntryRecord<UniformSim> = entry.into();
+            assert_eq!(
+                record.sync_failed_initiator_new_objects,
+                Some(error.initiator_new_objects.len())
+            );
+            assert_eq!(record.sync_failed_partial_kept, Some(keep));
+        }
+    }
+
+    #[test]
+    fn fault_seeds() {
+        let run = |seed| {
+            let params = ProtocolParams {
+                faults: Some(Faults {
+                    drop: 0.2,
+                    seed,
+                    ..Default::default()
+                }),
+                ..protocol().params
+            };
+            let trace = UniformSim::sim_with_options(
+                &mut ChaCha8Rng::from_seed([0u8; 32]),
+                2,
+                hourly(vec![
+                    (0, Event::Post),
+                    (1, Event::Post),
+                    (0, Event::Sync(1)),
+                ]),
+                SimDuration::WEEK,
+                Protocol::new(uniform::run_protocol, params),
+                SimOptions::default(),
+            );
+            trace
+                .entries()
+                .iter()
+                .map(|(_, entry)| entry.kind())
+                .collect::<Vec<_>>()
+        };
+
+        // the seed of the faults picks other faults for the same run
+        let kinds = run(0);
+        assert!(kinds.contains(&"SyncFailed"));
+        assert_eq!(kinds, run(0));
+        assert_ne!(kinds, run(1));
+    }
+}
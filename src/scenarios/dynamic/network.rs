@@ -0,0 +1,211 @@
+//! How long syncs take. Without a [`Network`], a sync is over in the instant it starts. With one,
+//! every message of a sync takes half the round trip time of the link between the parties, and
+//! every byte takes its share of the link's bandwidth. The objects a sync brings arrive once it
+//! is over, at the first instant after that.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+use serde::Serialize;
+
+use super::{SimDuration, SimInstant, SimObject};
+use crate::scenarios::protocol::RunStats;
+
+/// The connection between two parties.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Link {
+    pub rtt: Duration,
+    /// In bytes per second, or unlimited if `None`.
+    pub bandwidth: Option<u64>,
+}
+
+impl Link {
+    pub fn new(rtt: Duration, bandwidth: Option<u64>) -> Self {
+        Link { rtt, bandwidth }
+    }
+
+    /// How long it takes to send the messages, one after the other, and the bytes in them.
+    pub fn transfer_time(&self, msgs: usize, bytes: usize) -> Duration {
+        let latency = self.rtt * msgs as u32 / 2;
+        let transmission = match self.bandwidth {
+            Some(bandwidth) => Duration::from_secs_f64(bytes as f64 / bandwidth.max(1) as f64),
+            None => Duration::ZERO,
+        };
+        latency + transmission
+    }
+}
+
+/// The links between the parties. Links are the same in both directions, and parties without a
+/// link of their own use the default one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Network {
+    default: Link,
+    links: BTreeMap<(usize, usize), Link>, // lower id first
+}
+
+impl Network {
+    pub fn new(default: Link) -> Self {
+        Network {
+            default,
+            links: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_link(mut self, a: usize, b: usize, link: Link) -> Self {
+        self.links.insert((a.min(b), a.max(b)), link);
+        self
+    }
+
+    pub fn link(&self, a: usize, b: usize) -> &Link {
+        self.links
+            .get(&(a.min(b), a.max(b)))
+            .unwrap_or(&self.default)
+    }
+
+    /// How long the sync between the two parties takes, given what both of them sent.
+    pub fn sync_duration(
+        &self,
+        initiator_party_id: usize,
+        responder_party_id: usize,
+        init: &RunStats,
+        resp: &RunStats,
+    ) -> Duration {
+        self.link(initiator_party_id, responder_party_id)
+            .transfer_time(
+                init.msgs_sent + resp.msgs_sent,
+                init.bytes_sent + resp.bytes_sent,
+            )
+    }
+}
+
+/// The first instant at which a sync that started at `time` and took `duration` is over. Syncs
+/// that take any time at all are over at a later instant.
+pub(crate) fn completion(time: SimInstant, duration: Duration) -> SimInstant {
+    let minutes = duration
+        .as_nanos()
+        .div_ceil(Duration::from_secs(60).as_nanos());
+    time + SimDuration(minutes as u64)
+}
+
+/// A sync whose objects are still on their way.
+#[derive(Debug, Clone)]
+pub(crate) struct InFlight<I> {
+    pub initiator_party_id: usize,
+    pub responder_party_id: usize,
+    pub started_at: SimInstant,
+    pub duration: Duration,
+    pub initiator_new_objects: Vec<SimObject>,
+    pub responder_new_objects: Vec<SimObject>,
+    /// The items the parties synced, if convergence is checked. What they got in the meantime
+    /// isn't part of the sync.
+    pub synced_items: Option<BTreeSet<I>>,
+}
+
+/// How long each sync of a simulation run took on the [`Network`], in the order they completed.
+/// Empty if the run had no network.
+#[derive(Debug, Clone, Default)]
+pub struct SyncTimes {
+    records: Vec<SyncTimeRecord>,
+}
+
+/// A sync that completed, for writing to CSV.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SyncTimeRecord {
+    pub initiator_party_id: usize,
+    pub responder_party_id: usize,
+    pub started_at: SimInstant,
+    pub completed_at: SimInstant,
+    pub duration_ms: u64,
+}
+
+impl SyncTimes {
+    pub(crate) fn completed<I>(&mut self, sync: &InFlight<I>, completed_at: SimInstant) {
+        self.records.push(SyncTimeRecord {
+            initiator_party_id: sync.initiator_party_id,
+            responder_party_id: sync.responder_party_id,
+            started_at: sync.started_at,
+            completed_at,
+            duration_ms: sync.duration.as_millis() as u64,
+        });
+    }
+
+    pub fn records(&self) -> &[SyncTimeRecord] {
+        &self.records
+    }
+
+    /// Summary of the sync durations, or `None` if no sync completed.
+    pub fn distribution(&self) -> Option<TimeDistribution> {
+        if self.records.is_empty() {
+            return None;
+        }
+        let mut durations: Vec<_> = self.records.iter().map(|r| r.duration_ms).collect();
+        durations.sort();
+
+        let count = durations.len();
+        let percentile = |p: usize| durations[(p * count).div_ceil(100).max(1) - 1];
+        Some(TimeDistribution {
+            count,
+            min_ms: durations[0],
+            p50_ms: percentile(50),
+            p90_ms: percentile(90),
+            p99_ms: percentile(99),
+            max_ms: durations[count - 1],
+            mean_ms: durations.iter().sum::<u64>() / count as u64,
+        })
+    }
+}
+
+/// Like [`Distribution`](super::Distribution), in milliseconds of wall time instead of simulated
+/// minutes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct TimeDistribution {
+    pub count: usize,
+    pub min_ms: u64,
+    pub p50_ms: u64,
+    pub p90_ms: u64,
+    pub p99_ms: u64,
+    pub max_ms: u64,
+    pub mean_ms: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{completion, Link, Network};
+    use crate::scenarios::dynamic::SimInstant;
+    use crate::scenarios::protocol::RunStats;
+
+    #[test]
+    fn durations() {
+        let link = Link::new(Duration::from_millis(100), Some(1000));
+        assert_eq!(link.transfer_time(4, 500), Duration::from_millis(700));
+        let unlimited = Link::new(Duration::from_millis(100), None);
+        assert_eq!(unlimited.transfer_time(4, 500), Duration::from_millis(200));
+
+        let slow = Link::new(Duration::from_secs(2), Some(10));
+        let network = Network::new(link).with_link(3, 1, slow);
+        assert_eq!(network.link(1, 3), &slow);
+        assert_eq!(network.link(3, 2), &link);
+
+        let stats = |msgs_sent, bytes_sent| RunStats {
+            msgs_sent,
+            bytes_sent,
+            ..RunStats::new(0)
+        };
+        assert_eq!(
+            network.sync_duration(1, 3, &stats(3, 600), &stats(2, 300)),
+            Duration::from_secs(95)
+        );
+
+        assert_eq!(completion(SimInstant(5), Duration::ZERO), SimInstant(5));
+        assert_eq!(
+            completion(SimInstant(5), Duration::from_millis(1)),
+            SimInstant(6)
+        );
+        assert_eq!(
+            completion(SimInstant(5), Duration::from_secs(95)),
+            SimInstant(7)
+        );
+    }
+}
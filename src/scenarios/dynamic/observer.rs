@@ -0,0 +1,409 @@
+//! Hooks for metrics the trace doesn't have. An [`Observer`] is called around every event, after
+//! every protocol round and at fixed sampling instants, can look at the whole [`SystemState`], and
+//! adds its findings to the trace as [`Metric`]s.
+
+use unionize::Object;
+
+use super::{Event, SimDuration, SimInstant, SimObject, Simulator, SystemState, TraceEntry};
+use crate::scenarios::protocol::TranscriptEntry;
+
+/// A number an observer adds to the trace, see [`TraceEntry::Metric`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metric {
+    pub name: String,
+    pub value: f64,
+}
+
+/// Collects the metrics emitted by a callback. They are added to the trace right after it, with
+/// the time of the callback and the party they were emitted for.
+#[derive(Debug, Default)]
+pub struct Emitter {
+    metrics: Vec<(usize, Metric)>,
+}
+
+impl Emitter {
+    pub fn emit<N: Into<String>>(&mut self, party_id: usize, name: N, value: f64) {
+        self.metrics.push((
+            party_id,
+            Metric {
+                name: name.into(),
+                value,
+            },
+        ));
+    }
+
+    pub(crate) fn drain(&mut self) -> impl Iterator<Item = (usize, Metric)> + '_ {
+        self.metrics.drain(..)
+    }
+}
+
+/// Callbacks into a simulation run, see [`Simulator::sim_observed`]. All of them do nothing by
+/// default. Several observers can be combined in a tuple or a `Vec<Box<dyn Observer<S>>>`.
+pub trait Observer<S: Simulator>
+where
+    SimObject: Object<S::Item>,
+{
+    fn before_event(
+        &mut self,
+        _state: &SystemState<S>,
+        _time: SimInstant,
+        _party_id: usize,
+        _event: &Event,
+        _out: &mut Emitter,
+    ) {
+    }
+
+    fn after_event(
+        &mut self,
+        _state: &SystemState<S>,
+        _time: SimInstant,
+        _party_id: usize,
+        _entry: &TraceEntry<S::Item, SimObject>,
+        _out: &mut Emitter,
+    ) {
+    }
+
+    /// Whether [`Observer::after_round`] should be called. Rounds are only recorded if some
+    /// observer wants them, because that costs time.
+    fn observes_rounds(&self) -> bool {
+        false
+    }
+
+    /// Called for every round of a successful sync, once the sync is done.
+    fn after_round(
+        &mut self,
+        _state: &SystemState<S>,
+        _time: SimInstant,
+        _initiator_party_id: usize,
+        _responder_party_id: usize,
+        _round: &TranscriptEntry,
+        _out: &mut Emitter,
+    ) {
+    }
+
+    /// How often [`Observer::sample`] is called, if at all. Samples are taken at multiples of
+    /// the period, before the events of that instant.
+    fn sample_every(&self) -> Option<SimDuration> {
+        None
+    }
+
+    fn sample(&mut self, _state: &SystemState<S>, _time: SimInstant, _out: &mut Emitter) {}
+}
+
+impl<S: Simulator> Observer<S> for () where SimObject: Object<S::Item> {}
+
+impl<S: Simulator> Observer<S> for Vec<Box<dyn Observer<S>>>
+where
+    SimObject: Object<S::Item>,
+{
+    fn before_event(
+        &mut self,
+        state: &SystemState<S>,
+        time: SimInstant,
+        party_id: usize,
+        event: &Event,
+        out: &mut Emitter,
+    ) {
+        for observer in self {
+            observer.before_event(state, time, party_id, event, out);
+        }
+    }
+
+    fn after_event(
+        &mut self,
+        state: &SystemState<S>,
+        time: SimInstant,
+        party_id: usize,
+        entry: &TraceEntry<S::Item, SimObject>,
+        out: &mut Emitter,
+    ) {
+        for observer in self {
+            observer.after_event(state, time, party_id, entry, out);
+        }
+    }
+
+    fn observes_rounds(&self) -> bool {
+        self.iter().any(|observer| observer.observes_rounds())
+    }
+
+    fn after_round(
+        &mut self,
+        state: &SystemState<S>,
+        time: SimInstant,
+        initiator_party_id: usize,
+        responder_party_id: usize,
+        round: &TranscriptEntry,
+        out: &mut Emitter,
+    ) {
+        for observer in self
+            .iter_mut()
+            .filter(|observer| observer.observes_rounds())
+        {
+            observer.after_round(
+                state,
+                time,
+                initiator_party_id,
+                responder_party_id,
+                round,
+                out,
+            );
+        }
+    }
+
+    fn sample_every(&self) -> Option<SimDuration> {
+        combined_period(self.iter().map(|observer| observer.sample_every()))
+    }
+
+    fn sample(&mut self, state: &SystemState<S>, time: SimInstant, out: &mut Emitter) {
+        for observer in self {
+            if is_sampled(observer.sample_every(), time) {
+                observer.sample(state, time, out);
+            }
+        }
+    }
+}
+
+impl<S: Simulator, A: Observer<S>, B: Observer<S>> Observer<S> for (A, B)
+where
+    SimObject: Object<S::Item>,
+{
+    fn before_event(
+        &mut self,
+        state: &SystemState<S>,
+        time: SimInstant,
+        party_id: usize,
+        event: &Event,
+        out: &mut Emitter,
+    ) {
+        self.0.before_event(state, time, party_id, event, out);
+        self.1.before_event(state, time, party_id, event, out);
+    }
+
+    fn after_event(
+        &mut self,
+        state: &SystemState<S>,
+        time: SimInstant,
+        party_id: usize,
+        entry: &TraceEntry<S::Item, SimObject>,
+        out: &mut Emitter,
+    ) {
+        self.0.after_event(state, time, party_id, entry, out);
+        self.1.after_event(state, time, party_id, entry, out);
+    }
+
+    fn observes_rounds(&self) -> bool {
+        self.0.observes_rounds() || self.1.observes_rounds()
+    }
+
+    fn after_round(
+        &mut self,
+        state: &SystemState<S>,
+        time: SimInstant,
+        initiator_party_id: usize,
+        responder_party_id: usize,
+        round: &TranscriptEntry,
+        out: &mut Emitter,
+    ) {
+        let (initiator, responder) = (initiator_party_id, responder_party_id);
+        if self.0.observes_rounds() {
+            self.0
+                .after_round(state, time, initiator, responder, round, out);
+        }
+        if self.1.observes_rounds() {
+            self.1
+                .after_round(state, time, initiator, responder, round, out);
+        }
+    }
+
+    fn sample_every(&self) -> Option<SimDuration> {
+        combined_period([self.0.sample_every(), self.1.sample_every()])
+    }
+
+    fn sample(&mut self, state: &SystemState<S>, time: SimInstant, out: &mut Emitter) {
+        if is_sampled(self.0.sample_every(), time) {
+            self.0.sample(state, time, out);
+        }
+        if is_sampled(self.1.sample_every(), time) {
+            self.1.sample(state, time, out);
+        }
+    }
+}
+
+/// The period at which all of the periods are due: their greatest common divisor.
+fn combined_period<I: IntoIterator<Item = Option<SimDuration>>>(periods: I) -> Option<SimDuration> {
+    fn gcd(a: u64, b: u64) -> u64 {
+        if b == 0 {
+            a
+        } else {
+            gcd(b, a % b)
+        }
+    }
+
+    periods
+        .into_iter()
+        .flatten()
+        .map(|period| period.0.max(1))
+        .reduce(gcd)
+        .map(SimDuration)
+}
+
+fn is_sampled(period: Option<SimDuration>, time: SimInstant) -> bool {
+    period.is_some_and(|period| time.0.is_multiple_of(period.0.max(1)))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Emitter, Metric, Observer};
+    use crate::experiments::uniform::UniformSim;
+    use crate::scenarios::dynamic::tests::sim_two_parties;
+    use crate::scenarios::dynamic::{
+        Event, MemorySink, SimDuration, SimInstant, SimObject, SimOptions, Simulator, SystemState,
+        TraceEntry,
+    };
+    use crate::scenarios::protocol::TranscriptEntry;
+
+    fn run<O: Observer<UniformSim>>(observer: &mut O) -> MemorySink<UniformSim> {
+        let mut sink = MemorySink::new();
+        sim_two_parties(SimOptions::default(), &mut sink, observer).unwrap();
+        sink
+    }
+
+    /// Counts the events and the bytes of the syncs, round by round.
+    #[derive(Default)]
+    struct Counter {
+        before: usize,
+        after: usize,
+        round_bytes: usize,
+    }
+
+    impl Observer<UniformSim> for Counter {
+        fn before_event(
+            &mut self,
+            _state: &SystemState<UniformSim>,
+            _time: SimInstant,
+            _party_id: usize,
+            _event: &Event,
+            _out: &mut Emitter,
+        ) {
+            self.before += 1;
+        }
+
+        fn after_event(
+            &mut self,
+            _state: &SystemState<UniformSim>,
+            _time: SimInstant,
+            _party_id: usize,
+            _entry: &TraceEntry<<UniformSim as Simulator>::Item, SimObject>,
+            _out: &mut Emitter,
+        ) {
+            self.after += 1;
+        }
+
+        fn observes_rounds(&self) -> bool {
+            true
+        }
+
+        fn after_round(
+            &mut self,
+            _state: &SystemState<UniformSim>,
+            _time: SimInstant,
+            _initiator_party_id: usize,
+            _responder_party_id: usize,
+            round: &TranscriptEntry,
+            _out: &mut Emitter,
+        ) {
+            self.round_bytes += round.bytes;
+        }
+    }
+
+    /// Emits the number of objects every party holds.
+    struct HeldObjects(SimDuration);
+
+    impl Observer<UniformSim> for HeldObjects {
+        fn sample_every(&self) -> Option<SimDuration> {
+            Some(self.0)
+        }
+
+        fn sample(
+            &mut self,
+            state: &SystemState<UniformSim>,
+            _time: SimInstant,
+            out: &mut Emitter,
+        ) {
+            for (party_id, party) in state.party_states().iter().enumerate() {
+                out.emit(party_id, "held_objects", party.objects().len() as f64);
+            }
+        }
+    }
+
+    fn metrics(sink: &MemorySink<UniformSim>) -> Vec<(SimInstant, usize, Metric)> {
+        sink.entries()
+            .iter()
+            .filter_map(|(meta, entry)| match entry {
+                TraceEntry::Metric(metric) => Some((meta.time, meta.party_id, metric.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn hooks() {
+        let mut counter = Counter::default();
+        let sink = run(&mut counter);
+
+        let mut bytes = 0;
+        for (_, entry) in sink.entries() {
+            if let TraceEntry::Sync(_, init, resp, transcript) = entry {
+                bytes += init.bytes_sent + resp.bytes_sent;
+                // recorded for the observer only
+                assert!(transcript.is_none());
+            }
+        }
+        assert!(bytes > 0);
+        assert_eq!(counter.round_bytes, bytes);
+        assert_eq!(counter.before, sink.entries().len());
+        assert_eq!(counter.after, counter.before);
+    }
+
+    #[test]
+    fn samples() {
+        let sink = run(&mut HeldObjects(6 * SimDuration::HOUR));
+        let metrics = metrics(&sink);
+
+        let times: Vec<_> = metrics.iter().map(|(t, _, _)| t.0).step_by(2).collect();
+        assert_eq!(times, vec![0, 360, 720, 1080]);
+        assert_eq!(metrics[0].2.value, 0.0);
+        let held = |party_id: usize| {
+            sink.entries()
+                .iter()
+                .filter(|(meta, _)| meta.time.0 < 1080)
+                .filter(|(_, entry)| match entry {
+                    TraceEntry::Posted(obj) => obj.author == party_id,
+                    _ => false,
+                })
+                .count()
+        };
+        // syncs can only have added objects
+        assert!(metrics[6].2.value >= held(0) as f64);
+        assert_eq!(metrics[6].1, 0);
+    }
+
+    #[test]
+    fn composition() {
+        let mut observers: Vec<Box<dyn Observer<UniformSim>>> = vec![
+            Box::new(HeldObjects(6 * SimDuration::HOUR)),
+            Box::new(HeldObjects(4 * SimDuration::HOUR)),
+        ];
+        assert_eq!(observers.sample_every(), Some(2 * SimDuration::HOUR));
+        let sink = run(&mut observers);
+        // 4 samples every 6 hours and 6 every 4 hours, for two parties each
+        assert_eq!(metrics(&sink).len(), 2 * (4 + 6));
+
+        let mut pair = (Counter::default(), HeldObjects(SimDuration::DAY));
+        let sink = run(&mut pair);
+        assert_eq!(metrics(&sink).len(), 2);
+        assert!(pair.0.round_bytes > 0);
+        assert_eq!(pair.0.before, sink.entries().len() - 2);
+    }
+}
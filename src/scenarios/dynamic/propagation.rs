@@ -0,0 +1,335 @@
+//! How long posts take to spread: for every post, the instant at which each party first held it.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use super::{SimDuration, SimInstant, SimObject};
+
+/// When each party first held a post.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PostPropagation {
+    pub author: usize,
+    pub post_id: usize,
+    pub posted_at: SimInstant,
+    /// Indexed by party id, `None` for parties that never got the post.
+    pub received_at: Vec<Option<SimInstant>>,
+    /// Indexed by party id, whether the party was there when the post was made and didn't
+    /// leave before getting it. Full coverage is measured over these parties.
+    pub present: Vec<bool>,
+    /// The author and post id of the post this is a tombstone for.
+    pub deletes: Option<(usize, usize)>,
+}
+
+impl PostPropagation {
+    /// The number of parties holding the post, including the author.
+    pub fn n_reached(&self) -> usize {
+        self.received_at.iter().flatten().count()
+    }
+
+    /// How long it took each party other than the author to get the post.
+    pub fn delays(&self) -> impl Iterator<Item = SimDuration> + '_ {
+        self.received_at
+            .iter()
+            .enumerate()
+            .filter(move |(party_id, _)| *party_id != self.author)
+            .filter_map(move |(_, t)| t.map(|t| SimDuration(t.0 - self.posted_at.0)))
+    }
+
+    /// How long it took until every party that was present held the post, if it got that far.
+    /// Parties that joined later or left before getting the post don't count.
+    pub fn full_coverage(&self) -> Option<SimDuration> {
+        let mut max = SimDuration::zero();
+        for (received_at, present) in self.received_at.iter().zip(&self.present) {
+            match received_at {
+                Some(t) if *present => max = max.max(SimDuration(t.0 - self.posted_at.0)),
+                Some(_) => {}
+                None if *present => return None,
+                None => {}
+            }
+        }
+        Some(max)
+    }
+}
+
+/// The propagation of all posts of a simulation run.
+#[derive(Debug, Clone, Default)]
+pub struct Propagation {
+    n_parties: usize,
+    present: Vec<bool>,                               // by party id
+    posts: BTreeMap<(usize, usize), PostPropagation>, // by (author, post_id)
+}
+
+impl Propagation {
+    pub fn new(n_parties: usize) -> Self {
+        Propagation {
+            n_parties,
+            present: vec![true; n_parties],
+            posts: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn posted(&mut self, obj: &SimObject) {
+        let mut received_at = vec![None; self.n_parties];
+        received_at[obj.author] = Some(obj.timestamp);
+
+        self.posts.insert(
+            (obj.author, obj.post_id),
+            PostPropagation {
+                author: obj.author,
+                post_id: obj.post_id,
+                posted_at: obj.timestamp,
+                received_at,
+                present: self.present.clone(),
+                deletes: obj
+                    .deletes
+                    .as_ref()
+                    .map(|target| (target.author, target.post_id)),
+            },
+        );
+    }
+
+    /// Records that the party joined, making room for it if it is new. Parties skipped by
+    /// its id are absent until they join themselves. Only posts made from now on wait for it.
+    pub(crate) fn joined(&mut self, party_id: usize) {
+        if party_id >= self.n_parties {
+            self.n_parties = party_id + 1;
+            self.present.resize(self.n_parties, false);
+            for post in self.posts.values_mut() {
+                post.received_at.resize(self.n_parties, None);
+                post.present.resize(self.n_parties, false);
+            }
+        }
+        self.present[party_id] = true;
+    }
+
+    /// Records that the party left. Posts it didn't get no longer wait for it.
+    pub(crate) fn left(&mut self, party_id: usize) {
+        self.present[party_id] = false;
+        for post in self.posts.values_mut() {
+            if post.received_at[party_id].is_none() {
+                post.present[party_id] = false;
+            }
+        }
+    }
+
+    /// Records that the party got the object at `time`, unless it already had it.
+    pub(crate) fn received(&mut self, party_id: usize, obj: &SimObject, time: SimInstant) {
+        if let Some(post) = self.posts.get_mut(&(obj.author, obj.post_id)) {
+            post.received_at[party_id].get_or_insert(time);
+        }
+    }
+
+    pub fn get(&self, author: usize, post_id: usize) -> Option<&PostPropagation> {
+        self.posts.get(&(author, post_id))
+    }
+
+    /// All posts, including tombstones.
+    pub fn posts(&self) -> impl Iterator<Item = &PostPropagation> {
+        self.posts.values()
+    }
+
+    fn of_kind(&self, tombstones: bool) -> impl Iterator<Item = &PostPropagation> {
+        self.posts()
+            .filter(move |post| post.deletes.is_some() == tombstones)
+    }
+
+    /// The distribution of the time it took any post to reach any other party. Tombstones are
+    /// left out, see [`Propagation::deletion_delays`].
+    pub fn delays(&self) -> Option<Distribution> {
+        Distribution::new(
+            self.of_kind(false)
+                .flat_map(PostPropagation::delays)
+                .collect(),
+        )
+    }
+
+    /// The distribution of the time it took posts to reach all parties. Posts that never got
+    /// that far are left out; compare the count to the number of posts.
+    pub fn full_coverage(&self) -> Option<Distribution> {
+        Distribution::new(
+            self.of_kind(false)
+                .filter_map(PostPropagation::full_coverage)
+                .collect(),
+        )
+    }
+
+    /// Like [`Propagation::delays`], for the tombstones of deleted posts.
+    pub fn deletion_delays(&self) -> Option<Distribution> {
+        Distribution::new(
+            self.of_kind(true)
+                .flat_map(PostPropagation::delays)
+                .collect(),
+        )
+    }
+
+    /// Like [`Propagation::full_coverage`], for the tombstones of deleted posts.
+    pub fn deletion_full_coverage(&self) -> Option<Distribution> {
+        Distribution::new(
+            self.of_kind(true)
+                .filter_map(PostPropagation::full_coverage)
+                .collect(),
+        )
+    }
+
+    /// One record per post, for writing to CSV.
+    pub fn records(&self) -> Vec<PropagationRecord> {
+        self.posts()
+            .map(|post| {
+                let delays = Distribution::new(post.delays().collect());
+                PropagationRecord {
+                    author: post.author,
+                    post_id: post.post_id,
+                    posted_at: post.posted_at,
+                    n_reached: post.n_reached(),
+                    n_parties: self.n_parties,
+                    median_delay: delays.as_ref().map(|d| d.p50),
+                    max_delay: delays.as_ref().map(|d| d.max),
+                    full_coverage: post.full_coverage(),
+                    deleted_author: post.deletes.map(|(author, _)| author),
+                    deleted_post_id: post.deletes.map(|(_, post_id)| post_id),
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PropagationRecord {
+    pub author: usize,
+    pub post_id: usize,
+    pub posted_at: SimInstant,
+    pub n_reached: usize,
+    pub n_parties: usize,
+    pub median_delay: Option<SimDuration>,
+    pub max_delay: Option<SimDuration>,
+    pub full_coverage: Option<SimDuration>,
+    pub deleted_author: Option<usize>,
+    pub deleted_post_id: Option<usize>,
+}
+
+/// Summary of a set of durations. Percentiles use the nearest rank.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Distribution {
+    pub count: usize,
+    pub min: SimDuration,
+    pub p50: SimDuration,
+    pub p90: SimDuration,
+    pub p99: SimDuration,
+    pub max: SimDuration,
+    pub mean: SimDuration,
+}
+
+impl Distribution {
+    /// Returns `None` if there are no durations.
+    pub fn new(mut durations: Vec<SimDuration>) -> Option<Self> {
+        if durations.is_empty() {
+            return None;
+        }
+        durations.sort();
+
+        let count = durations.len();
+        let percentile = |p: usize| durations[(p * count).div_ceil(100).max(1) - 1];
+        let sum: u64 = durations.iter().map(|d| d.0).sum();
+
+        Some(Distribution {
+            count,
+            min: durations[0],
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: durations[count - 1],
+            mean: SimDuration(sum / count as u64),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Distribution, Propagation};
+    use crate::scenarios::dynamic::{SimDuration, SimInstant, SimObject};
+
+    #[test]
+    fn propagation() {
+        let obj = SimObject {
+            author: 1,
+            post_id: 0,
+            timestamp: SimInstant(10),
+            deletes: None,
+        };
+
+        let mut propagation = Propagation::new(3);
+        propagation.posted(&obj);
+        propagation.received(0, &obj, SimInstant(15));
+        propagation.received(0, &obj, SimInstant(17));
+
+        let post = propagation.get(1, 0).unwrap();
+        assert_eq!(post.n_reached(), 2);
+        assert_eq!(post.delays().collect::<Vec<_>>(), vec![SimDuration(5)]);
+        assert_eq!(post.full_coverage(), None);
+
+        propagation.received(2, &obj, SimInstant(40));
+        let post = propagation.get(1, 0).unwrap();
+        assert_eq!(post.full_coverage(), Some(SimDuration(30)));
+
+        let delays = propagation.delays().unwrap();
+        assert_eq!(
+            (delays.count, delays.min, delays.max),
+            (2, SimDuration(5), SimDuration(30))
+        );
+        assert_eq!(propagation.full_coverage().unwrap().count, 1);
+    }
+
+    #[test]
+    fn coverage_of_present_parties() {
+        let post = |author, post_id, t| SimObject {
+            author,
+            post_id,
+            timestamp: SimInstant(t),
+            deletes: None,
+        };
+        let (a, b, c) = (post(0, 0, 10), post(0, 1, 20), post(0, 2, 30));
+
+        let mut propagation = Propagation::new(3);
+        propagation.posted(&a);
+        propagation.received(1, &a, SimInstant(12));
+        propagation.received(2, &a, SimInstant(15));
+        propagation.posted(&b);
+        propagation.received(1, &b, SimInstant(25));
+
+        // party 2 leaves without post b, and party 4 joins, leaving a gap at 3
+        propagation.left(2);
+        propagation.joined(4);
+        let coverage = |post_id| propagation.get(0, post_id).unwrap().full_coverage();
+        assert_eq!(coverage(0), Some(SimDuration(5)));
+        assert_eq!(coverage(1), Some(SimDuration(5)));
+
+        // party 4 counts for posts made after it joined, party 3 doesn't until it joins
+        propagation.posted(&c);
+        propagation.received(1, &c, SimInstant(31));
+        assert_eq!(propagation.get(0, 2).unwrap().full_coverage(), None);
+        propagation.received(4, &c, SimInstant(33));
+        assert_eq!(
+            propagation.get(0, 2).unwrap().full_coverage(),
+            Some(SimDuration(3))
+        );
+        assert_eq!(propagation.full_coverage().unwrap().count, 3);
+    }
+
+    #[test]
+    fn distribution() {
+        assert_eq!(Distribution::new(vec![]), None);
+
+        let d = Distribution::new((1..=100).rev().map(SimDuration).collect()).unwrap();
+        assert_eq!(d.count, 100);
+        assert_eq!(d.min, SimDuration(1));
+        assert_eq!(d.p50, SimDuration(50));
+        assert_eq!(d.p90, SimDuration(90));
+        assert_eq!(d.p99, SimDuration(99));
+        assert_eq!(d.max, SimDuration(100));
+        assert_eq!(d.mean, SimDuration(50));
+
+        let d = Distribution::new(vec![SimDuration(7)]).unwrap();
+        assert_eq!((d.p50, d.p99), (SimDuration(7), SimDuration(7)));
+    }
+}
@@ -0,0 +1,293 @@
+//! Reads trace files written by [`CsvSink`](super::CsvSink) back into typed rows, so archived runs
+//! can be analysed without simulating them again.
+
+use std::fs::File;
+use std::io;
+use std::marker::PhantomData;
+use std::path::Path;
+
+use csv::StringRecord;
+use serde::{Deserialize, Serialize};
+use unionize::Object;
+
+use super::{SimObject, Simulator, TraceEntryRecord, TraceMeta};
+
+/// The version of the columns of trace files. Files without a `schema_version` column are
+/// version 0. Before version 2, the event was a single column in Debug form, which is read into
+/// [`TraceMeta::event_debug`].
+pub const TRACE_SCHEMA_VERSION: u32 = 2;
+
+/// The first column of every row of a trace file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Schema {
+    pub schema_version: u32,
+}
+
+impl Schema {
+    pub(crate) const CURRENT: Schema = Schema {
+        schema_version: TRACE_SCHEMA_VERSION,
+    };
+}
+
+#[derive(Debug)]
+pub enum TraceReadError {
+    Csv(csv::Error),
+    /// The file was written by a newer version.
+    UnsupportedSchema(u32),
+    InvalidSchemaVersion(String),
+    MissingColumn(&'static str),
+}
+
+impl From<csv::Error> for TraceReadError {
+    fn from(value: csv::Error) -> Self {
+        TraceReadError::Csv(value)
+    }
+}
+
+impl std::fmt::Display for TraceReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TraceReadError::Csv(e) => write!(f, "{e}"),
+            TraceReadError::UnsupportedSchema(version) => write!(
+                f,
+                "trace schema {version} is newer than the supported {TRACE_SCHEMA_VERSION}"
+            ),
+            TraceReadError::InvalidSchemaVersion(version) => {
+                write!(f, "invalid trace schema version {version:?}")
+            }
+            TraceReadError::MissingColumn(column) => write!(f, "missing column {column:?}"),
+        }
+    }
+}
+
+impl std::error::Error for TraceReadError {}
+
+/// Iterates over the rows of a trace file. The columns up to `kind` are the [`TraceMeta`], the
+/// rest are the [`TraceEntryRecord`].
+pub struct TraceReader<S: Simulator, R: io::Read>
+where
+    SimObject: Object<S::Item>,
+{
+    reader: csv::Reader<R>,
+    schema_version: u32,
+    meta_columns: (usize, usize),
+    meta_headers: StringRecord,
+    record_headers: StringRecord,
+    first_row: Option<StringRecord>, // read ahead to learn the schema version
+    _phantom: PhantomData<S>,
+}
+
+impl<S: Simulator> TraceReader<S, File>
+where
+    SimObject: Object<S::Item>,
+{
+    /// Opens a trace file, tab separated if it ends in `.tsv`, comma separated otherwise.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, TraceReadError> {
+        let path = path.as_ref();
+        let delimiter = match path.extension() {
+            Some(ext) if ext == "tsv" => b'\t',
+            _ => b',',
+        };
+        let reader = csv::ReaderBuilder::new()
+            .flexible(true)
+            .delimiter(delimiter)
+            .from_path(path)?;
+        Self::new(reader)
+    }
+}
+
+impl<S: Simulator, R: io::Read> TraceReader<S, R>
+where
+    SimObject: Object<S::Item>,
+{
+    /// The reader has to read the header row.
+    pub fn new(mut reader: csv::Reader<R>) -> Result<Self, TraceReadError> {
+        let headers = reader.headers()?.clone();
+        let record_start = headers
+            .iter()
+            .position(|header| header == "kind")
+            .ok_or(TraceReadError::MissingColumn("kind"))?;
+
+        let mut first_row = None;
+        let (meta_start, schema_version) = if headers.get(0) == Some("schema_version") {
+            let mut row = StringRecord::new();
+            let schema_version = if reader.read_record(&mut row)? {
+                let version = row.get(0).unwrap_or_default();
+                let version = version
+                    .parse()
+                    .map_err(|_| TraceReadError::InvalidSchemaVersion(version.to_string()))?;
+                first_row = Some(row);
+                version
+            } else {
+                TRACE_SCHEMA_VERSION
+            };
+            (1, schema_version)
+        } else {
+            (0, 0)
+        };
+
+        if schema_version > TRACE_SCHEMA_VERSION {
+            return Err(TraceReadError::UnsupportedSchema(schema_version));
+        }
+
+        let mut meta_headers = columns(&headers, meta_start, record_start);
+        if schema_version < 2 {
+            meta_headers = meta_headers
+                .iter()
+                .map(|header| match header {
+                    "event" => "event_debug",
+                    header => header,
+                })
+                .collect();
+        }
+
+        Ok(TraceReader {
+            reader,
+            schema_version,
+            meta_columns: (meta_start, record_start),
+            meta_headers,
+            record_headers: columns(&headers, record_start, headers.len()),
+            first_row,
+            _phantom: PhantomData,
+        })
+    }
+
+    pub fn schema_version(&self) -> u32 {
+        self.schema_version
+    }
+
+    fn read_row(&mut self) -> Result<Option<(TraceMeta, TraceEntryRecord<S>)>, TraceReadError> {
+        let row = match self.first_row.take() {
+            Some(row) => row,
+            None => {
+                let mut row = StringRecord::new();
+                if !self.reader.read_record(&mut row)? {
+                    return Ok(None);
+                }
+                row
+            }
+        };
+
+        let (meta_start, record_start) = self.meta_columns;
+        let meta = columns(&row, meta_start, record_start).deserialize(Some(&self.meta_headers))?;
+        let record =
+            columns(&row, record_start, row.len()).deserialize(Some(&self.record_headers))?;
+        Ok(Some((meta, record)))
+    }
+}
+
+impl<S: Simulator, R: io::Read> Iterator for TraceReader<S, R>
+where
+    SimObject: Object<S::Item>,
+{
+    type Item = Result<(TraceMeta, TraceEntryRecord<S>), TraceReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_row().transpose()
+    }
+}
+
+fn columns(row: &StringRecord, start: usize, end: usize) -> StringRecord {
+    row.iter().take(end).skip(start).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TraceReadError, TraceReader, TRACE_SCHEMA_VERSION};
+    use crate::experiments::uniform::UniformSim;
+    use crate::scenarios::dynamic::tests::sim_two_parties;
+    use crate::scenarios::dynamic::{
+        ConvergenceCheck, CsvSink, EventKind, MemorySink, SimInstant, SimOptions,
+        SyncFailurePolicy, TraceEntryRecord, TraceSink,
+    };
+
+    fn trace_csv() -> String {
+        let mut entries = MemorySink::new();
+        let options = SimOptions {
+            check_convergence: ConvergenceCheck::Record,
+            on_sync_failure: SyncFailurePolicy::Continue,
+            ..Default::default()
+        };
+        sim_two_parties(options, &mut entries, &mut ()).unwrap();
+
+        let mut sink = CsvSink::<UniformSim, _>::new(
+            csv::WriterBuilder::new().flexible(true).from_writer(vec![]),
+        );
+        for (meta, entry) in entries.into_entries() {
+            sink.record(&meta, entry).unwrap();
+        }
+        String::from_utf8(sink.into_inner().unwrap()).unwrap()
+    }
+
+    fn read(data: &str) -> Result<TraceReader<UniformSim, &[u8]>, TraceReadError> {
+        TraceReader::new(
+            csv::ReaderBuilder::new()
+                .flexible(true)
+                .from_reader(data.as_bytes()),
+        )
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = trace_csv();
+        let reader = read(&data).unwrap();
+        assert_eq!(reader.schema_version(), TRACE_SCHEMA_VERSION);
+        let rows: Vec<_> = reader.collect::<Result<_, _>>().unwrap();
+        assert_eq!(rows.len(), data.lines().count() - 1);
+
+        let (meta, record) = rows
+            .iter()
+            .find(|(_, record)| record.kind == "Sync")
+            .unwrap();
+        assert!(meta.time() > SimInstant::zero());
+        assert_eq!(meta.party_id(), 0);
+        assert_eq!(meta.event_kind(), Some(EventKind::Sync));
+        assert_eq!(meta.sync_partner(), Some(1));
+        assert_eq!(record.sync_resp_party_id, Some(1));
+        assert!(record.sync_initiator_bytes_sent.unwrap() > 0);
+        assert_eq!(record.posted_object_author, None);
+
+        // writing what was read gives the same file
+        let mut wtr = csv::WriterBuilder::new().flexible(true).from_writer(vec![]);
+        for (meta, record) in &rows {
+            wtr.serialize((super::Schema::CURRENT, meta, record))
+                .unwrap();
+        }
+        assert_eq!(String::from_utf8(wtr.into_inner().unwrap()).unwrap(), data);
+    }
+
+    #[test]
+    fn older_schemas() {
+        // before versioning, rows started with the meta and had fewer record columns
+        let data = "time,party_id,event,kind,posted_object_author,posted_object_post_id\n\
+                    3,1,Post,Posted,1,0\n";
+        let mut reader = read(data).unwrap();
+        assert_eq!(reader.schema_version(), 0);
+        let (meta, record): (_, TraceEntryRecord<UniformSim>) = reader.next().unwrap().unwrap();
+        assert_eq!(meta.time(), SimInstant(3));
+        assert_eq!(meta.event_debug(), Some("Post"));
+        assert_eq!(meta.event_kind(), None);
+        assert_eq!(record.posted_object_author, Some(1));
+        assert_eq!(record.sync_resp_party_id, None);
+        assert!(reader.next().is_none());
+
+        let data = "schema_version,time,party_id,event,kind,sync_resp_party_id\n\
+                    1,5,0,Sync(2),Sync,2\n";
+        let mut reader = read(data).unwrap();
+        assert_eq!(reader.schema_version(), 1);
+        let (meta, record) = reader.next().unwrap().unwrap();
+        assert_eq!(meta.event_debug(), Some("Sync(2)"));
+        assert_eq!(meta.sync_partner(), None);
+        assert_eq!(record.sync_resp_party_id, Some(2));
+
+        let data = trace_csv().replacen("\n2,", "\n3,", 1);
+        assert!(matches!(
+            read(&data),
+            Err(TraceReadError::UnsupportedSchema(3))
+        ));
+        assert!(matches!(
+            read("time,party_id\n1,2\n"),
+            Err(TraceReadError::MissingColumn("kind"))
+        ));
+    }
+}
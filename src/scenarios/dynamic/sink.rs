@@ -0,0 +1,287 @@
+//! Where the trace of a simulation run goes. [`Simulator::sim_into`] hands every entry to a
+//! [`TraceSink`] as soon as it happens, so long runs don't have to keep the whole trace around.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::marker::PhantomData;
+
+use unionize::Object;
+
+use super::reader::Schema;
+use super::{SimObject, Simulator, TraceEntry, TraceEntryRecord, TraceMeta, TranscriptRecord};
+
+pub trait TraceSink<S: Simulator>
+where
+    SimObject: Object<S::Item>,
+{
+    /// Takes the next entry. The simulation stops at the first error and returns it.
+    fn record(&mut self, meta: &TraceMeta, entry: TraceEntry<S::Item, SimObject>)
+        -> io::Result<()>;
+}
+
+/// Keeps all entries, like [`Trace`](super::Trace) does.
+#[derive(Debug, Clone)]
+pub struct MemorySink<S: Simulator>
+where
+    SimObject: Object<S::Item>,
+{
+    entries: Vec<(TraceMeta, TraceEntry<S::Item, SimObject>)>,
+}
+
+impl<S: Simulator> MemorySink<S>
+where
+    SimObject: Object<S::Item>,
+{
+    pub fn new() -> Self {
+        MemorySink { entries: vec![] }
+    }
+
+    pub fn entries(&self) -> &Vec<(TraceMeta, TraceEntry<S::Item, SimObject>)> {
+        &self.entries
+    }
+
+    pub fn into_entries(self) -> Vec<(TraceMeta, TraceEntry<S::Item, SimObject>)> {
+        self.entries
+    }
+}
+
+impl<S: Simulator> Default for MemorySink<S>
+where
+    SimObject: Object<S::Item>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: Simulator> TraceSink<S> for MemorySink<S>
+where
+    SimObject: Object<S::Item>,
+{
+    fn record(
+        &mut self,
+        meta: &TraceMeta,
+        entry: TraceEntry<S::Item, SimObject>,
+    ) -> io::Result<()> {
+        self.entries.push((meta.clone(), entry));
+        Ok(())
+    }
+}
+
+/// Writes one row per entry: the schema version, the [`TraceMeta`] and the [`TraceEntryRecord`].
+/// Rows go out whenever the writer's buffer is full, so the file holds most of the trace even if
+/// the run doesn't finish. [`TraceReader`](super::TraceReader) reads them back.
+pub struct CsvSink<S: Simulator, W: io::Write>
+where
+    SimObject: Object<S::Item>,
+{
+    writer: csv::Writer<W>,
+    transcripts: Option<csv::Writer<W>>,
+    n_entries: usize,
+    _phantom: PhantomData<S>,
+}
+
+impl<S: Simulator, W: io::Write> CsvSink<S, W>
+where
+    SimObject: Object<S::Item>,
+{
+    /// The writer should be flexible, because the meta and record columns are written as one
+    /// row.
+    pub fn new(writer: csv::Writer<W>) -> Self {
+        CsvSink {
+            writer,
+            transcripts: None,
+            n_entries: 0,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Also writes the transcripts of the syncs, one [`TranscriptRecord`] per round, if the run
+    /// records them.
+    pub fn with_transcripts(mut self, writer: csv::Writer<W>) -> Self {
+        self.transcripts = Some(writer);
+        self
+    }
+
+    /// The number of rows written so far.
+    pub fn n_entries(&self) -> usize {
+        self.n_entries
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        if let Some(transcripts) = &mut self.transcripts {
+            transcripts.flush()?;
+        }
+        self.writer.flush()
+    }
+
+    /// Flushes the rows and returns the underlying writer of the trace.
+    pub fn into_inner(self) -> io::Result<W> {
+        self.writer.into_inner().map_err(|e| e.into_error())
+    }
+}
+
+impl<S: Simulator, W: io::Write> TraceSink<S> for CsvSink<S, W>
+where
+    SimObject: Object<S::Item>,
+{
+    fn record(
+        &mut self,
+        meta: &TraceMeta,
+        entry: TraceEntry<S::Item, SimObject>,
+    ) -> io::Result<()> {
+        if let Some(transcripts) = &mut self.transcripts {
+            for record in TranscriptRecord::of_entry(meta, &entry) {
+                transcripts.serialize(record).map_err(io_error)?;
+            }
+        }
+        let rec: TraceEntryRecord<S> = entry.into();
+        self.writer
+            .serialize((Schema::CURRENT, meta, rec))
+            .map_err(io_error)?;
+        self.n_entries += 1;
+        Ok(())
+    }
+}
+
+/// Keeps the kind of i/o errors, which converting the csv error would hide.
+fn io_error(e: csv::Error) -> io::Error {
+    match e.into_kind() {
+        csv::ErrorKind::Io(e) => e,
+        kind => io::Error::other(format!("{kind:?}")),
+    }
+}
+
+/// Only counts the entries, by [`TraceEntry::kind`].
+#[derive(Debug, Clone, Default)]
+pub struct CountingSink {
+    counts: BTreeMap<&'static str, usize>,
+}
+
+impl CountingSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn count(&self, kind: &str) -> usize {
+        self.counts.get(kind).copied().unwrap_or(0)
+    }
+
+    pub fn counts(&self) -> &BTreeMap<&'static str, usize> {
+        &self.counts
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.values().sum()
+    }
+}
+
+impl<S: Simulator> TraceSink<S> for CountingSink
+where
+    SimObject: Object<S::Item>,
+{
+    fn record(
+        &mut self,
+        _meta: &TraceMeta,
+        entry: TraceEntry<S::Item, SimObject>,
+    ) -> io::Result<()> {
+        *self.counts.entry(entry.kind()).or_default() += 1;
+        Ok(())
+    }
+}
+
+/// Drops all entries, for when only the propagation or the final state matter.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NullSink;
+
+impl<S: Simulator> TraceSink<S> for NullSink
+where
+    SimObject: Object<S::Item>,
+{
+    fn record(
+        &mut self,
+        _meta: &TraceMeta,
+        _entry: TraceEntry<S::Item, SimObject>,
+    ) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{CountingSink, CsvSink, MemorySink, NullSink, TraceSink};
+    use crate::experiments::uniform::UniformSim;
+    use crate::scenarios::dynamic::tests::sim_two_parties;
+    use crate::scenarios::dynamic::{SimOptions, SimOutcome};
+
+    fn run<K: TraceSink<UniformSim>>(sink: &mut K) -> io::Result<SimOutcome> {
+        sim_two_parties(SimOptions::default(), sink, &mut ())
+    }
+
+    /// Accepts a few bytes, then fails.
+    struct FullDisk(usize);
+
+    impl io::Write for FullDisk {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.0 < buf.len() {
+                return Err(io::Error::new(io::ErrorKind::StorageFull, "disk full"));
+            }
+            self.0 -= buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sinks_see_the_same_entries() {
+        let mut memory = MemorySink::new();
+        let outcome = run(&mut memory).unwrap();
+        let entries = memory.entries();
+        assert!(!entries.is_empty());
+
+        let mut counting = CountingSink::new();
+        run(&mut counting).unwrap();
+        assert_eq!(counting.total(), entries.len());
+        let posts = entries.iter().filter(|(_, e)| e.kind() == "Posted").count();
+        assert_eq!(counting.count("Posted"), posts);
+        assert_eq!(outcome.propagation.posts().count(), posts);
+
+        let mut csv = CsvSink::<UniformSim, _>::new(
+            csv::WriterBuilder::new().flexible(true).from_writer(vec![]),
+        );
+        run(&mut csv).unwrap();
+        assert_eq!(csv.n_entries(), entries.len());
+        let data = String::from_utf8(csv.into_inner().unwrap()).unwrap();
+        assert_eq!(data.lines().count(), entries.len() + 1); // header
+
+        // the outcome doesn't depend on where the trace goes
+        let null_outcome = run(&mut NullSink).unwrap();
+        assert_eq!(
+            null_outcome.propagation.records().len(),
+            outcome.propagation.records().len()
+        );
+    }
+
+    #[test]
+    fn write_errors_stop_the_simulation() {
+        // csv buffers rows, so make the buffer small enough to hit the writer during the run
+        let writer = csv::WriterBuilder::new()
+            .flexible(true)
+            .buffer_capacity(64)
+            .from_writer(FullDisk(2048));
+        let mut sink = CsvSink::<UniformSim, _>::new(writer);
+
+        let err = run(&mut sink).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::StorageFull);
+        assert!(sink.n_entries() > 0);
+
+        let mut counting = CountingSink::new();
+        run(&mut counting).unwrap();
+        assert!(sink.n_entries() < counting.total());
+    }
+}
@@ -1,13 +1,104 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use serde::{Deserialize, Serialize};
 use unionize::{
     protocol::{
-        first_message, respond_to_message, Encodable, Message, ProtocolMonoid, RespondError,
+        first_message, respond_to_message, Encodable, Fingerprint, Message, ProtocolMonoid,
+        RespondError,
     },
     Monoid, Node, Object,
 };
 
+/// The new objects for initiator and responder, and their stats; or the error that ended the run.
+pub type ProtocolResult<O> = Result<(Vec<O>, Vec<O>, RunStats, RunStats), Box<SyncError<O>>>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SyncErrorKind {
+    Encode,
+    Decode,
+    /// The run took more rounds than [`ProtocolParams::max_rounds`].
+    RoundLimit,
+    /// A party sent a fingerprint it already sent in an earlier round, so the run would never
+    /// end.
+    Cycle,
+    /// A message was dropped and no copy of it arrived, so the party waiting for the answer
+    /// gave up. See [`Faults::drop`].
+    Lost,
+    /// A message arrived cut short and couldn't be decoded. See [`Faults::truncate`].
+    Truncated,
+    /// The session was cut, see [`Faults::kill_after`].
+    Killed,
+}
+
+impl SyncErrorKind {
+    fn respond<M: ProtocolMonoid>(err: RespondError<M>) -> (Self, String) {
+        match err {
+            RespondError::EncodeError(e) => (SyncErrorKind::Encode, format!("{e:?}")),
+            RespondError::DecodeError(e) => (SyncErrorKind::Decode, format!("{e:?}")),
+        }
+    }
+}
+
+/// A protocol run that ended early, and what had been sent until then.
+#[derive(Clone, Debug, Serialize)]
+pub struct SyncError<O> {
+    pub kind: SyncErrorKind,
+    pub message: String,
+    /// The round that failed, counting from zero.
+    pub round: usize,
+    pub initiator_stats: RunStats,
+    pub responder_stats: RunStats,
+    /// The objects that arrived at the initiator before the run ended. Whether they are kept is
+    /// up to the caller.
+    pub initiator_new_objects: Vec<O>,
+    pub responder_new_objects: Vec<O>,
+}
+
+impl<O> std::fmt::Display for SyncError<O> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} error in round {}: {}",
+            self.kind, self.round, self.message
+        )
+    }
+}
+
+impl<O: std::fmt::Debug> std::error::Error for SyncError<O> {}
+
+/// Turns protocol messages into bytes, so we can measure what actually goes over the wire.
+pub trait MessageCodec {
+    fn encoded_len<T: Serialize>(&self, value: &T) -> usize;
+}
+
+/// Encodes messages as CBOR, which is what unionize uses itself.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Cbor;
+
+impl MessageCodec for Cbor {
+    fn encoded_len<T: Serialize>(&self, value: &T) -> usize {
+        let mut counter = ByteCounter(0);
+        serde_cbor::to_writer(&mut counter, value).expect("encoding to CBOR failed");
+        counter.0
+    }
+}
+
+/// A writer that only counts the bytes written to it.
+struct ByteCounter(usize);
+
+impl std::io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
 #[derive(Clone, Debug, Serialize)]
 pub struct RunStats {
     pub msgs_sent: usize,
@@ -17,10 +108,12 @@ pub struct RunStats {
     pub items_wanted: usize,
     pub objects_sent: usize,
     pub items_known: usize,
+    pub bytes_sent: usize,
+    pub object_bytes_sent: usize, // the part of bytes_sent that are provided objects
 }
 
 impl RunStats {
-    fn new(items_known: usize) -> Self {
+    pub fn new(items_known: usize) -> Self {
         RunStats {
             msgs_sent: 0,
             item_sets_sent: 0,
@@ -29,10 +122,14 @@ impl RunStats {
             items_wanted: 0,
             objects_sent: 0,
             items_known,
+            bytes_sent: 0,
+            object_bytes_sent: 0,
         }
     }
-    fn consume<M, O>(&mut self, msg: &Message<M, O>)
+    /// Adds the message to the stats and returns its encoded length.
+    fn consume<M, O, C>(&mut self, msg: &Message<M, O>, codec: &C) -> usize
     where
+        C: MessageCodec,
         M: ProtocolMonoid,
         O: Object<M::Item> + Serialize + for<'de2> serde::Deserialize<'de2>,
         <M as unionize::Monoid>::Item: Serialize,
@@ -50,18 +147,543 @@ impl RunStats {
             .fold(0, |acc, set| acc + set.items().len());
         self.items_wanted += msg.wants().len();
         self.objects_sent += msg.provide().len();
+        let bytes = codec.encoded_len(msg);
+        self.bytes_sent += bytes;
+        self.object_bytes_sent += codec.encoded_len(msg.provide());
+        bytes
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Role {
+    Initiator,
+    Responder,
+}
+
+impl Role {
+    fn other(self) -> Role {
+        match self {
+            Role::Initiator => Role::Responder,
+            Role::Responder => Role::Initiator,
+        }
+    }
+}
+
+/// What was sent in a single round of a protocol run.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TranscriptEntry {
+    pub round: usize,
+    pub sender: Role,
+    pub fingerprints: usize,
+    pub item_set_sizes: Vec<usize>,
+    pub wants: usize,
+    pub provides: usize,
+    pub bytes: usize,
+}
+
+impl TranscriptEntry {
+    fn new<M, O>(round: usize, sender: Role, msg: &Message<M, O>, bytes: usize) -> Self
+    where
+        M: ProtocolMonoid,
+        O: Object<M::Item> + Serialize + for<'de2> serde::Deserialize<'de2>,
+        <M as unionize::Monoid>::Item: Serialize,
+        <M as Encodable>::Encoded: Serialize,
+        for<'de2> <M as unionize::Monoid>::Item: Deserialize<'de2>,
+        for<'de2> <M as Encodable>::Encoded: Deserialize<'de2>,
+    {
+        TranscriptEntry {
+            round,
+            sender,
+            fingerprints: msg.fingerprints().len(),
+            item_set_sizes: msg
+                .item_sets()
+                .iter()
+                .map(|set| set.items().len())
+                .collect(),
+            wants: msg.wants().len(),
+            provides: msg.provide().len(),
+            bytes,
+        }
+    }
+}
+
+/// The messages of a protocol run, one entry per round. Only recorded if asked for, because
+/// this gets big for long runs.
+pub type Transcript = Vec<TranscriptEntry>;
+
+/// The knobs of the protocol itself.
+#[derive(Clone, Copy, Debug)]
+pub struct ProtocolParams {
+    /// Ranges with fewer items than this are sent as item sets instead of being split further.
+    pub threshold: usize,
+    pub split: fn(usize) -> Vec<usize>,
+    /// Give up after this many messages.
+    pub max_rounds: Option<usize>,
+    /// Give up when a party sends a fingerprint it already sent in an earlier round, e.g.
+    /// because the split function returns the whole range as one of the parts.
+    pub detect_cycles: bool,
+    /// Send the messages over a flaky channel instead of a perfect one.
+    pub faults: Option<Faults>,
+}
+
+impl ProtocolParams {
+    /// Without a round limit, but with cycle detection.
+    pub fn new(threshold: usize, split: fn(usize) -> Vec<usize>) -> Self {
+        ProtocolParams {
+            threshold,
+            split,
+            max_rounds: None,
+            detect_cycles: true,
+            faults: None,
+        }
+    }
+}
+
+/// What can go wrong with a message between the parties. The probabilities are per message and
+/// between 0 and 1; at most one fault happens to a message.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Faults {
+    /// The message is lost. The run goes on with the other messages in flight, if any, and fails
+    /// once nothing is left, unless a copy of the message arrived.
+    pub drop: f64,
+    /// The message arrives twice. The receiver can't tell and answers both copies, and the
+    /// answers go on as separate exchanges. Copies and the answers to them aren't duplicated again.
+    pub duplicate: f64,
+    /// Only the start of the message arrives, which fails the run.
+    pub truncate: f64,
+    /// The message is overtaken by the next one in flight. This only happens while more than one
+    /// message is in flight, i.e. after a duplicate.
+    pub reorder: f64,
+    /// Cut the session once this many messages arrived.
+    pub kill_after: Option<usize>,
+    /// Seeds the faults of the run. The simulator mixes it with a new draw for every sync, so
+    /// other seeds pick other faults for the same simulation run.
+    pub seed: u64,
+}
+
+impl Faults {
+    pub fn check(&self) -> Result<(), String> {
+        for (name, p) in [
+            ("drop", self.drop),
+            ("duplicate", self.duplicate),
+            ("truncate", self.truncate),
+            ("reorder", self.reorder),
+        ] {
+            if !(0.0..=1.0).contains(&p) {
+                return Err(format!("{name} probability {p} is not between 0 and 1"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses comma separated `<fault>=<value>` pairs, e.g. `drop=0.01,kill-after=20,seed=3`.
+/// Faults that are left out don't happen.
+impl std::str::FromStr for Faults {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut faults = Faults::default();
+        for pair in s.split(',') {
+            let Some((name, value)) = pair.split_once('=') else {
+                return Err(format!("expected <fault>=<value>, got {pair:?}"));
+            };
+            let invalid = || format!("invalid value {value:?} for {name}");
+            match name {
+                "drop" => faults.drop = value.parse().map_err(|_| invalid())?,
+                "duplicate" => faults.duplicate = value.parse().map_err(|_| invalid())?,
+                "truncate" => faults.truncate = value.parse().map_err(|_| invalid())?,
+                "reorder" => faults.reorder = value.parse().map_err(|_| invalid())?,
+                "kill-after" => faults.kill_after = Some(value.parse().map_err(|_| invalid())?),
+                "seed" => faults.seed = value.parse().map_err(|_| invalid())?,
+                _ => {
+                    return Err(format!(
+                        "unknown fault {name:?}, \
+                         use drop, duplicate, truncate, reorder, kill-after or seed"
+                    ))
+                }
+            }
+        }
+        faults.check()?;
+        Ok(faults)
+    }
+}
+
+enum Fault {
+    Drop,
+    Duplicate,
+    Truncate,
+    Reorder,
+}
+
+/// Decides which [`Faults`] happen to the messages.
+struct Channel {
+    faults: Faults,
+    rng: ChaCha8Rng,
+}
+
+impl Channel {
+    fn new(faults: Faults) -> Self {
+        Channel {
+            faults,
+            rng: ChaCha8Rng::seed_from_u64(faults.seed),
+        }
+    }
+
+    fn fault<M, O>(&mut self, sent: &Sent<M, O>, others_in_flight: bool) -> Option<Fault>
+    where
+        M: ProtocolMonoid,
+        O: Object<M::Item> + Serialize + for<'de2> Deserialize<'de2>,
+        M::Item: Serialize,
+        M::Encoded: Serialize,
+        for<'de2> M::Item: Deserialize<'de2>,
+        for<'de2> M::Encoded: Deserialize<'de2>,
+    {
+        let Faults {
+            drop,
+            duplicate,
+            truncate,
+            reorder,
+            ..
+        } = self.faults;
+        if self.rng.gen_bool(drop) {
+            Some(Fault::Drop)
+        } else if self.rng.gen_bool(truncate) {
+            Some(Fault::Truncate)
+        } else if !sent.copy && self.rng.gen_bool(duplicate) {
+            Some(Fault::Duplicate)
+        } else if others_in_flight && !sent.reordered && self.rng.gen_bool(reorder) {
+            Some(Fault::Reorder)
+        } else {
+            None
+        }
+    }
+}
+
+/// The rounds fingerprints were sent in, by sender, exchange and range.
+type SentFingerprints<M> = BTreeMap<
+    (Role, usize, <M as Monoid>::Item, <M as Monoid>::Item),
+    Vec<(usize, <M as Encodable>::Encoded)>,
+>;
+
+/// Stops protocol runs that don't end, see [`ProtocolParams::max_rounds`] and
+/// [`ProtocolParams::detect_cycles`].
+struct Watchdog<M>
+where
+    M: ProtocolMonoid,
+    M::Item: Serialize,
+    M::Encoded: Serialize,
+    for<'de2> M::Item: Deserialize<'de2>,
+    for<'de2> M::Encoded: Deserialize<'de2>,
+{
+    max_rounds: Option<usize>,
+    detect_cycles: bool,
+    /// Fingerprints can only be compared for equality, so the ranges narrow them down.
+    sent: SentFingerprints<M>,
+}
+
+impl<M> Watchdog<M>
+where
+    M: ProtocolMonoid,
+    M::Item: Serialize,
+    M::Encoded: Serialize,
+    for<'de2> M::Item: Deserialize<'de2>,
+    for<'de2> M::Encoded: Deserialize<'de2>,
+{
+    fn new(params: &ProtocolParams) -> Self {
+        Watchdog {
+            max_rounds: params.max_rounds,
+            detect_cycles: params.detect_cycles,
+            sent: BTreeMap::new(),
+        }
+    }
+
+    /// Checks the message sent in `round` before the next one is sent. Fingerprints are only
+    /// compared within an exchange, because the answers to duplicates repeat each other.
+    fn check<O>(
+        &mut self,
+        round: usize,
+        sender: Role,
+        exchange: usize,
+        msg: &Message<M, O>,
+    ) -> Result<(), (SyncErrorKind, String)>
+    where
+        O: Object<M::Item> + Serialize + for<'de2> Deserialize<'de2>,
+    {
+        if let Some(max_rounds) = self.max_rounds {
+            if round + 1 >= max_rounds {
+                let message = format!("no end after {max_rounds} rounds");
+                return Err((SyncErrorKind::RoundLimit, message));
+            }
+        }
+
+        if self.detect_cycles {
+            // ranges only get smaller in runs that make progress, so a fingerprint that was
+            // sent before means the parties are going around in circles
+            let key = |fingerprint: &Fingerprint<M>| {
+                let range = fingerprint.range();
+                (sender, exchange, range.from().clone(), range.to().clone())
+            };
+            for fingerprint in msg.fingerprints() {
+                let earlier = self
+                    .sent
+                    .get(&key(fingerprint))
+                    .and_then(|earlier| earlier.iter().find(|(_, fp)| fp == fingerprint.fp()));
+                if let Some((earlier_round, _)) = earlier {
+                    let message = format!(
+                        "{sender:?} repeats a fingerprint of round {earlier_round}: {:?}",
+                        fingerprint.range()
+                    );
+                    return Err((SyncErrorKind::Cycle, message));
+                }
+            }
+            for fingerprint in msg.fingerprints() {
+                self.sent
+                    .entry(key(fingerprint))
+                    .or_default()
+                    .push((round, fingerprint.fp().clone()));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A message on its way.
+struct Sent<M, O>
+where
+    M: ProtocolMonoid,
+    O: Object<M::Item> + Serialize + for<'de2> Deserialize<'de2>,
+    M::Item: Serialize,
+    M::Encoded: Serialize,
+    for<'de2> M::Item: Deserialize<'de2>,
+    for<'de2> M::Encoded: Deserialize<'de2>,
+{
+    to: Role,
+    msg: Message<M, O>,
+    /// The round it was sent in, which copies of it share.
+    round: usize,
+    exchange: usize,
+    copy: bool,
+    reordered: bool,
+}
+
+/// What a party sent, and the objects it got.
+struct Side<I, O> {
+    stats: RunStats,
+    new_objects: Vec<O>,
+    received: BTreeSet<I>, // the answers to duplicates provide the same objects again
+}
+
+impl<I: Ord, O: Object<I>> Side<I, O> {
+    fn new(items_known: usize) -> Self {
+        Side {
+            stats: RunStats::new(items_known),
+            new_objects: vec![],
+            received: BTreeSet::new(),
+        }
+    }
+
+    fn receive(&mut self, objects: Vec<O>) {
+        for obj in objects {
+            if self.received.insert(obj.to_item()) {
+                self.new_objects.push(obj);
+            }
+        }
+    }
+}
+
+/// What ended a run, and in which round.
+type Failure = ((SyncErrorKind, String), usize);
+
+/// A protocol run and the messages in flight. Without [`Faults`], there is only ever one
+/// message in flight, and the parties take turns.
+struct Session<'t, M, O>
+where
+    M: ProtocolMonoid,
+    O: Object<M::Item> + Serialize + for<'de2> Deserialize<'de2>,
+    M::Item: Serialize,
+    M::Encoded: Serialize,
+    for<'de2> M::Item: Deserialize<'de2>,
+    for<'de2> M::Encoded: Deserialize<'de2>,
+{
+    watchdog: Watchdog<M>,
+    channel: Option<Channel>,
+    transcript: Option<&'t mut Transcript>,
+    round: usize,
+    n_exchanges: usize,
+    in_flight: VecDeque<Sent<M, O>>,
+    n_delivered: usize,
+    delivered: BTreeSet<usize>, // rounds
+    dropped: Vec<usize>,        // rounds
+    initiator: Side<M::Item, O>,
+    responder: Side<M::Item, O>,
+}
+
+impl<'t, M, O> Session<'t, M, O>
+where
+    M: ProtocolMonoid,
+    O: Object<M::Item> + Serialize + for<'de2> Deserialize<'de2>,
+    M::Item: Serialize,
+    M::Encoded: Serialize,
+    for<'de2> M::Item: Deserialize<'de2>,
+    for<'de2> M::Encoded: Deserialize<'de2>,
+{
+    fn new(
+        params: &ProtocolParams,
+        initiator_items_known: usize,
+        responder_items_known: usize,
+        transcript: Option<&'t mut Transcript>,
+    ) -> Self {
+        Session {
+            watchdog: Watchdog::new(params),
+            channel: params.faults.map(Channel::new),
+            transcript,
+            round: 0,
+            n_exchanges: 1,
+            in_flight: VecDeque::new(),
+            n_delivered: 0,
+            delivered: BTreeSet::new(),
+            dropped: vec![],
+            initiator: Side::new(initiator_items_known),
+            responder: Side::new(responder_items_known),
+        }
+    }
+
+    fn side(&mut self, role: Role) -> &mut Side<M::Item, O> {
+        match role {
+            Role::Initiator => &mut self.initiator,
+            Role::Responder => &mut self.responder,
+        }
+    }
+
+    /// Counts the message and puts it in flight. The message that ends an exchange is counted,
+    /// but there is nothing to answer, so it isn't delivered. Answers to copies are copies too,
+    /// so the exchanges that duplicates start don't fork again.
+    fn send<C: MessageCodec>(
+        &mut self,
+        sender: Role,
+        msg: Message<M, O>,
+        exchange: usize,
+        copy: bool,
+        codec: &C,
+    ) -> Result<(), Failure> {
+        let round = self.round;
+        let bytes = self.side(sender).stats.consume(&msg, codec);
+        if let Some(transcript) = self.transcript.as_deref_mut() {
+            transcript.push(TranscriptEntry::new(round, sender, &msg, bytes));
+        }
+        self.round += 1;
+        if msg.is_end() {
+            return Ok(());
+        }
+
+        self.watchdog
+            .check(round, sender, exchange, &msg)
+            .map_err(|e| (e, round))?;
+        self.in_flight.push_back(Sent {
+            to: sender.other(),
+            msg,
+            round,
+            exchange,
+            copy,
+            reordered: false,
+        });
+        Ok(())
+    }
+
+    /// The next message that arrives, or `None` once all exchanges ended.
+    fn next(&mut self) -> Result<Option<Sent<M, O>>, Failure> {
+        while let Some(mut sent) = self.in_flight.pop_front() {
+            let Some(channel) = &mut self.channel else {
+                return Ok(Some(sent));
+            };
+            if let Some(kill_after) = channel.faults.kill_after {
+                if self.n_delivered >= kill_after {
+                    let message = format!("session cut after {kill_after} messages");
+                    return Err(((SyncErrorKind::Killed, message), sent.round));
+                }
+            }
+
+            match channel.fault(&sent, !self.in_flight.is_empty()) {
+                None => {}
+                Some(Fault::Drop) => {
+                    self.dropped.push(sent.round);
+                    continue;
+                }
+                Some(Fault::Truncate) => {
+                    let message = format!("{:?} got a truncated message", sent.to);
+                    return Err(((SyncErrorKind::Truncated, message), sent.round));
+                }
+                Some(Fault::Duplicate) => {
+                    self.in_flight.push_back(Sent {
+                        to: sent.to,
+                        msg: sent.msg.clone(),
+                        round: sent.round,
+                        exchange: self.n_exchanges,
+                        copy: true,
+                        reordered: sent.reordered,
+                    });
+                    self.n_exchanges += 1;
+                }
+                Some(Fault::Reorder) => {
+                    sent.reordered = true;
+                    self.in_flight.insert(1, sent);
+                    continue;
+                }
+            }
+
+            self.n_delivered += 1;
+            self.delivered.insert(sent.round);
+            return Ok(Some(sent));
+        }
+
+        match self
+            .dropped
+            .iter()
+            .find(|round| !self.delivered.contains(round))
+        {
+            Some(&round) => {
+                let message = format!("the message of round {round} was lost");
+                Err(((SyncErrorKind::Lost, message), round))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn fail(&self, ((kind, message), round): Failure) -> Box<SyncError<O>> {
+        Box::new(SyncError {
+            kind,
+            message,
+            round,
+            initiator_stats: self.initiator.stats.clone(),
+            responder_stats: self.responder.stats.clone(),
+            initiator_new_objects: self.initiator.new_objects.clone(),
+            responder_new_objects: self.responder.new_objects.clone(),
+        })
+    }
+
+    fn finish(self) -> (Vec<O>, Vec<O>, RunStats, RunStats) {
+        (
+            self.initiator.new_objects,
+            self.responder.new_objects,
+            self.initiator.stats,
+            self.responder.stats,
+        )
     }
 }
 
-pub fn run_protocol<M, N, O>(
+pub fn run_protocol<M, N, O, C>(
     initiator_node: &N,
     initiator_objects: &BTreeMap<M::Item, O>,
     responder_node: &N,
     responder_objects: &BTreeMap<M::Item, O>,
-    threshold: usize,
-    split: fn(usize) -> Vec<usize>,
-) -> Result<(Vec<O>, Vec<O>, RunStats, RunStats), RespondError<M>>
+    params: ProtocolParams,
+    codec: &C,
+    transcript: Option<&mut Transcript>,
+) -> ProtocolResult<O>
 where
+    C: MessageCodec,
     M: Monoid + Encodable + ProtocolMonoid,
     N: Node<M>,
     O: Object<M::Item> + for<'de2> Deserialize<'de2> + Serialize,
@@ -70,44 +692,256 @@ where
     for<'de2> M::Item: Deserialize<'de2>,
     for<'de2> M::Encoded: Deserialize<'de2>,
 {
-    let mut new_objects_initiator = vec![];
-    let mut new_objects_responder = vec![];
-
-    let mut stats_initiator = RunStats::new(initiator_objects.len());
-    let mut stats_responder = RunStats::new(responder_objects.len());
-
-    let mut msg = first_message(initiator_node)?;
-    stats_initiator.consume(&msg);
-
-    loop {
-        // println!("i: {msg:#?}");
-        let (resp, mut new_objs) =
-            respond_to_message(responder_node, responder_objects, &msg, threshold, split)?;
-        msg = resp;
-        stats_responder.consume(&msg);
-        new_objects_responder.append(&mut new_objs);
-        if msg.is_end() {
-            break;
-        }
+    let ProtocolParams {
+        threshold, split, ..
+    } = params;
+    let mut session = Session::new(
+        &params,
+        initiator_objects.len(),
+        responder_objects.len(),
+        transcript,
+    );
 
-        // println!("r: {msg:#?}");
+    let msg = first_message(initiator_node).map_err(|e| {
+        let e = RespondError::<M>::from(e);
+        session.fail((SyncErrorKind::respond(e), 0))
+    })?;
+    session
+        .send(Role::Initiator, msg, 0, false, codec)
+        .map_err(|e| session.fail(e))?;
 
-        let (resp, mut new_objs) =
-            respond_to_message(initiator_node, initiator_objects, &msg, threshold, split)?;
-        msg = resp;
-        stats_initiator.consume(&msg);
-        new_objects_initiator.append(&mut new_objs);
-        if msg.is_end() {
-            break;
+    while let Some(sent) = session.next().map_err(|e| session.fail(e))? {
+        let (node, objects) = match sent.to {
+            Role::Initiator => (initiator_node, initiator_objects),
+            Role::Responder => (responder_node, responder_objects),
+        };
+        let (resp, new_objs) = respond_to_message(node, objects, &sent.msg, threshold, split)
+            .map_err(|e| session.fail((SyncErrorKind::respond(e), session.round)))?;
+        session.side(sent.to).receive(new_objs);
+        session
+            .send(sent.to, resp, sent.exchange, sent.copy, codec)
+            .map_err(|e| session.fail(e))?;
+    }
+
+    Ok(session.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use unionize::Object;
+
+    use super::{run_protocol, Cbor, Faults, MessageCodec, ProtocolParams, SyncErrorKind};
+    use crate::scenarios::dynamic::{SimInstant, SimObject};
+    use crate::scenarios::tree::{mem_rc, Tree};
+    use crate::suites::uniform;
+
+    type UniformTree = mem_rc::Tree<uniform::Monoid>;
+
+    fn party(authors: std::ops::Range<usize>) -> (UniformTree, BTreeMap<uniform::Item, SimObject>) {
+        let mut tree = UniformTree::nil();
+        let mut objects = BTreeMap::new();
+        for author in authors {
+            let obj = SimObject {
+                author,
+                post_id: author,
+                timestamp: SimInstant(author as u64),
+                deletes: None,
+            };
+            let item: uniform::Item = obj.to_item();
+            tree.insert(item);
+            objects.insert(item, obj);
         }
+        (tree, objects)
+    }
+
+    #[test]
+    fn counts_encoded_bytes() {
+        let (tree_a, objects_a) = party(0..20);
+        let (tree_b, objects_b) = party(10..40);
+
+        let (new_a, new_b, stats_a, stats_b) = run_protocol(
+            tree_a.node(),
+            &objects_a,
+            tree_b.node(),
+            &objects_b,
+            ProtocolParams::new(3, uniform::split::<2>),
+            &Cbor,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(new_a.len(), 20);
+        assert_eq!(new_b.len(), 10);
+
+        // bytes are only counted from actual encodings, and the objects are part of them
+        let objects_len = Cbor.encoded_len(&new_b);
+        assert_eq!(objects_len, serde_cbor::to_vec(&new_b).unwrap().len());
+        assert!(stats_a.object_bytes_sent >= objects_len);
+        assert!(stats_a.bytes_sent > stats_a.object_bytes_sent);
+        assert!(stats_b.bytes_sent > stats_b.object_bytes_sent);
+    }
+
+    /// Never makes the ranges smaller, so a run with differing items never ends.
+    fn no_split(n: usize) -> Vec<usize> {
+        vec![n]
+    }
+
+    #[test]
+    fn stops_runs_that_never_end() {
+        let (tree_a, objects_a) = party(0..20);
+        let (tree_b, objects_b) = party(10..40);
+        let run = |params| {
+            run_protocol(
+                tree_a.node(),
+                &objects_a,
+                tree_b.node(),
+                &objects_b,
+                params,
+                &Cbor,
+                None,
+            )
+        };
+
+        let err = run(ProtocolParams::new(3, no_split)).unwrap_err();
+        assert_eq!(err.kind, SyncErrorKind::Cycle);
+        assert!(err.initiator_stats.msgs_sent > 0);
+        assert!(err.responder_stats.msgs_sent > 0);
+
+        let err = run(ProtocolParams {
+            max_rounds: Some(5),
+            detect_cycles: false,
+            ..ProtocolParams::new(3, no_split)
+        })
+        .unwrap_err();
+        assert_eq!(err.kind, SyncErrorKind::RoundLimit);
+        assert_eq!(err.round, 4);
+        assert_eq!(
+            err.initiator_stats.msgs_sent + err.responder_stats.msgs_sent,
+            5
+        );
+
+        // runs that end are left alone
+        run(ProtocolParams {
+            max_rounds: Some(100),
+            ..ProtocolParams::new(3, uniform::split::<2>)
+        })
+        .unwrap();
     }
 
-    // println!("end.");
+    #[test]
+    fn injects_faults() {
+        let (tree_a, objects_a) = party(0..20);
+        let (tree_b, objects_b) = party(10..40);
+        let run = |faults| {
+            run_protocol(
+                tree_a.node(),
+                &objects_a,
+                tree_b.node(),
+                &objects_b,
+                ProtocolParams {
+                    faults: Some(faults),
+                    ..ProtocolParams::new(3, uniform::split::<2>)
+                },
+                &Cbor,
+                None,
+            )
+        };
+        let items = |objects: &[SimObject]| -> Vec<uniform::Item> {
+            let mut items: Vec<_> = objects.iter().map(|obj| obj.to_item()).collect();
+            items.sort();
+            items
+        };
+
+        let (new_a, new_b, stats_a, stats_b) = run(Faults::default()).unwrap();
+        let n_msgs = stats_a.msgs_sent + stats_b.msgs_sent;
+
+        let err = run(Faults {
+            drop: 1.0,
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert_eq!((err.kind, err.round), (SyncErrorKind::Lost, 0));
+        let err = run(Faults {
+            truncate: 1.0,
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert_eq!((err.kind, err.round), (SyncErrorKind::Truncated, 0));
+
+        // the answers to copies are redundant, but the run still ends with the same objects
+        let (dup_a, dup_b, dup_stats_a, dup_stats_b) = run(Faults {
+            duplicate: 1.0,
+            reorder: 0.5,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(items(&dup_a), items(&new_a));
+        assert_eq!(items(&dup_b), items(&new_b));
+        assert!(dup_stats_a.msgs_sent + dup_stats_b.msgs_sent > n_msgs);
 
-    Ok((
-        new_objects_initiator,
-        new_objects_responder,
-        stats_initiator,
-        stats_responder,
-    ))
+        // what arrived before the session was cut is kept in the error, and only grows
+        let mut n_partial = 0;
+        for kill_after in 1..n_msgs - 1 {
+            let err = run(Faults {
+                kill_after: Some(kill_after),
+                ..Default::default()
+            })
+            .unwrap_err();
+            assert_eq!((err.kind, err.round), (SyncErrorKind::Killed, kill_after));
+            assert!(items(&err.initiator_new_objects)
+                .iter()
+                .all(|item| items(&new_a).contains(item)));
+            let n = err.initiator_new_objects.len() + err.responder_new_objects.len();
+            assert!(n >= n_partial);
+            n_partial = n;
+        }
+        assert!(n_partial > 0);
+
+        // every original message starts at most one more exchange, which doesn't fork again
+        let (tree_a, objects_a) = party(0..400);
+        let (tree_b, objects_b) = party(200..600);
+        let run = |faults| {
+            run_protocol(
+                tree_a.node(),
+                &objects_a,
+                tree_b.node(),
+                &objects_b,
+                ProtocolParams {
+                    faults,
+                    ..ProtocolParams::new(3, uniform::split::<2>)
+                },
+                &Cbor,
+                None,
+            )
+            .unwrap()
+        };
+        let (new_a, new_b, stats_a, stats_b) = run(None);
+        let n_msgs = stats_a.msgs_sent + stats_b.msgs_sent;
+        assert!(n_msgs >= 10);
+        let (dup_a, dup_b, dup_stats_a, dup_stats_b) = run(Some(Faults {
+            duplicate: 0.8,
+            ..Default::default()
+        }));
+        assert_eq!(items(&dup_a), items(&new_a));
+        assert_eq!(items(&dup_b), items(&new_b));
+        assert!(dup_stats_a.msgs_sent + dup_stats_b.msgs_sent <= n_msgs * n_msgs);
+    }
+
+    #[test]
+    fn parse_faults() {
+        assert_eq!(
+            "drop=0.1,kill-after=20,seed=3".parse(),
+            Ok(Faults {
+                drop: 0.1,
+                kill_after: Some(20),
+                seed: 3,
+                ..Default::default()
+            })
+        );
+        for invalid in ["drop", "drop=x", "drop=1.5", "delay=0.1"] {
+            assert!(invalid.parse::<Faults>().is_err(), "{invalid}");
+        }
+    }
 }
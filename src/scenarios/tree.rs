@@ -1,9 +1,41 @@
-use unionize::{Monoid, Node};
+use unionize::{Monoid, Node, NonNilNodeRef};
 
 pub trait Tree<M: Monoid, N: Node<M>>: Clone + std::fmt::Debug {
     fn nil() -> Self;
     fn insert(&mut self, item: M::Item);
     fn node(&self) -> &N;
+
+    /// Removes the item, if the tree holds it. The nodes can't remove items, so this builds the
+    /// tree again from the other items, which takes time linear in the size of the tree. To
+    /// remove many items, build the tree once from the items that stay instead.
+    fn remove(&mut self, item: &M::Item) {
+        let items = items(self.node());
+        if !items.contains(item) {
+            return;
+        }
+
+        *self = Self::nil();
+        for other in items.into_iter().filter(|other| other != item) {
+            self.insert(other);
+        }
+    }
+}
+
+/// The items of the tree, in order.
+pub fn items<M: Monoid, N: Node<M>>(node: &N) -> Vec<M::Item> {
+    fn collect<M: Monoid, N: Node<M>>(node: &N, out: &mut Vec<M::Item>) {
+        if let Some(contents) = node.node_contents() {
+            for (child, item) in contents.children() {
+                collect(child, out);
+                out.push(item.clone());
+            }
+            collect(contents.last_child(), out);
+        }
+    }
+
+    let mut out = vec![];
+    collect(node, &mut out);
+    out
 }
 
 pub mod mem_rc {
@@ -31,6 +63,7 @@ pub mod mem_rc_bounds {
     use unionize::{tree::mem_rc_bounds::Node, Monoid};
 
     #[derive(Clone, Debug)]
+    #[allow(dead_code, reason = "no suite uses the bounds tree yet")]
     struct Tree<M: Monoid>(Node<M>);
 
     impl<M: Monoid> super::Tree<M, Node<M>> for Tree<M> {
@@ -47,3 +80,36 @@ pub mod mem_rc_bounds {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{items, mem_rc, Tree};
+    use crate::suites::uniform;
+
+    #[test]
+    fn remove() {
+        let item = |i: u8| {
+            let mut buf = [0u8; 30];
+            buf[0] = i;
+            unionize::item::le_byte_array::LEByteArray(buf)
+        };
+
+        let mut tree = mem_rc::Tree::<uniform::Monoid>::nil();
+        let mut expected = mem_rc::Tree::<uniform::Monoid>::nil();
+        for i in 0..20 {
+            tree.insert(item(i));
+            if i != 7 {
+                expected.insert(item(i));
+            }
+        }
+
+        tree.remove(&item(7));
+        assert_eq!(items(tree.node()), items(expected.node()));
+        assert_eq!(items(tree.node()).len(), 19);
+        assert_eq!(tree.node().monoid(), expected.node().monoid());
+
+        // removing an item that isn't there changes nothing
+        tree.remove(&item(7));
+        assert_eq!(tree.node().monoid(), expected.node().monoid());
+    }
+}
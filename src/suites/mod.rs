@@ -3,17 +3,21 @@ pub mod uniform {
 
     use serde::{Deserialize, Serialize};
     pub use unionize::easy::uniform::*;
-    use unionize::protocol::{Encodable, ProtocolMonoid, RespondError};
+    use unionize::protocol::{Encodable, ProtocolMonoid};
     use unionize::{Monoid as MonoidTrait, Node as NodeTrait, Object as ObjectTrait};
 
-    use crate::scenarios::protocol::{run_protocol as run_uniform_protocol, RunStats};
+    use crate::scenarios::protocol::{
+        run_protocol as run_uniform_protocol, Cbor, ProtocolParams, ProtocolResult, Transcript,
+    };
 
-    pub fn run_protocol<M, N, O, const SPLIT: usize, const THRESH: usize>(
+    pub fn run_protocol<M, N, O>(
+        params: ProtocolParams,
         initiator_node: &N,
         initiator_objects: &BTreeMap<M::Item, O>,
         responder_node: &N,
         responder_objects: &BTreeMap<M::Item, O>,
-    ) -> Result<(Vec<O>, Vec<O>, RunStats, RunStats), RespondError<M>>
+        transcript: Option<&mut Transcript>,
+    ) -> ProtocolResult<O>
     where
         M: MonoidTrait + Encodable + ProtocolMonoid,
         N: NodeTrait<M>,
@@ -23,16 +27,31 @@ pub mod uniform {
         for<'de2> M::Item: Deserialize<'de2>,
         for<'de2> M::Encoded: Deserialize<'de2>,
     {
-        let res = run_uniform_protocol(
+        run_uniform_protocol(
             initiator_node,
             initiator_objects,
             responder_node,
             responder_objects,
-            THRESH,
-            split::<SPLIT>,
-        );
+            params,
+            &Cbor,
+            transcript,
+        )
+    }
 
-        res
+    /// unionize wants the split function as a plain function pointer, so we can only offer the
+    /// split factors we instantiated here.
+    pub fn split_fn(split: usize) -> Option<fn(usize) -> Vec<usize>> {
+        let f: fn(usize) -> Vec<usize> = match split {
+            2 => self::split::<2>,
+            3 => self::split::<3>,
+            4 => self::split::<4>,
+            5 => self::split::<5>,
+            6 => self::split::<6>,
+            7 => self::split::<7>,
+            8 => self::split::<8>,
+            _ => return None,
+        };
+        Some(f)
     }
 }
 pub mod timestamped;
@@ -3,7 +3,7 @@ use std::collections::BTreeMap;
 use serde::{Deserialize, Serialize};
 use unionize::easy::timestamped::{split, split_dynamic};
 use unionize::monoid::timestamped::Timestamped;
-use unionize::protocol::{Encodable, ProtocolMonoid, RespondError};
+use unionize::protocol::{Encodable, ProtocolMonoid};
 use unionize::{Monoid as MonoidTrait, Node as NodeTrait, Object as ObjectTrait};
 
 use crate::scenarios::protocol::run_protocol as run_base_protocol;
@@ -14,18 +14,20 @@ use unionize::{
 };
 
 use crate::scenarios::dynamic::SimInstant;
-use crate::scenarios::protocol::RunStats;
+use crate::scenarios::protocol::{Cbor, ProtocolParams, ProtocolResult, Transcript};
 
 pub type Item = TimestampedItem<SimInstant, super::uniform::Item>;
 pub type Monoid = Timestamped<SimInstant, CountingMonoid<Xsk233MulHashMonoid>>;
 pub type Node = unionize::tree::mem_rc::Node<Monoid>;
 
-pub fn run_protocol<M, N, O, const SPLIT: usize, const THRESH: usize>(
+pub fn run_protocol<M, N, O>(
+    params: ProtocolParams,
     initiator_node: &N,
     initiator_objects: &BTreeMap<M::Item, O>,
     responder_node: &N,
     responder_objects: &BTreeMap<M::Item, O>,
-) -> Result<(Vec<O>, Vec<O>, RunStats, RunStats), RespondError<M>>
+    transcript: Option<&mut Transcript>,
+) -> ProtocolResult<O>
 where
     M: MonoidTrait + Encodable + ProtocolMonoid,
     N: NodeTrait<M>,
@@ -35,40 +37,47 @@ where
     for<'de2> M::Item: Deserialize<'de2>,
     for<'de2> M::Encoded: Deserialize<'de2>,
 {
-    let res = run_base_protocol(
+    run_base_protocol(
         initiator_node,
         initiator_objects,
         responder_node,
         responder_objects,
-        THRESH,
-        split::<SPLIT>,
-    );
+        params,
+        &Cbor,
+        transcript,
+    )
+}
 
-    res
+/// Same as [`super::uniform::split_fn`], but splitting in halves: the first range gets half of
+/// the items, the next one half of the rest, and so on.
+pub fn split_fn(split: usize) -> Option<fn(usize) -> Vec<usize>> {
+    let f: fn(usize) -> Vec<usize> = match split {
+        2 => self::split::<2>,
+        3 => self::split::<3>,
+        4 => self::split::<4>,
+        5 => self::split::<5>,
+        6 => self::split::<6>,
+        7 => self::split::<7>,
+        8 => self::split::<8>,
+        _ => return None,
+    };
+    Some(f)
 }
-pub fn run_protocol_dynamic_split<M, N, O, const THRESH: usize>(
-    initiator_node: &N,
-    initiator_objects: &BTreeMap<M::Item, O>,
-    responder_node: &N,
-    responder_objects: &BTreeMap<M::Item, O>,
-) -> Result<(Vec<O>, Vec<O>, RunStats, RunStats), RespondError<M>>
-where
-    M: MonoidTrait + Encodable + ProtocolMonoid,
-    N: NodeTrait<M>,
-    O: ObjectTrait<M::Item> + for<'de2> Deserialize<'de2> + Serialize,
-    M::Item: Serialize,
-    M::Encoded: Serialize,
-    for<'de2> M::Item: Deserialize<'de2>,
-    for<'de2> M::Encoded: Deserialize<'de2>,
-{
-    let res = run_base_protocol(
-        initiator_node,
-        initiator_objects,
-        responder_node,
-        responder_objects,
-        THRESH,
-        split_dynamic::<THRESH>,
-    );
 
-    res
+/// Returns a split function that keeps halving until the rest is below the threshold.
+pub fn split_dynamic_fn(threshold: usize) -> Option<fn(usize) -> Vec<usize>> {
+    let f: fn(usize) -> Vec<usize> = match threshold {
+        2 => split_dynamic::<2>,
+        3 => split_dynamic::<3>,
+        4 => split_dynamic::<4>,
+        5 => split_dynamic::<5>,
+        6 => split_dynamic::<6>,
+        7 => split_dynamic::<7>,
+        8 => split_dynamic::<8>,
+        10 => split_dynamic::<10>,
+        12 => split_dynamic::<12>,
+        16 => split_dynamic::<16>,
+        _ => return None,
+    };
+    Some(f)
 }
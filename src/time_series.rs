@@ -1 +1,439 @@
+//! Turns a trace into regularly spaced series, e.g. the bytes sent per hour or the 95th percentile
+//! of the messages per sync per day. Every window of the run gets a point, also the ones in which
+//! nothing happened, so series of different runs line up.
 
+use std::collections::BTreeMap;
+use std::io;
+
+use serde::Serialize;
+use unionize::{Item, Object};
+
+use crate::scenarios::dynamic::{SimDuration, SimInstant, Trace, TraceEntry, TraceMeta};
+
+/// What a series measures. Trace entries contribute values at their time, e.g. one per sync.
+/// Only successful syncs are counted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Measure {
+    /// The bytes each party sent in a sync.
+    Bytes,
+    /// One per sync, for the initiator.
+    Syncs,
+    /// The messages of both parties of a sync, for the initiator.
+    Messages,
+    /// The number of items each party knew when it synced.
+    ItemsKnown,
+    /// The values of an observer's metric with this name.
+    Metric(String),
+}
+
+impl Measure {
+    /// The values the entry contributes, with the party they belong to.
+    fn values<I: Item, O: Object<I>>(
+        &self,
+        meta: &TraceMeta,
+        entry: &TraceEntry<I, O>,
+    ) -> Vec<(usize, f64)> {
+        let party_id = meta.party_id();
+        match (self, entry) {
+            (Measure::Bytes, TraceEntry::Sync(responder_party_id, init, resp, _)) => vec![
+                (party_id, init.bytes_sent as f64),
+                (*responder_party_id, resp.bytes_sent as f64),
+            ],
+            (Measure::Syncs, TraceEntry::Sync(..)) => vec![(party_id, 1.0)],
+            (Measure::Messages, TraceEntry::Sync(_, init, resp, _)) => {
+                vec![(party_id, (init.msgs_sent + resp.msgs_sent) as f64)]
+            }
+            (Measure::ItemsKnown, TraceEntry::Sync(responder_party_id, init, resp, _)) => vec![
+                (party_id, init.items_known as f64),
+                (*responder_party_id, resp.items_known as f64),
+            ],
+            (Measure::Metric(name), TraceEntry::Metric(metric)) if metric.name == *name => {
+                vec![(party_id, metric.value)]
+            }
+            _ => vec![],
+        }
+    }
+}
+
+impl std::fmt::Display for Measure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Measure::Bytes => write!(f, "bytes"),
+            Measure::Syncs => write!(f, "syncs"),
+            Measure::Messages => write!(f, "messages"),
+            Measure::ItemsKnown => write!(f, "items_known"),
+            Measure::Metric(name) => write!(f, "metric.{name}"),
+        }
+    }
+}
+
+impl std::str::FromStr for Measure {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bytes" => Ok(Measure::Bytes),
+            "syncs" => Ok(Measure::Syncs),
+            "messages" => Ok(Measure::Messages),
+            "items_known" => Ok(Measure::ItemsKnown),
+            _ => match s.strip_prefix("metric.") {
+                Some(name) if !name.is_empty() => Ok(Measure::Metric(name.to_string())),
+                _ => Err(format!(
+                    "unknown measure {s:?}, use one of bytes, syncs, messages, items_known, \
+                     metric.<name>"
+                )),
+            },
+        }
+    }
+}
+
+/// How the values within a window become the value of the point. Percentiles use the nearest
+/// rank.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Aggregation {
+    Count,
+    Sum,
+    Mean,
+    P50,
+    P95,
+    P99,
+    Max,
+}
+
+impl Aggregation {
+    pub const ALL: [Aggregation; 7] = [
+        Aggregation::Count,
+        Aggregation::Sum,
+        Aggregation::Mean,
+        Aggregation::P50,
+        Aggregation::P95,
+        Aggregation::P99,
+        Aggregation::Max,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aggregation::Count => "count",
+            Aggregation::Sum => "sum",
+            Aggregation::Mean => "mean",
+            Aggregation::P50 => "p50",
+            Aggregation::P95 => "p95",
+            Aggregation::P99 => "p99",
+            Aggregation::Max => "max",
+        }
+    }
+
+    /// Returns `None` for the aggregations that are undefined without values. Counts and sums of
+    /// no values are zero.
+    pub fn apply(&self, values: &mut [f64]) -> Option<f64> {
+        let percentile = |values: &mut [f64], p: usize| {
+            values.sort_by(f64::total_cmp);
+            let rank = (p * values.len()).div_ceil(100).max(1);
+            values[rank - 1]
+        };
+
+        match self {
+            Aggregation::Count => Some(values.len() as f64),
+            Aggregation::Sum => Some(values.iter().sum()),
+            _ if values.is_empty() => None,
+            Aggregation::Mean => Some(values.iter().sum::<f64>() / values.len() as f64),
+            Aggregation::P50 => Some(percentile(values, 50)),
+            Aggregation::P95 => Some(percentile(values, 95)),
+            Aggregation::P99 => Some(percentile(values, 99)),
+            Aggregation::Max => values.iter().copied().reduce(f64::max),
+        }
+    }
+}
+
+impl std::str::FromStr for Aggregation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Aggregation::ALL
+            .into_iter()
+            .find(|aggregation| aggregation.name() == s)
+            .ok_or_else(|| {
+                format!(
+                    "unknown aggregation {s:?}, use one of count, sum, mean, p50, p95, p99, max"
+                )
+            })
+    }
+}
+
+/// Which series to compute.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SeriesSpec {
+    pub measure: Measure,
+    pub window: SimDuration,
+    pub aggregation: Aggregation,
+    /// One series per party instead of one for all of them.
+    pub per_party: bool,
+}
+
+/// Written as `<measure>:<window>:<aggregation>[:party]`, e.g. `bytes:1h:sum` or
+/// `items_known:1d:max:party`.
+impl std::fmt::Display for SeriesSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}",
+            self.measure,
+            self.window,
+            self.aggregation.name()
+        )?;
+        if self.per_party {
+            write!(f, ":party")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for SeriesSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (measure, window, aggregation, per_party) = match s.split(':').collect::<Vec<_>>()[..] {
+            [measure, window, aggregation] => (measure, window, aggregation, false),
+            [measure, window, aggregation, "party"] => (measure, window, aggregation, true),
+            _ => {
+                return Err(format!(
+                    "invalid series {s:?}, expected <measure>:<window>:<aggregation>[:party]"
+                ))
+            }
+        };
+
+        let window: SimDuration = window.parse()?;
+        if window == SimDuration::zero() {
+            return Err("the window of a series must not be zero".to_string());
+        }
+
+        Ok(SeriesSpec {
+            measure: measure.parse()?,
+            window,
+            aggregation: aggregation.parse()?,
+            per_party,
+        })
+    }
+}
+
+/// The aggregated values of one window.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Point {
+    pub start: SimInstant,
+    /// `None` unless the series is per party.
+    pub party_id: Option<usize>,
+    /// The number of values in the window.
+    pub count: usize,
+    pub value: Option<f64>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TimeSeries {
+    spec: SeriesSpec,
+    points: Vec<Point>,
+}
+
+impl TimeSeries {
+    /// Computes the series over the windows that start before `end`. Per-party series get a
+    /// point for every party up to the highest party id in the entries.
+    pub fn new<'a, I, O, E>(spec: SeriesSpec, entries: E, end: SimInstant) -> Self
+    where
+        I: Item + 'a,
+        O: Object<I> + 'a,
+        E: IntoIterator<Item = &'a (TraceMeta, TraceEntry<I, O>)>,
+    {
+        let window = spec.window.0.max(1);
+        let n_windows = end.0.div_ceil(window) as usize;
+
+        let mut n_parties = 0;
+        let mut values: BTreeMap<(usize, Option<usize>), Vec<f64>> = BTreeMap::new();
+        for (meta, entry) in entries {
+            n_parties = n_parties.max(meta.party_id() + 1);
+            for (party_id, value) in spec.measure.values(meta, entry) {
+                n_parties = n_parties.max(party_id + 1);
+                let i = (meta.time().0 / window) as usize;
+                if i < n_windows {
+                    let party_id = spec.per_party.then_some(party_id);
+                    values.entry((i, party_id)).or_default().push(value);
+                }
+            }
+        }
+
+        let parties: Vec<Option<usize>> = if spec.per_party {
+            (0..n_parties).map(Some).collect()
+        } else {
+            vec![None]
+        };
+
+        let mut points = Vec::with_capacity(n_windows * parties.len());
+        for i in 0..n_windows {
+            for party_id in &parties {
+                let mut values = values.remove(&(i, *party_id)).unwrap_or_default();
+                points.push(Point {
+                    start: SimInstant(i as u64 * window),
+                    party_id: *party_id,
+                    count: values.len(),
+                    value: spec.aggregation.apply(&mut values),
+                });
+            }
+        }
+
+        TimeSeries { spec, points }
+    }
+
+    /// Computes the series up to the window of the last entry.
+    pub fn from_trace<I: Item, O: Object<I>>(spec: SeriesSpec, trace: &Trace<I, O>) -> Self {
+        let end = trace
+            .entries()
+            .last()
+            .map_or(SimInstant::zero(), |(meta, _)| meta.time() + SimDuration(1));
+        Self::new(spec, trace.entries(), end)
+    }
+
+    pub fn spec(&self) -> &SeriesSpec {
+        &self.spec
+    }
+
+    /// Ordered by window, then party.
+    pub fn points(&self) -> &[Point] {
+        &self.points
+    }
+
+    /// One record per point, for writing to CSV.
+    pub fn records(&self) -> Vec<TimeSeriesRecord> {
+        let series = self.spec.to_string();
+        self.points
+            .iter()
+            .map(|point| TimeSeriesRecord {
+                series: series.clone(),
+                start: point.start,
+                end: point.start + self.spec.window,
+                party_id: point.party_id,
+                count: point.count,
+                value: point.value,
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TimeSeriesRecord {
+    /// The spec of the series, see [`SeriesSpec`]'s `Display`.
+    pub series: String,
+    pub start: SimInstant,
+    pub end: SimInstant,
+    pub party_id: Option<usize>,
+    pub count: usize,
+    pub value: Option<f64>,
+}
+
+/// Writes the points of all series, one row each, in long format.
+pub fn write_csv<W: io::Write>(series: &[TimeSeries], wtr: &mut csv::Writer<W>) -> io::Result<()> {
+    for series in series {
+        for record in series.records() {
+            wtr.serialize(record)?;
+        }
+    }
+    wtr.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    use super::{write_csv, Aggregation, Measure, SeriesSpec, TimeSeries};
+    use crate::experiments::uniform::UniformSim;
+    use crate::scenarios::dynamic::tests::{hourly, protocol};
+    use crate::scenarios::dynamic::{Event, SimDuration, SimInstant, Simulator, TraceEntry};
+
+    #[test]
+    fn aggregations() {
+        let values = || (1..=100).rev().map(f64::from).collect::<Vec<_>>();
+        let apply = |aggregation: Aggregation| aggregation.apply(&mut values());
+
+        assert_eq!(apply(Aggregation::Count), Some(100.0));
+        assert_eq!(apply(Aggregation::Sum), Some(5050.0));
+        assert_eq!(apply(Aggregation::Mean), Some(50.5));
+        assert_eq!(apply(Aggregation::P50), Some(50.0));
+        assert_eq!(apply(Aggregation::P95), Some(95.0));
+        assert_eq!(apply(Aggregation::P99), Some(99.0));
+        assert_eq!(apply(Aggregation::Max), Some(100.0));
+
+        assert_eq!(Aggregation::Sum.apply(&mut []), Some(0.0));
+        assert_eq!(Aggregation::P95.apply(&mut []), None);
+    }
+
+    #[test]
+    fn parse_specs() {
+        for s in [
+            "bytes:1h:sum",
+            "messages:1d:p95",
+            "items_known:6h:max:party",
+        ] {
+            assert_eq!(s.parse::<SeriesSpec>().unwrap().to_string(), s);
+        }
+        assert_eq!(
+            "metric.held:1d:mean".parse::<SeriesSpec>().unwrap().measure,
+            Measure::Metric("held".to_string())
+        );
+        assert!("bytes:1h".parse::<SeriesSpec>().is_err());
+        assert!("bytes:0h:sum".parse::<SeriesSpec>().is_err());
+        assert!("bytes:1h:median".parse::<SeriesSpec>().is_err());
+        assert!("bytez:1h:sum".parse::<SeriesSpec>().is_err());
+    }
+
+    #[test]
+    fn series() {
+        let triggers = hourly(vec![
+            (0, Event::Post),
+            (1, Event::Post),
+            (2, Event::Sync(0)),
+            (0, Event::Sync(1)),
+        ]);
+        let trace = UniformSim::sim(
+            &mut ChaCha8Rng::from_seed([0u8; 32]),
+            3,
+            triggers,
+            3 * SimDuration::DAY,
+            protocol(),
+        );
+
+        let mut syncs = 0;
+        let mut bytes = 0;
+        for (_, entry) in trace.entries() {
+            if let TraceEntry::Sync(_, init, resp, _) = entry {
+                syncs += 1;
+                bytes += init.bytes_sent + resp.bytes_sent;
+            }
+        }
+
+        let series = |s: &str| TimeSeries::from_trace(s.parse().unwrap(), &trace);
+        let total =
+            |series: &TimeSeries| -> f64 { series.points().iter().map(|p| p.value.unwrap()).sum() };
+
+        let hourly = series("syncs:1h:count");
+        assert_eq!(hourly.points().len(), 72);
+        assert_eq!(hourly.points()[1].start, SimInstant(60));
+        assert_eq!(total(&hourly), syncs as f64);
+        assert_eq!(total(&series("bytes:1d:sum")), bytes as f64);
+
+        // the bytes per party add up to the same
+        let per_party = series("bytes:1d:sum:party");
+        assert_eq!(per_party.points().len(), 3 * 3);
+        assert_eq!(per_party.points()[4].party_id, Some(1));
+        assert_eq!(total(&per_party), bytes as f64);
+
+        let p95 = series("messages:1d:p95");
+        let max = series("messages:1d:max");
+        for (p95, max) in p95.points().iter().zip(max.points()) {
+            assert!(p95.value <= max.value);
+        }
+
+        let mut wtr = csv::Writer::from_writer(vec![]);
+        write_csv(&[hourly, per_party], &mut wtr).unwrap();
+        let data = String::from_utf8(wtr.into_inner().unwrap()).unwrap();
+        assert_eq!(data.lines().count(), 1 + 72 + 9);
+        assert!(data.contains("bytes:1d:sum:party,1440,2880,1,"));
+    }
+}